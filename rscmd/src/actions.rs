@@ -1,99 +1,502 @@
 use crate::{candy, cli};
 use clap::ArgMatches;
+use indicatif::ProgressBar;
 use rustone::{
-    cacher::{self, CachedJarMetaKey},
-    config,
-    config::ServerVersion,
+    archive,
+    cacher::{self, JarKey, JarRecord},
+    config::{MinecraftSelector, MinecraftVersion, ServerVersion, UpdatePolicy, VersionSelector},
+    download::Progress,
     errors::*,
-    server_kinds::{paper, ServerKind},
+    import::{self, ImportMode},
+    java, modpacks,
+    paths::Paths,
+    plugins::Sources,
+    properties,
+    provision::{Console, ProgressSink, ProvisionOptions, Update},
+    server_kinds::{Build, ServerKind},
     servers::{get_servers, Server},
+    templates,
+    updates::{self, Startup},
+    upgrade,
 };
-use std::{fs::File, io::BufRead, process::Stdio};
+use std::{fs::File, io::BufRead, path::PathBuf, sync::Mutex};
 
+mod backup;
 mod cache;
+mod config;
+mod mods;
+mod plugin;
 
-pub async fn download(args: &ArgMatches<'_>) -> Result<()> {
-    let mut version = ServerVersion::new(args.value_of("VERSION").unwrap())?;
+pub async fn download(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    let kind = args.value_of("type").unwrap().parse::<ServerKind>()?;
+    let selector: VersionSelector = args.value_of("VERSION").unwrap().parse()?;
+    let mut version = match selector.minecraft {
+        MinecraftSelector::Exact(minecraft) => ServerVersion {
+            minecraft,
+            patch: selector.build,
+        },
+        _ => updates::resolve(paths, &kind, &selector).await?,
+    };
 
-    let output = format!("paper-{}.jar", version.minecraft);
+    let output = format!("{}-{}.jar", kind, version.minecraft);
     let output = args.value_of("output").unwrap_or(&output);
 
-    println!("Downloading version {} into {}", version, output);
+    println!("Downloading {} version {} into {}", kind, version, output);
 
-    let mut response = paper::download_response(&mut version).await?;
+    let response = kind.download_response(paths, &mut version).await?;
     let mut file = File::create(output).chain_err(|| "failed to create jar file")?;
-    candy::download(&mut response, &mut file).await?;
+    candy::download(paths, response, &mut file).await?;
 
     Ok(())
 }
 
-pub fn create(args: &ArgMatches<'_>) -> Result<()> {
+pub async fn create(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
     let name = args.value_of("NAME").unwrap();
-    let version = args.value_of("VERSION").unwrap();
-    let kind = args.value_of("type").unwrap();
-    Server::create(
-        &name,
-        config::ServerVersion::new(&version)?,
-        kind.to_owned(),
-    )?;
+    if let Some(path) = args.value_of("from-archive") {
+        let (server, manifest) = archive::restore(
+            paths,
+            name,
+            std::path::Path::new(path),
+            args.is_present("force"),
+        )?;
+        println!("Restored {}, downloading server jar...", server.config.name);
+        let kind = server.config.kind.parse::<ServerKind>()?;
+        let mut server = server;
+        let (_, record) = cache_server_jar(paths, &kind, &mut server.config.version).await?;
+        server.save()?;
+        match manifest.jar_sha256 {
+            Some(expected) if expected != record.sha256 => println!(
+                "{} the downloaded server jar differs from the one {} was exported with",
+                cli::WARNING_HEADER_STYLE.paint("warning:"),
+                manifest.name
+            ),
+            _ => {}
+        }
+        return Ok(());
+    }
+    if let Some(pack) = args.value_of("from-modpack") {
+        println!("Importing modpack...");
+        let (server, report) = modpacks::import(paths, name, std::path::Path::new(pack)).await?;
+        println!(
+            "Created {} server {} with {} files and {} overrides",
+            server.config.kind,
+            cli::SECONDARY.paint(server.config.version.to_string()),
+            report.downloaded,
+            report.overrides
+        );
+        for file in report.client_only {
+            println!("Skipped {} (client-only)", file);
+        }
+        return Ok(());
+    }
+    if let Some(template) = args.value_of("template") {
+        let version = args
+            .value_of("VERSION")
+            .map(ServerVersion::new)
+            .transpose()?;
+        let server = templates::create(paths, name, template, version)?;
+        println!(
+            "Created {} server {} from template {}",
+            server.config.kind,
+            cli::SECONDARY.paint(server.config.version.to_string()),
+            template
+        );
+        return Ok(());
+    }
+    let selector: VersionSelector = args.value_of("VERSION").unwrap().parse()?;
+    let kind_name = args.value_of("type").unwrap();
+    let kind = kind_name.parse::<ServerKind>()?;
+    // Exact versions get their build when the server first starts
+    let version = match selector.minecraft {
+        MinecraftSelector::Exact(minecraft) => ServerVersion {
+            minecraft,
+            patch: selector.build,
+        },
+        _ => {
+            println!("Resolving {}...", selector);
+            updates::resolve(paths, &kind, &selector).await?
+        }
+    };
+    let policy = match args.value_of("update-policy") {
+        Some(policy) => policy.parse()?,
+        None if selector.exact().is_some() => UpdatePolicy::Pinned,
+        None => UpdatePolicy::default(),
+    };
+    let mut server = Server::create(paths, name, version, kind_name.to_owned())?;
+    if !matches!(selector.minecraft, MinecraftSelector::Exact(_)) {
+        server.config.selector = Some(selector);
+    }
+    server.config.update_policy = policy;
+    server.save()?;
+    println!(
+        "Created {} server {}",
+        server.config.kind,
+        cli::SECONDARY.paint(server.config.version.to_string())
+    );
     Ok(())
 }
 
-pub async fn start(args: &ArgMatches<'_>) -> Result<()> {
-    let name = args.value_of("NAME").unwrap();
-    let server = Server::get(&name)?;
-    let key = CachedJarMetaKey {
-        kind: server.config.kind.clone(),
-        version: server.config.version.minecraft,
+/// Download a server jar into the cache, unless it is there already: the
+/// given build of `version`, or the latest one if it has none, which
+/// `version` is given.
+async fn cache_server_jar(
+    paths: &Paths,
+    kind: &ServerKind,
+    version: &mut ServerVersion,
+) -> Result<(PathBuf, JarRecord)> {
+    if version.patch.is_none() {
+        version.patch = Some(kind.get_latest_patch(paths, &version.minecraft).await?);
+    }
+    let key = JarKey {
+        kind: kind.to_string(),
+        version: version.minecraft,
+        build: version.patch.unwrap(),
     };
-    let cached_patch = cacher::get_cached_patch(&key)?;
-    let kind = server.config.kind.parse::<ServerKind>()?;
-    if let Some(cached_patch) = cached_patch {
-        println!("Checking for updates...");
-        let latest_patch = kind
-            .get_latest_patch(&server.config.version.minecraft)
-            .await?;
-        if latest_patch > cached_patch {
-            println!("Found a new build! Downloading...");
-            let mut response = kind
-                .download_response(&mut server.config.version.clone())
-                .await?;
-            let mut file =
-                File::create(key.path()).chain_err(|| "failed to create file for downloading")?;
-            candy::download(&mut response, &mut file).await?;
-        } else {
-            println!("Server up to date");
+    if let Some(cached) = cacher::get_cached(paths, &key)? {
+        return Ok(cached);
+    }
+    let (resp, mut writer) = cacher::download(paths, kind, version).await?;
+    candy::download(paths, resp, &mut writer).await?;
+    writer.finish()?;
+    cacher::get_cached(paths, &key)?
+        .ok_or_else(|| ErrorKind::NotFound(format!("cached jar {}", key)).into())
+}
+
+pub fn clone(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    let source = Server::get(paths, args.value_of("SRC").unwrap())?;
+    let port = match args.value_of("port") {
+        Some(port) => Some(port.parse().chain_err(|| "invalid port")?),
+        None => None,
+    };
+    println!("Copying {}...", source.config.name);
+    let server = source.clone_as(
+        args.value_of("DST").unwrap(),
+        !args.is_present("no-worlds"),
+        port,
+    )?;
+    println!(
+        "Created {} on port {}",
+        server.config.name,
+        cli::SECONDARY.paint(properties::server_port(&server.config)?.to_string())
+    );
+    Ok(())
+}
+
+pub fn template(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    match args.subcommand() {
+        ("list", _) => {
+            let names = templates::list(paths)?;
+            if names.is_empty() {
+                println!(
+                    "No templates, add them to {}",
+                    paths.templates().to_string_lossy()
+                );
+            }
+            for name in names {
+                println!("{}", name);
+            }
+            Ok(())
         }
+        _ => unreachable!(),
+    }
+}
+
+pub fn export(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    let server = Server::get(paths, args.value_of("NAME").unwrap())?;
+    let dest = std::path::Path::new(args.value_of("FILE").unwrap());
+    println!("Exporting {}...", server.config.name);
+    archive::export(&server, dest)?;
+    println!(
+        "Exported {} to {}",
+        server.config.name,
+        cli::SECONDARY.paint(dest.to_string_lossy())
+    );
+    Ok(())
+}
+
+pub fn import(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    let mode = if args.is_present("move") {
+        ImportMode::Move
+    } else if args.is_present("flat") {
+        ImportMode::Flat
     } else {
-        println!("Downloading server jar...");
-        let latest_patch = kind
-            .get_latest_patch(&server.config.version.minecraft)
-            .await?;
-        let (mut file, _) = cacher::cache_jar(
-            server.config.version.minecraft,
-            latest_patch,
-            server.config.kind.clone(),
-        )?;
-        let mut version = ServerVersion {
-            patch: Some(latest_patch),
-            ..server.config.version
-        };
-        let mut resp = kind.download_response(&mut version).await?;
-        candy::download(&mut resp, &mut file).await?;
-    }
-    let mut child = kind.launch(
-        server.config,
-        Stdio::inherit(),
-        Stdio::inherit(),
-        Stdio::inherit(),
+        ImportMode::Copy
+    };
+    let kind = args.value_of("type").map(str::parse).transpose()?;
+    let version = args
+        .value_of("version")
+        .map(ServerVersion::new)
+        .transpose()?;
+    let server = import::import(
+        paths,
+        args.value_of("NAME").unwrap(),
+        std::path::Path::new(args.value_of("PATH").unwrap()),
+        mode,
+        kind,
+        version,
     )?;
+    println!(
+        "Imported {} server {} as {}",
+        server.config.kind,
+        cli::SECONDARY.paint(server.config.version.to_string()),
+        server.config.name
+    );
+    Ok(())
+}
+
+pub async fn upgrade(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    let mut server = Server::get(paths, args.value_of("NAME").unwrap())?;
+    let mut target = ServerVersion::new(args.value_of("VERSION").unwrap())?;
+    let kind = server.config.kind.parse::<ServerKind>()?;
+    upgrade::check_downgrade(&server.config, &target.minecraft, args.is_present("force"))?;
+
+    println!("Checking plugins and mods...");
+    let mut incompatible =
+        upgrade::check_plugins(&server.config, &target.minecraft, &Sources::new(paths)).await?;
+    incompatible.extend(upgrade::check_mods(&server.config, &target.minecraft).await?);
+    for item in &incompatible {
+        println!("{}: {}", item.name, item.reason);
+    }
+    if !incompatible.is_empty() && !args.is_present("force") {
+        return Err(format!(
+            "{} plugins or mods don't support Minecraft {} (use --force to upgrade anyway)",
+            incompatible.len(),
+            target.minecraft
+        )
+        .into());
+    }
+
+    if !args.is_present("no-backup") {
+        println!("Backing up {}...", server.config.name);
+        let (snapshot, _) = backup::open_or_init_repository(paths)?
+            .backup(&server.config.name, &server.server_path()?)?;
+        println!("Snapshot {} saved", cli::SECONDARY.paint(&snapshot.id));
+    }
+
+    println!("Downloading server jar...");
+    cache_server_jar(paths, &kind, &mut target).await?;
+    let from = server.config.version;
+    upgrade::apply(&mut server.config, target);
+    server.save()?;
+    updates::log_update(&server.config, from, &[])?;
+    println!(
+        "Upgraded {} from {} to {}",
+        server.config.name,
+        from,
+        cli::SECONDARY.paint(server.config.version.to_string())
+    );
+    Ok(())
+}
+
+pub async fn start(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    let name = args.value_of("NAME").unwrap();
+    let mut server = Server::get(paths, name)?;
+    let kind = server.config.kind.parse::<ServerKind>()?;
+    let progress = CliProgress::new(kind);
+    server
+        .prepare(&ProvisionOptions::default(), &progress)
+        .await?;
     println!("Launching...");
-    child.wait().await.chain_err(|| "wait failed")?;
+    let mut child = server
+        .start_watched(
+            &ProvisionOptions::default(),
+            Console::Inherit,
+            &progress,
+            |line| println!("{}", line),
+        )
+        .await?;
+    child
+        .wait()
+        .await
+        .chain_err(|| ErrorKind::ProcessFailed("waiting for the server".to_owned()))?;
     Ok(())
 }
 
-pub fn list() -> Result<()> {
-    for server in get_servers()? {
+/// Show what changed since `from` in the builds `server` was updated to,
+/// and log the update.
+pub(crate) async fn log_update(
+    server: &Server,
+    kind: &ServerKind,
+    from: ServerVersion,
+) -> Result<()> {
+    let builds =
+        match updates::changelog(&server.config.paths, kind, &from, &server.config.version).await {
+            Ok(builds) => builds,
+            Err(e) => {
+                println!(
+                    "{} could not get the changelog: {}",
+                    cli::WARNING_HEADER_STYLE.paint("warning:"),
+                    e
+                );
+                vec![]
+            }
+        };
+    print_changelog(kind, &builds);
+    updates::log_update(&server.config, from, &builds)
+}
+
+fn print_changelog(kind: &ServerKind, builds: &[Build]) {
+    for build in builds {
+        println!("  {}", cli::HIGHLIGHT.paint(kind.build_name(build.number)));
+        for change in &build.changes {
+            println!(
+                "    {} {}",
+                cli::SECONDARY.paint(
+                    change
+                        .commit
+                        .as_deref()
+                        .map_or("", |c| &c[..c.len().min(7)])
+                ),
+                first_line(&change.summary)
+            );
+        }
+    }
+}
+
+/// Shows the preparation of a server on the terminal, with a progress bar
+/// for the server jar download.
+struct CliProgress {
+    kind: ServerKind,
+    bar: Mutex<Option<ProgressBar>>,
+}
+
+impl CliProgress {
+    fn new(kind: ServerKind) -> Self {
+        Self {
+            kind,
+            bar: Mutex::new(None),
+        }
+    }
+}
+
+impl ProgressSink for CliProgress {
+    fn status(&self, message: &str) {
+        println!("{}", message);
+    }
+
+    fn warning(&self, message: &str) {
+        println!(
+            "{} {}",
+            cli::WARNING_HEADER_STYLE.paint("warning:"),
+            message
+        );
+    }
+
+    fn updated(&self, update: &Update) {
+        println!(
+            "Updated from {} to {}",
+            update.from,
+            cli::SECONDARY.paint(update.to.to_string())
+        );
+        print_changelog(&self.kind, &update.builds);
+    }
+
+    fn download(&self, progress: Progress) {
+        let mut bar = self.bar.lock().unwrap();
+        let bar = bar.get_or_insert_with(|| cli::create_download_progressbar(progress.total));
+        if let Some(total) = progress.total {
+            bar.set_length(total);
+        }
+        bar.set_position(progress.downloaded);
+    }
+
+    fn downloaded(&self) {
+        if let Some(bar) = self.bar.lock().unwrap().take() {
+            bar.finish_with_message("done");
+        }
+    }
+
+    fn rolled_back(&self, bad: &ServerVersion, startup: Startup, to: &ServerVersion) {
+        println!(
+            "{} {} {}, rolling back to {}",
+            cli::WARNING_HEADER_STYLE.paint("warning:"),
+            bad,
+            startup,
+            cli::SECONDARY.paint(to.to_string())
+        );
+    }
+}
+
+pub fn history(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    let server = Server::get(paths, args.value_of("NAME").unwrap())?;
+    let records = updates::read_log(&server.config)?;
+    if records.is_empty() {
+        println!("{} has not been updated yet", server.config.name);
+    }
+    for record in records {
+        println!(
+            "{} {} -> {}",
+            backup::format_time(record.time),
+            record.from,
+            cli::SECONDARY.paint(record.to.to_string())
+        );
+        for change in record.changes {
+            println!("    {}", first_line(&change.summary));
+        }
+    }
+    Ok(())
+}
+
+pub async fn versions(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    let kind = args.value_of("type").unwrap().parse::<ServerKind>()?;
+    let version = match args.value_of("VERSION") {
+        Some(version) => version.parse::<MinecraftVersion>()?,
+        None => {
+            let mut versions = kind.get_versions(paths).await?;
+            versions.sort_unstable_by(|a, b| b.cmp(a));
+            for version in versions {
+                println!("{}", version);
+            }
+            return Ok(());
+        }
+    };
+    for build in kind.get_builds(paths, &version).await? {
+        // Timestamps are shortened to their date
+        let date = build
+            .time
+            .as_deref()
+            .and_then(|t| t.get(..10))
+            .unwrap_or("");
+        let summary = match build.changes.as_slice() {
+            [] => String::new(),
+            [change] => first_line(&change.summary).to_owned(),
+            [change, rest @ ..] => {
+                format!("{} (+{} more)", first_line(&change.summary), rest.len())
+            }
+        };
+        println!(
+            "{} {:10} {:12} {}",
+            cli::SECONDARY.paint(format!("{:>8}", kind.build_name(build.number))),
+            date,
+            build.channel,
+            summary
+        );
+    }
+    Ok(())
+}
+
+fn first_line(text: &str) -> &str {
+    text.lines().next().unwrap_or("")
+}
+
+pub fn java() -> Result<()> {
+    let runtimes = java::discover();
+    if runtimes.is_empty() {
+        println!("No Java runtimes found");
+    }
+    for runtime in runtimes {
+        println!(
+            "Java {} {} ({})",
+            runtime.major,
+            cli::SECONDARY.paint(&runtime.version),
+            runtime.path.to_string_lossy()
+        );
+    }
+    Ok(())
+}
+
+pub fn list(paths: &Paths) -> Result<()> {
+    for server in get_servers(paths)? {
         println!(
             "{} ({})",
             server.config.name,
@@ -104,8 +507,18 @@ pub fn list() -> Result<()> {
     Ok(())
 }
 
-pub fn remove(args: &ArgMatches) -> Result<()> {
-    let server = Server::get(&args.value_of("NAME").unwrap())?;
+pub fn remove(paths: &Paths, args: &ArgMatches) -> Result<()> {
+    let server = Server::get(paths, args.value_of("NAME").unwrap())?;
+    // Imported servers in their own directory are only forgotten
+    if let Some(directory) = &server.config.directory {
+        server.remove()?;
+        println!(
+            "Removed {}, its files are left in {}",
+            server.config.name,
+            directory.to_string_lossy()
+        );
+        return Ok(());
+    }
     let confirm_str = format!(
         "Yes, erase {} completely and irrecoverably.",
         server.config.name
@@ -117,20 +530,58 @@ pub fn remove(args: &ArgMatches) -> Result<()> {
     );
     let input = std::io::stdin().lock().lines().next().unwrap().unwrap();
     if confirm_str == input {
-        std::fs::remove_dir_all(server.server_path()?)
-            .chain_err(|| "failed to remove server directory")?;
-        std::fs::remove_file(server.config_path()?)
-            .chain_err(|| "failed to remove server config")?;
+        server.remove()?;
     } else {
         println!("Abort.");
     }
     Ok(())
 }
 
-pub async fn cache(args: &ArgMatches<'_>) -> Result<()> {
+pub async fn cache(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    match args.subcommand() {
+        ("upgrade", _) => cache::upgrade(paths).await,
+        ("purge", _) => cache::purge(paths),
+        ("list", _) => cache::list(paths),
+        ("prune", _) => cache::prune(paths),
+        _ => unreachable!(),
+    }
+}
+
+pub fn config(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    match args.subcommand() {
+        ("show", Some(args)) => config::show(paths, args),
+        _ => unreachable!(),
+    }
+}
+
+pub fn backup(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    match args.subcommand() {
+        ("init", Some(args)) => backup::init(paths, args),
+        ("create", Some(args)) => backup::create(paths, args),
+        ("list", Some(args)) => backup::list(paths, args),
+        ("restore", Some(args)) => backup::restore(paths, args),
+        ("prune", Some(args)) => backup::prune(paths, args),
+        ("check", Some(args)) => backup::check(paths, args),
+        _ => unreachable!(),
+    }
+}
+
+pub async fn plugin(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    match args.subcommand() {
+        ("add", Some(args)) => plugin::add(paths, args).await,
+        ("remove", Some(args)) => plugin::remove(paths, args),
+        ("list", Some(args)) => plugin::list(paths, args),
+        ("update", Some(args)) => plugin::update(paths, args).await,
+        _ => unreachable!(),
+    }
+}
+
+pub async fn mods(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
     match args.subcommand() {
-        ("upgrade", _) => cache::upgrade().await,
-        ("purge", _) => cache::purge(),
+        ("add", Some(args)) => mods::add(paths, args).await,
+        ("remove", Some(args)) => mods::remove(paths, args),
+        ("list", Some(args)) => mods::list(paths, args),
+        ("update", Some(args)) => mods::update(paths, args).await,
         _ => unreachable!(),
     }
 }
@@ -0,0 +1,157 @@
+use chrono::{Local, TimeZone};
+use clap::ArgMatches;
+use rustone::{backup::Repository, errors::*, paths::Paths, servers::Server, settings};
+use std::path::PathBuf;
+
+use crate::cli;
+
+fn passphrase() -> Option<String> {
+    std::env::var("RUSTONE_BACKUP_PASSPHRASE").ok()
+}
+
+/// The backup repository, from the `backup_target` setting.
+fn repository_path(paths: &Paths) -> Result<PathBuf> {
+    Ok(settings::effective(paths, None)?.backup_target.value)
+}
+
+fn open_repository(paths: &Paths) -> Result<Repository> {
+    Repository::open(&repository_path(paths)?, passphrase().as_deref())
+}
+
+/// Open the backup repository, creating it first if there is none. It is
+/// encrypted if a passphrase is set.
+pub(super) fn open_or_init_repository(paths: &Paths) -> Result<Repository> {
+    let path = repository_path(paths)?;
+    if Repository::exists(&path) {
+        return open_repository(paths);
+    }
+    let passphrase = passphrase().filter(|p| !p.is_empty());
+    Repository::init(&path, passphrase.as_deref())
+}
+
+pub(crate) fn format_time(time: u64) -> String {
+    Local.timestamp_opt(time as i64, 0).single().map_or_else(
+        || time.to_string(),
+        |t| t.format("%Y-%m-%d %H:%M:%S").to_string(),
+    )
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+pub fn init(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    let passphrase = if args.is_present("encrypt") {
+        match passphrase() {
+            Some(p) if !p.is_empty() => Some(p),
+            _ => {
+                return Err(
+                    "set RUSTONE_BACKUP_PASSPHRASE to create an encrypted repository".into(),
+                )
+            }
+        }
+    } else {
+        None
+    };
+    let path = repository_path(paths)?;
+    Repository::init(&path, passphrase.as_deref())?;
+    println!(
+        "Created {}backup repository at {}",
+        if passphrase.is_some() {
+            "encrypted "
+        } else {
+            ""
+        },
+        path.to_string_lossy()
+    );
+    Ok(())
+}
+
+pub fn create(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    let server = Server::get(paths, args.value_of("NAME").unwrap())?;
+    let repo = open_repository(paths)?;
+    println!("Backing up {}...", server.config.name);
+    let (snapshot, stats) = repo.backup(&server.config.name, &server.server_path()?)?;
+    println!(
+        "Snapshot {} saved: {} files ({} unchanged), {} in total, {} new in {} chunks",
+        cli::SECONDARY.paint(&snapshot.id),
+        stats.files,
+        stats.unchanged_files,
+        format_size(stats.total_bytes),
+        format_size(stats.new_bytes),
+        stats.new_chunks
+    );
+    Ok(())
+}
+
+pub fn list(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    let repo = open_repository(paths)?;
+    let server = args.value_of("NAME");
+    for snapshot in repo.snapshots()? {
+        if server.is_none_or(|s| s == snapshot.server) {
+            println!(
+                "{} {} {} ({})",
+                cli::SECONDARY.paint(&snapshot.id),
+                format_time(snapshot.time),
+                snapshot.server,
+                format_size(snapshot.size())
+            );
+        }
+    }
+    Ok(())
+}
+
+pub fn restore(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    let repo = open_repository(paths)?;
+    let snapshot = repo.find_snapshot(args.value_of("SNAPSHOT").unwrap())?;
+    let target = args.value_of("TARGET").unwrap();
+    println!(
+        "Restoring snapshot {} of {} into {}...",
+        snapshot.id, snapshot.server, target
+    );
+    repo.restore(&snapshot, target.as_ref())?;
+    println!("done");
+    Ok(())
+}
+
+pub fn prune(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    let keep_last = args
+        .value_of("keep-last")
+        .unwrap()
+        .parse::<usize>()
+        .chain_err(|| "--keep-last must be a number")?;
+    // Keeping nothing would delete every backup
+    if keep_last == 0 {
+        return Err("--keep-last must be at least 1".into());
+    }
+    let repo = open_repository(paths)?;
+    let stats = repo.prune(keep_last)?;
+    println!(
+        "Removed {} snapshots and {} chunks",
+        stats.removed_snapshots, stats.removed_chunks
+    );
+    Ok(())
+}
+
+pub fn check(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    let repo = open_repository(paths)?;
+    let report = repo.check(args.is_present("read-data"))?;
+    for error in &report.errors {
+        println!("{} {}", cli::ERROR_HEADER_STYLE.paint("error:"), error);
+    }
+    println!(
+        "Checked {} snapshots and {} chunks",
+        report.snapshots, report.chunks
+    );
+    if !report.errors.is_empty() {
+        return Err(format!("the repository has {} errors", report.errors.len()).into());
+    }
+    Ok(())
+}
@@ -1,58 +1,97 @@
-use std::io::Write;
+use std::collections::HashSet;
 
 use indicatif::MultiProgress;
 use rustone::{
-    cacher::{cache_jar, erase_cache, read_cache_meta},
-    config::ServerVersion,
+    cacher::{download, erase_cache, get_cached, prune as prune_jars, read_index, JarKey},
+    metadata,
+    paths::Paths,
     server_kinds::ServerKind,
+    servers::get_servers,
+    updates,
 };
 
 use rustone::errors::*;
 
-pub fn purge() -> Result<()> {
+pub fn purge(paths: &Paths) -> Result<()> {
     println!("Purging cache...");
-    let meta = read_cache_meta()?.jars;
-    let pb = crate::cli::create_progressbar(meta.len() as u64);
-    for (jar, _) in meta {
-        std::fs::remove_file(jar.path()).chain_err(|| "failed to delete")?;
-        pb.inc(1);
+    let count = read_index(paths)?.jars.len();
+    erase_cache(paths)?;
+    metadata::clear(paths)?;
+    println!("Removed {} jars", count);
+    Ok(())
+}
+
+pub fn list(paths: &Paths) -> Result<()> {
+    for (key, record) in read_index(paths)?.jars {
+        println!(
+            "{} {} ({})",
+            key,
+            crate::cli::SECONDARY.paint(&record.sha256[..12]),
+            record.url.as_deref().unwrap_or("unknown origin")
+        );
+    }
+    Ok(())
+}
+
+/// Remove the jars no server runs or may roll back to.
+pub fn prune(paths: &Paths) -> Result<()> {
+    let mut used = HashSet::new();
+    for server in get_servers(paths)? {
+        used.extend(JarKey::of(&server.config));
+        // Updated servers may still need to roll back
+        if let Some(rollback_to) = server.config.rollback_to {
+            let mut config = server.config;
+            config.version = rollback_to;
+            used.extend(JarKey::of(&config));
+        }
     }
-    erase_cache()?;
-    pb.finish_with_message("done");
+    let removed = prune_jars(paths, &used)?;
+    for key in &removed {
+        println!("Removed {}", key);
+    }
+    println!("Removed {} jars", removed.len());
     Ok(())
 }
 
-pub async fn upgrade() -> Result<()> {
-    println!("Upgrading jars...");
+/// Update every server as far as its selector and update policy allow, and
+/// download the jars they need.
+pub async fn upgrade(paths: &Paths) -> Result<()> {
+    println!("Upgrading servers...");
     let mut handles = vec![];
+    let mut downloading = HashSet::new();
     let multibar = MultiProgress::new();
-    for (cjmk, patch) in read_cache_meta()?.jars {
-        let kind = cjmk.kind.parse::<ServerKind>()?;
-        let latest_patch = kind.get_latest_patch(&cjmk.version).await?;
-        if latest_patch > patch {
-            let mut resp = kind
-                .download_response(&mut ServerVersion {
-                    minecraft: cjmk.version,
-                    patch: Some(latest_patch),
-                })
-                .await?;
-            let (mut out_file, _) = cache_jar(cjmk.version, latest_patch, cjmk.kind)?;
-            let pb = crate::cli::create_download_progressbar(resp.content_length());
-            let pb = multibar.add(pb);
-            handles.push(tokio::spawn(async move {
-                while let Some(chunk) = resp
-                    .chunk()
-                    .await
-                    .chain_err(|| "failed to chunk response")?
-                {
-                    out_file
-                        .write_all(&chunk)
-                        .chain_err(|| "failed to write into file")?;
-                    pb.inc(chunk.len() as u64);
-                }
-                Ok(()) as Result<()>
-            }));
+    for mut server in get_servers(paths)? {
+        let version = updates::update(&server.config, true).await?;
+        if !updates::same_build(&version, &server.config.version) {
+            println!(
+                "{}: {} -> {}",
+                server.config.name,
+                server.config.version,
+                crate::cli::SECONDARY.paint(version.to_string())
+            );
+            let from = server.config.version;
+            updates::apply(&mut server.config, version);
+            server.save()?;
+            let kind = server.config.kind.parse::<ServerKind>()?;
+            super::log_update(&server, &kind, from).await?;
+        }
+        let key = match JarKey::of(&server.config) {
+            Some(key) => key,
+            None => continue,
+        };
+        if get_cached(paths, &key)?.is_some() || !downloading.insert(key) {
+            continue;
         }
+        let kind = server.config.kind.parse::<ServerKind>()?;
+        let (resp, mut out_file) = download(paths, &kind, &mut version.clone()).await?;
+        let pb = crate::cli::create_download_progressbar(resp.content_length());
+        let pb = multibar.add(pb);
+        let paths = paths.clone();
+        handles.push(tokio::spawn(async move {
+            crate::candy::download_with_pb(&paths, resp, &mut out_file, &pb).await?;
+            out_file.finish()?;
+            Ok(()) as Result<()>
+        }));
     }
     multibar
         .join()
@@ -61,10 +100,9 @@ pub async fn upgrade() -> Result<()> {
         let merged = futures::future::join_all(handles);
         let result = merged.await;
         for r in result {
-            if r.is_err() {
-                return Err("failed to join an upgrade task".into());
-            } else {
-                r.unwrap()?; // return an error if a future returned one
+            match r {
+                Ok(r) => r?, // return an error if a future returned one
+                Err(_) => return Err("failed to join an upgrade task".into()),
             }
         }
     } else {
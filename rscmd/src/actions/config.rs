@@ -0,0 +1,99 @@
+use clap::ArgMatches;
+use rustone::{
+    errors::*,
+    paths::Paths,
+    servers::Server,
+    settings::{self, Setting},
+};
+
+use crate::cli;
+
+fn print<T>(name: &str, setting: &Setting<T>, format: impl Fn(&T) -> String) {
+    println!(
+        "{:<20} {:<40} ({})",
+        name,
+        format(&setting.value),
+        cli::SECONDARY.paint(setting.origin.to_string())
+    );
+}
+
+fn optional<T: ToString>(value: &Option<T>) -> String {
+    value
+        .as_ref()
+        .map_or_else(|| "-".to_owned(), ToString::to_string)
+}
+
+pub fn show(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    let server = match args.value_of("NAME") {
+        Some(name) => Some(Server::get(paths, name)?),
+        None => None,
+    };
+    if let (Some(server), false) = (&server, args.is_present("effective")) {
+        let path = server.config.config_path();
+        print!(
+            "{}",
+            std::fs::read_to_string(&path)
+                .chain_err(|| format!("failed to read {}", path.to_string_lossy()))?
+        );
+        return Ok(());
+    }
+
+    println!(
+        "Global configuration: {}",
+        paths.global_config().to_string_lossy()
+    );
+    let config = settings::effective(paths, server.as_ref().map(|s| &s.config))?;
+    print("java_path", &config.java_path, |p| {
+        optional(&p.as_ref().map(|p| p.to_string_lossy()))
+    });
+    print("min_heap", &config.min_heap, optional);
+    print("max_heap", &config.max_heap, ToString::to_string);
+    print("jvm_preset", &config.jvm_preset, |p| {
+        format!("{:?}", p).to_lowercase()
+    });
+    print("port_range", &config.port_range, ToString::to_string);
+    print("backup_target", &config.backup_target, |p| {
+        p.to_string_lossy().into_owned()
+    });
+    print("offline", &config.offline, ToString::to_string);
+    print("metadata_ttl", &config.metadata_ttl, |ttl| {
+        format!("{}s", ttl)
+    });
+    let network = &config.network;
+    print("network.proxy", &network.proxy, optional);
+    print("network.no_proxy", &network.no_proxy, |hosts| {
+        if hosts.is_empty() {
+            "-".to_owned()
+        } else {
+            hosts.join(",")
+        }
+    });
+    print("network.ca_bundle", &network.ca_bundle, |p| {
+        optional(&p.as_ref().map(|p| p.to_string_lossy()))
+    });
+    print("network.user_agent", &network.user_agent, Clone::clone);
+    print("network.connect_timeout", &network.connect_timeout, |t| {
+        format!("{}s", t)
+    });
+    print("network.read_timeout", &network.read_timeout, |t| {
+        format!("{}s", t)
+    });
+    print(
+        "network.ip_version",
+        &network.ip_version,
+        ToString::to_string,
+    );
+    let mirrors = &config.mirrors;
+    for (name, mirror) in &[
+        ("mirrors.paper", &mirrors.paper),
+        ("mirrors.fabric", &mirrors.fabric),
+        ("mirrors.forge", &mirrors.forge),
+        ("mirrors.mojang", &mirrors.mojang),
+        ("mirrors.modrinth", &mirrors.modrinth),
+        ("mirrors.hangar", &mirrors.hangar),
+        ("mirrors.curseforge", &mirrors.curseforge),
+    ] {
+        print(name, mirror, Clone::clone);
+    }
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use clap::ArgMatches;
+use rustone::{
+    errors::*,
+    mods::{self, ModEntry, ModManifest, ModSource, SyncReport},
+    paths::Paths,
+    servers::Server,
+};
+
+use crate::cli;
+
+fn print_report(report: SyncReport) {
+    for (name, version) in report.installed {
+        println!("Installed {} {}", name, cli::SECONDARY.paint(version));
+    }
+    for name in report.removed {
+        println!("Removed {}", name);
+    }
+    for name in report.client_only {
+        println!("Skipped {} (client-only)", name);
+    }
+}
+
+pub async fn add(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    let server = Server::get(paths, args.value_of("SERVER").unwrap())?;
+    let name = args.value_of("MOD").unwrap();
+    let project = args.value_of("project").unwrap_or(name).to_owned();
+    let source = match args.value_of("source").unwrap() {
+        "modrinth" => ModSource::Modrinth { project },
+        "curseforge" => ModSource::Curseforge { project },
+        "url" => ModSource::Url {
+            url: args.value_of("url").unwrap().to_owned(),
+        },
+        "path" => ModSource::Path {
+            path: std::fs::canonicalize(args.value_of("path").unwrap())
+                .chain_err(|| "mod jar not found")?,
+        },
+        _ => unreachable!(),
+    };
+    let entry = ModEntry {
+        source,
+        version: args.value_of("version").unwrap().parse()?,
+    };
+    println!("Installing {}...", name);
+    print_report(mods::add(&server.config, name, entry).await?);
+    Ok(())
+}
+
+pub fn remove(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    let server = Server::get(paths, args.value_of("SERVER").unwrap())?;
+    print_report(mods::remove(&server.config, args.value_of("MOD").unwrap())?);
+    Ok(())
+}
+
+pub fn list(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    let server = Server::get(paths, args.value_of("SERVER").unwrap())?;
+    let manifest = ModManifest::load(&server.config)?;
+    for installed in manifest.installed.values() {
+        let requested = manifest.mods.get(&installed.name);
+        println!(
+            "{} {}{}",
+            installed.name,
+            cli::SECONDARY.paint(&installed.version),
+            match requested {
+                Some(entry) => format!(" ({})", entry.version),
+                None => format!(" (required by {})", installed.required_by.join(", ")),
+            }
+        );
+    }
+    Ok(())
+}
+
+pub async fn update(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    let server = Server::get(paths, args.value_of("SERVER").unwrap())?;
+    println!("Updating mods...");
+    let report = mods::update(&server.config).await?;
+    if report.installed.is_empty() && report.removed.is_empty() {
+        println!("Mods up to date");
+    }
+    print_report(report);
+    Ok(())
+}
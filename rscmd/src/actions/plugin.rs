@@ -0,0 +1,92 @@
+use clap::ArgMatches;
+use rustone::{
+    errors::*,
+    paths::Paths,
+    plugins::{self, PluginEntry, PluginManifest, PluginSource, Sources},
+    servers::Server,
+};
+
+use crate::cli;
+
+pub async fn add(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    let server = Server::get(paths, args.value_of("SERVER").unwrap())?;
+    let name = args.value_of("PLUGIN").unwrap();
+    let project = args.value_of("project").unwrap_or(name).to_owned();
+    let source = match args.value_of("source").unwrap() {
+        "modrinth" => PluginSource::Modrinth { project },
+        "hangar" => PluginSource::Hangar { project },
+        "url" => PluginSource::Url {
+            url: args.value_of("url").unwrap().to_owned(),
+        },
+        "path" => PluginSource::Path {
+            path: std::fs::canonicalize(args.value_of("path").unwrap())
+                .chain_err(|| "plugin jar not found")?,
+        },
+        _ => unreachable!(),
+    };
+    let entry = PluginEntry {
+        source,
+        version: args.value_of("version").unwrap().parse()?,
+        installed: None,
+    };
+    println!("Installing {}...", name);
+    let installed = plugins::add(&server.config, name, entry, &Sources::new(paths)).await?;
+    println!(
+        "Installed {} {}",
+        name,
+        cli::SECONDARY.paint(&installed.version)
+    );
+    Ok(())
+}
+
+pub fn remove(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    let server = Server::get(paths, args.value_of("SERVER").unwrap())?;
+    plugins::remove(&server.config, args.value_of("PLUGIN").unwrap())
+}
+
+pub fn list(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    let server = Server::get(paths, args.value_of("SERVER").unwrap())?;
+    for (name, entry) in PluginManifest::load(&server.config)?.plugins {
+        let source = match &entry.source {
+            PluginSource::Modrinth { project } => format!("modrinth:{}", project),
+            PluginSource::Hangar { project } => format!("hangar:{}", project),
+            PluginSource::Url { url } => url.clone(),
+            PluginSource::Path { path } => path.to_string_lossy().into_owned(),
+        };
+        println!(
+            "{} {} ({}, {})",
+            name,
+            cli::SECONDARY.paint(
+                entry
+                    .installed
+                    .map_or_else(|| "not installed".to_owned(), |i| i.version)
+            ),
+            entry.version,
+            source
+        );
+    }
+    Ok(())
+}
+
+pub async fn update(paths: &Paths, args: &ArgMatches<'_>) -> Result<()> {
+    let server = Server::get(paths, args.value_of("SERVER").unwrap())?;
+    println!("Updating plugins...");
+    let updates = plugins::update(
+        &server.config,
+        args.value_of("PLUGIN"),
+        &Sources::new(paths),
+    )
+    .await?;
+    if updates.is_empty() {
+        println!("Plugins up to date");
+    }
+    for update in updates {
+        println!(
+            "{}: {} -> {}",
+            update.name,
+            update.from.as_deref().unwrap_or("not installed"),
+            cli::SECONDARY.paint(update.to)
+        );
+    }
+    Ok(())
+}
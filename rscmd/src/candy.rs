@@ -1,23 +1,40 @@
-use std::{fs, io::Write};
-use indicatif::ProgressBar;
-use rustone::errors::*;
 use crate::cli;
+use indicatif::ProgressBar;
+use rustone::{
+    download::{self, DownloadOptions},
+    errors::*,
+    paths::Paths,
+};
+use std::io::Write;
 
-pub async fn download(response: &mut reqwest::Response, file: &mut fs::File) -> Result<()> {
+pub async fn download(
+    paths: &Paths,
+    response: reqwest::Response,
+    file: &mut impl Write,
+) -> Result<()> {
     let pb = cli::create_download_progressbar(response.content_length());
-    download_with_pb(response, file, &pb).await
+    download_with_pb(paths, response, file, &pb).await
 }
 
-pub async fn download_with_pb(response: &mut reqwest::Response, file: &mut fs::File, pb: &ProgressBar) -> Result<()> {
-    while let Some(chunk) = response
-        .chunk()
-        .await
-        .chain_err(|| "failed to read chunk")?
-    {
-        file.write_all(&chunk)
-            .chain_err(|| "failed to write into file")?;
-        pb.inc(chunk.len() as u64);
-    }
+pub async fn download_with_pb(
+    paths: &Paths,
+    response: reqwest::Response,
+    file: &mut impl Write,
+    pb: &ProgressBar,
+) -> Result<()> {
+    download::stream(
+        paths,
+        response,
+        file,
+        &DownloadOptions::default(),
+        |progress| {
+            if let Some(total) = progress.total {
+                pb.set_length(total);
+            }
+            pb.set_position(progress.downloaded);
+        },
+    )
+    .await?;
     pb.finish_with_message("done");
     Ok(())
-}
\ No newline at end of file
+}
@@ -1,5 +1,5 @@
 use ansi_term::Color;
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::ProgressBar;
 use lazy_static::lazy_static;
 
 fn style_if_term(style: ansi_term::Style) -> ansi_term::Style {
@@ -29,16 +29,6 @@ pub(crate) fn create_download_progressbar(len: Option<u64>) -> ProgressBar {
     }
 }
 
-pub fn create_progressbar(len: u64) -> ProgressBar {
-    let pb = ProgressBar::new(len);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("[{bar:20.red}] {pos}/{len}")
-            .progress_chars("=> "),
-    );
-    pb
-}
-
 lazy_static! {
     pub static ref USE_COLOR: bool =
         atty::is(atty::Stream::Stdout) && atty::is(atty::Stream::Stderr);
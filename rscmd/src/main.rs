@@ -1,29 +1,98 @@
 extern crate rustone;
 
-use clap::{load_yaml, App};
+use clap::{load_yaml, App, ArgMatches};
+use rustone::{
+    errors::{Category, Error},
+    paths::Paths,
+    settings::Layers,
+};
+use tracing_subscriber::EnvFilter;
 
 mod actions;
-mod cli;
 mod candy;
+mod cli;
 
 fn enable_ansi() {
     #[cfg(windows)]
     ansi_term::enable_ansi_support();
 }
 
+/// The exit code for `err`, from sysexits.h, so that scripts can tell
+/// failures apart.
+fn exit_code(err: &Error) -> i32 {
+    match err.category() {
+        Category::NotFound => 66,            // EX_NOINPUT
+        Category::AlreadyExists => 73,       // EX_CANTCREAT
+        Category::UpstreamUnavailable => 69, // EX_UNAVAILABLE
+        Category::InvalidConfig => 78,       // EX_CONFIG
+        Category::Io => 74,                  // EX_IOERR
+        Category::ProcessFailed => 71,       // EX_OSERR
+        Category::Other => 1,
+    }
+}
+
+/// Log to standard error, as verbosely as `-v` and `-q` ask. `RUST_LOG`
+/// overrides them.
+fn init_logging(matches: &ArgMatches<'_>) {
+    let level = if matches.is_present("quiet") {
+        "error"
+    } else {
+        match matches.occurrences_of("verbose") {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }
+    };
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        // Dependencies stay at warnings, RUST_LOG can turn them up
+        EnvFilter::new(format!("warn,rustone={0},rscmd={0}", level))
+    });
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_ansi(atty::is(atty::Stream::Stderr))
+        .init();
+}
+
 #[tokio::main]
 async fn main() {
     let yaml = load_yaml!("cli.yaml");
     let matches = App::from_yaml(yaml).get_matches();
+    init_logging(&matches);
+
+    let paths = match matches.value_of_os("home") {
+        Some(home) => Ok(Paths::from_home(home.as_ref())),
+        None => Paths::from_env(),
+    };
 
-    let out = match matches.subcommand() {
-        ("download", matches) => actions::download(matches.unwrap()).await,
-        ("list", _) => actions::list(),
-        ("create", matches) => actions::create(matches.unwrap()),
-        ("start", matches) => actions::start(matches.unwrap()).await,
-        ("remove", matches) => actions::remove(matches.unwrap()),
-        ("cache", matches) => actions::cache(matches.unwrap()).await,
-        _ => unreachable!(),
+    // Report a broken rustone.toml before anything else reads it
+    let out = match paths.and_then(|paths| Layers::load(&paths).map(|_| paths)) {
+        Err(e) => Err(e),
+        Ok(paths) => {
+            let paths = &paths;
+            match matches.subcommand() {
+                ("download", matches) => actions::download(paths, matches.unwrap()).await,
+                ("versions", matches) => actions::versions(paths, matches.unwrap()).await,
+                ("list", _) => actions::list(paths),
+                ("java", _) => actions::java(),
+                ("create", matches) => actions::create(paths, matches.unwrap()).await,
+                ("clone", matches) => actions::clone(paths, matches.unwrap()),
+                ("template", matches) => actions::template(paths, matches.unwrap()),
+                ("export", matches) => actions::export(paths, matches.unwrap()),
+                ("import", matches) => actions::import(paths, matches.unwrap()),
+                ("upgrade", matches) => actions::upgrade(paths, matches.unwrap()).await,
+                ("history", matches) => actions::history(paths, matches.unwrap()),
+                ("start", matches) => actions::start(paths, matches.unwrap()).await,
+                ("remove", matches) => actions::remove(paths, matches.unwrap()),
+                ("cache", matches) => actions::cache(paths, matches.unwrap()).await,
+                ("config", matches) => actions::config(paths, matches.unwrap()),
+                ("backup", matches) => actions::backup(paths, matches.unwrap()),
+                ("plugin", matches) => actions::plugin(paths, matches.unwrap()).await,
+                ("mod", matches) => actions::mods(paths, matches.unwrap()).await,
+                _ => unreachable!(),
+            }
+        }
     };
 
     enable_ansi();
@@ -61,6 +130,6 @@ async fn main() {
                 }
             }
         }
-        std::process::exit(1);
+        std::process::exit(exit_code(&err));
     }
 }
@@ -1,18 +1,72 @@
+use std::path::PathBuf;
+
+use rustone::paths::Paths;
+use tracing::error;
+use tracing_subscriber::EnvFilter;
 use warp::Filter;
 
 //mod communication;
 mod routes;
 
+/// The directory given with `--home <dir>` or `--home=<dir>`, which is used
+/// instead of `RUSTONE_HOME`. Other arguments are refused.
+fn home_arg() -> Result<Option<PathBuf>, String> {
+    let mut args = std::env::args_os().skip(1);
+    let mut home = None;
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--home") => match args.next() {
+                Some(dir) => home = Some(PathBuf::from(dir)),
+                None => return Err("--home needs a directory".to_owned()),
+            },
+            Some(arg) if arg.starts_with("--home=") => {
+                home = Some(PathBuf::from(&arg["--home=".len()..]))
+            }
+            _ => return Err(format!("unexpected argument {}", arg.to_string_lossy())),
+        }
+    }
+    Ok(home)
+}
+
 #[tokio::main]
 async fn main() {
+    // One JSON object per line on standard output, filtered by RUST_LOG
+    tracing_subscriber::fmt()
+        .json()
+        .with_env_filter(
+            EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| EnvFilter::new("warn,rustone=info,rshttp=info,warp=info")),
+        )
+        .init();
+
+    // Every route works on the same instance
+    let paths = match home_arg() {
+        Ok(Some(home)) => Ok(Paths::from_home(&home)),
+        Ok(None) => Paths::from_env(),
+        Err(e) => {
+            error!(error = %e, "invalid arguments, usage: rshttp [--home <dir>]");
+            std::process::exit(2);
+        }
+    };
+    let paths = match paths {
+        Ok(paths) => paths,
+        Err(e) => {
+            error!(error = %e, "failed to find the rustone home");
+            std::process::exit(1);
+        }
+    };
+    let with_paths = warp::any().map(move || paths.clone());
+
     let get_server = warp::get()
         .and(warp::path("server"))
         .and(warp::path::param())
         .and(warp::path::end())
+        .and(with_paths.clone())
         .and_then(routes::get_server);
     let get_servers = warp::get()
         .and(warp::path("server"))
         .and(warp::path::end())
+        .and(with_paths.clone())
         .and_then(routes::list_servers);
 
     // /server/<name>/start: start a server
@@ -22,11 +76,27 @@ async fn main() {
         .and(warp::path::param())
         .and(warp::path("start"))
         .and(warp::path::end())
+        .and(with_paths)
         .and_then(routes::start_server);
 
-    println!("Serving on 0.0.0.0:8080");
+    // /server/<name>/progress: the jar download of a starting server
+    // returns {success: true, payload: {downloaded, total}} while downloading
+    let progress_path = warp::get()
+        .and(warp::path("server"))
+        .and(warp::path::param())
+        .and(warp::path("progress"))
+        .and(warp::path::end())
+        .and_then(routes::download_progress);
+
+    let address = "0.0.0.0:8081".parse::<std::net::SocketAddrV4>().unwrap();
 
-    warp::serve(get_server.or(get_servers).or(start_server_path))
-        .run("0.0.0.0:8081".parse::<std::net::SocketAddrV4>().unwrap())
-        .await;
+    warp::serve(
+        get_server
+            .or(get_servers)
+            .or(start_server_path)
+            .or(progress_path)
+            .with(warp::trace::request()),
+    )
+    .run(address)
+    .await;
 }
@@ -1,16 +1,42 @@
-use std::{io::Write, process::Stdio};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
 
 use http::StatusCode;
 use json::JsonValue;
+use lazy_static::lazy_static;
 use rustone::{
-    cacher::{self, CachedJarMetaKey},
-    config::{MinecraftVersion, ServerConfig, ServerVersion},
-    errors::{self, ResultExt},
-    server_kinds::ServerKind,
+    config::ServerVersion,
+    download::Progress,
+    errors::{self, Category},
+    paths::Paths,
+    provision::{Console, ProgressSink, ProvisionOptions, Update},
+    server_kinds::{Build, ServerKind},
     servers,
+    updates::Startup,
 };
+use tokio::sync::Notify;
+use tracing::{debug, error, info_span, Instrument};
 use warp::reply::with_status;
 
+/// The HTTP status matching the category of `error`.
+fn status(error: &errors::Error) -> StatusCode {
+    match error.category() {
+        Category::NotFound => StatusCode::NOT_FOUND,
+        Category::AlreadyExists => StatusCode::CONFLICT,
+        Category::UpstreamUnavailable => StatusCode::BAD_GATEWAY,
+        Category::InvalidConfig | Category::Io | Category::ProcessFailed | Category::Other => {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+lazy_static! {
+    /// Progress of the jar downloads running in the background, by server
+    static ref JOBS: Mutex<HashMap<String, Progress>> = Mutex::new(HashMap::new());
+}
+
 macro_rules! route_try {
     ($x:expr) => {{
         match $x {
@@ -18,16 +44,18 @@ macro_rules! route_try {
             Err(e) => {
                 return Ok(with_status(
                     json::stringify(json::object! {success: false, payload: e.to_string()}),
-                    StatusCode::INTERNAL_SERVER_ERROR,
+                    status(&e),
                 ))
             }
         }
     }};
 }
 
-pub async fn get_server(name: String) -> std::result::Result<impl warp::Reply, warp::Rejection> {
-    let server = servers::Server::get(&name);
-    match server {
+pub async fn get_server(
+    name: String,
+    paths: Paths,
+) -> std::result::Result<impl warp::Reply, warp::Rejection> {
+    match servers::Server::get(&paths, &name) {
         Ok(server) => Ok(warp::reply::with_status(
             json::stringify(json::object! {
                 success: true,
@@ -39,17 +67,17 @@ pub async fn get_server(name: String) -> std::result::Result<impl warp::Reply, w
         )),
         Err(error) => Ok(warp::reply::with_status(
             json::stringify(json::object! {success: false, payload: error.to_string()}),
-            StatusCode::NOT_FOUND,
+            status(&error),
         )),
     }
 }
 
-pub async fn list_servers() -> std::result::Result<impl warp::Reply, warp::Rejection> {
-    let servers_iter = servers::get_servers();
+pub async fn list_servers(paths: Paths) -> std::result::Result<impl warp::Reply, warp::Rejection> {
+    let servers_iter = servers::get_servers(&paths);
     if let Err(error) = servers_iter {
         return Ok(warp::reply::with_status(
             json::stringify(json::object! {success: false, payload: error.to_string()}),
-            StatusCode::INTERNAL_SERVER_ERROR,
+            status(&error),
         ));
     }
     let servers: Vec<JsonValue> = servers_iter
@@ -64,113 +92,158 @@ pub async fn list_servers() -> std::result::Result<impl warp::Reply, warp::Rejec
     ))
 }
 
-async fn download_jar_and_start(
-    version: MinecraftVersion,
-    patch: u32,
+/// Reports the preparation of a starting server: its jar download as job
+/// progress, and the update applied to it or rolled back.
+struct StartJob {
+    name: String,
     kind: ServerKind,
-    cfg: ServerConfig,
-) -> errors::Result<()> {
-    let (mut file, _) = cacher::cache_jar(version, patch, kind.to_string())?;
-    let resp = kind
-        .download_response(&mut ServerVersion {
-            patch: Some(patch),
-            minecraft: version,
+    update: Mutex<Option<JsonValue>>,
+    rollback: Mutex<Option<JsonValue>>,
+    /// Notified when the jar starts downloading
+    downloading: Notify,
+}
+
+impl ProgressSink for StartJob {
+    fn updated(&self, update: &Update) {
+        *self.update.lock().unwrap() = Some(update_json(
+            &self.kind,
+            update.from,
+            update.to,
+            &update.builds,
+        ));
+    }
+
+    fn download(&self, progress: Progress) {
+        if JOBS
+            .lock()
+            .unwrap()
+            .insert(self.name.clone(), progress)
+            .is_none()
+        {
+            self.downloading.notify_one();
+        }
+    }
+
+    fn downloaded(&self) {
+        JOBS.lock().unwrap().remove(&self.name);
+    }
+
+    fn rolled_back(&self, bad: &ServerVersion, startup: Startup, to: &ServerVersion) {
+        *self.rollback.lock().unwrap() = Some(json::object! {
+            from: bad.to_string(),
+            to: to.to_string(),
+            reason: startup.to_string()
+        });
+    }
+}
+
+/// /server/<name>/progress: how far the jar download of a starting server is
+pub async fn download_progress(
+    name: String,
+) -> std::result::Result<impl warp::Reply, warp::Rejection> {
+    match JOBS.lock().unwrap().get(&name) {
+        Some(progress) => Ok(with_status(
+            json::stringify(json::object! {
+                success: true,
+                payload: {downloaded: progress.downloaded, total: progress.total}
+            }),
+            StatusCode::OK,
+        )),
+        None => Ok(with_status(
+            json::stringify(json::object! {
+                success: false,
+                payload: format!("{} is not downloading anything", name)
+            }),
+            StatusCode::NOT_FOUND,
+        )),
+    }
+}
+
+fn update_json(
+    kind: &ServerKind,
+    from: ServerVersion,
+    to: ServerVersion,
+    builds: &[Build],
+) -> JsonValue {
+    let builds: Vec<JsonValue> = builds
+        .iter()
+        .map(|build| {
+            let changes: Vec<JsonValue> = build
+                .changes
+                .iter()
+                .map(|c| json::object! {commit: c.commit.clone(), summary: c.summary.clone()})
+                .collect();
+            json::object! {build: kind.build_name(build.number), changes: changes}
         })
-        .await?;
-    tokio::spawn(async move {
-        file.write_all(
-            &resp
-                .bytes()
-                .await
-                .chain_err(|| "failed to download jar file")?,
-        )
-        .chain_err(|| "failed to write into jar file")?;
-        kind.launch(cfg, Stdio::piped(), Stdio::piped(), Stdio::piped())?;
-        Ok(()) as errors::Result<()>
-    });
-    Ok(())
+        .collect();
+    json::object! {from: from.to_string(), to: to.to_string(), builds: builds}
 }
 
-pub async fn start_server(name: String) -> std::result::Result<impl warp::Reply, warp::Rejection> {
-    match servers::Server::get(&name) {
-        Ok(server) => {
-            let kind = server.config.kind.parse::<ServerKind>();
-            if let Err(e) = kind {
-                return Ok(with_status(
-                    json::stringify(json::object! {success: false, payload: e.to_string()}),
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                ));
+/// /server/<name>/start: prepare and launch a server. Servers whose jar has
+/// to be downloaded first are launched in the background, with `202
+/// Accepted`.
+pub async fn start_server(
+    name: String,
+    paths: Paths,
+) -> std::result::Result<impl warp::Reply, warp::Rejection> {
+    let mut server = route_try!(servers::Server::get(&paths, &name));
+    let kind = route_try!(server.config.kind.parse::<ServerKind>());
+    let job = Arc::new(StartJob {
+        name,
+        kind,
+        update: Mutex::new(None),
+        rollback: Mutex::new(None),
+        downloading: Notify::new(),
+    });
+    let job_sink = job.clone();
+    let span = info_span!("start", server = %job.name);
+    let mut task = tokio::spawn(
+        async move {
+            let started = async {
+                let options = ProvisionOptions::default();
+                let prepared = server.prepare(&options, job_sink.as_ref()).await;
+                // Failed downloads leave their progress behind
+                JOBS.lock().unwrap().remove(&job_sink.name);
+                prepared?;
+                let started = server
+                    .start_watched(&options, Console::Piped, job_sink.as_ref(), |line| {
+                        debug!(line, "server output")
+                    })
+                    .await;
+                JOBS.lock().unwrap().remove(&job_sink.name);
+                started?;
+                Ok(()) as errors::Result<()>
             }
-            let kind = kind.unwrap();
-            let key = CachedJarMetaKey {
-                kind: server.config.kind.clone(),
-                version: server.config.version.minecraft,
-            };
-            let cached = cacher::get_cached_patch(&key);
-            if let Err(e) = cached {
-                return Ok(with_status(
-                    json::stringify(json::object! {success: false, payload: e.to_string()}),
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                ));
+            .await;
+            // Nobody hears about failures after 202 Accepted otherwise
+            if let Err(e) = &started {
+                error!(error = %e, "failed to start server");
             }
-            let cached = cached.unwrap();
-            match cached {
-                Some(patch) => {
-                    let latest_patch = route_try!(
-                        kind.get_latest_patch(&server.config.version.minecraft)
-                            .await
-                    );
-
-                    if latest_patch > patch {
-                        route_try!(
-                            download_jar_and_start(
-                                server.config.version.minecraft,
-                                latest_patch,
-                                kind,
-                                server.config
-                            )
-                            .await
-                        );
-                        return Ok(with_status(
-                            json::stringify(json::object! {success: true, payload: {}}),
-                            StatusCode::ACCEPTED,
-                        ));
-                    } else {
-                        route_try!(kind.launch(
-                            server.config,
-                            Stdio::piped(),
-                            Stdio::piped(),
-                            Stdio::piped()
-                        ));
-                    }
-                }
-                None => {
-                    route_try!(
-                        download_jar_and_start(
-                            server.config.version.minecraft,
-                            route_try!(
-                                kind.get_latest_patch(&server.config.version.minecraft)
-                                    .await
-                            ),
-                            kind,
-                            server.config
-                        )
-                        .await
-                    );
-                    return Ok(with_status(
-                        json::stringify(json::object! {success: true, payload: {}}),
-                        StatusCode::ACCEPTED,
-                    ));
-                }
+            started
+        }
+        .instrument(span),
+    );
+    let status = tokio::select! {
+        result = &mut task => {
+            match result {
+                Ok(result) => route_try!(result),
+                Err(_) => route_try!(Err(errors::Error::from("failed to join the start task"))),
             }
-            Ok(warp::reply::with_status(
-                json::stringify(json::object! {success: true, payload: {}}),
-                http::StatusCode::OK,
-            ))
+            StatusCode::OK
         }
-        Err(error) => Ok(warp::reply::with_status(
-            json::stringify(json::object! {success: false, payload: error.to_string()}),
-            http::StatusCode::NOT_FOUND,
-        )),
+        _ = job.downloading.notified() => StatusCode::ACCEPTED,
+    };
+    // The update applied, if any, with what changed, and the rollback from
+    // it if it failed to start
+    let mut payload = json::object! {};
+    if let Some(update) = job.update.lock().unwrap().clone() {
+        payload["update"] = update;
+    }
+    if let Some(rollback) = job.rollback.lock().unwrap().clone() {
+        payload["rollback"] = rollback;
     }
+    Ok(with_status(
+        json::stringify(json::object! {success: true, payload: payload}),
+        status,
+    ))
 }
@@ -0,0 +1,281 @@
+//! Portable server archives, to move a server to another host.
+//!
+//! An archive is a zip file with a `manifest.json`, the `server.toml`
+//! configuration and the files of the server under `files/`. Server jars
+//! are not included, only the checksum of the cached one: the jar is
+//! downloaded again when the archive is restored. Servers in the flat layout
+//! have their jar and what it downloads in their directory, these are left
+//! out too.
+
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use error_chain::bail;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    cacher::{self, JarKey},
+    config::{ServerConfig, ServerLayout, ServerVersion},
+    errors::*,
+    import::is_server_file,
+    paths::Paths,
+    servers::Server,
+};
+
+const FORMAT_VERSION: u32 = 1;
+const MANIFEST_NAME: &str = "manifest.json";
+const CONFIG_NAME: &str = "server.toml";
+const FILES_PREFIX: &str = "files/";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ArchiveManifest {
+    pub format: u32,
+    pub name: String,
+    pub kind: String,
+    pub version: ServerVersion,
+    /// SHA-256 of the server jar the server was running on
+    pub jar_sha256: Option<String>,
+    /// Unix time of the export
+    pub created: u64,
+}
+
+/// Hex SHA-256 of a file.
+pub fn file_sha256(path: &Path) -> Result<String> {
+    let mut file =
+        fs::File::open(path).chain_err(|| format!("failed to open {}", path.to_string_lossy()))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)
+        .chain_err(|| format!("failed to read {}", path.to_string_lossy()))?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Files which are left out: unfinished downloads and staging directories.
+pub(crate) fn is_temporary(name: &str) -> bool {
+    name.ends_with(".part") || name == ".mods-staging"
+}
+
+/// Add the files under `dir` to the archive, with paths relative to `base`.
+/// With `flat`, the server files at the top of `base` are left out.
+fn add_dir<W: Write + io::Seek>(
+    zip: &mut zip::ZipWriter<W>,
+    base: &Path,
+    dir: &Path,
+    flat: bool,
+    options: zip::write::FileOptions,
+) -> Result<()> {
+    let mut entries: Vec<_> = fs::read_dir(dir)
+        .chain_err(|| format!("failed to list {}", dir.to_string_lossy()))?
+        .collect::<io::Result<_>>()
+        .chain_err(|| "failed to read directory entry")?;
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let path = entry.path();
+        if is_temporary(&entry.file_name().to_string_lossy())
+            || (flat && dir == base && is_server_file(&path))
+        {
+            continue;
+        }
+        let file_type = entry.file_type().chain_err(|| "failed to read file type")?;
+        let relative = path.strip_prefix(base).unwrap();
+        let name = format!(
+            "{}{}",
+            FILES_PREFIX,
+            relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/")
+        );
+        if file_type.is_dir() {
+            zip.add_directory(name, options)
+                .chain_err(|| "failed to write archive")?;
+            add_dir(zip, base, &path, flat, options)?;
+        } else if file_type.is_file() {
+            zip.start_file(name, options)
+                .chain_err(|| "failed to write archive")?;
+            let mut file = fs::File::open(&path)
+                .chain_err(|| format!("failed to open {}", path.to_string_lossy()))?;
+            io::copy(&mut file, zip)
+                .chain_err(|| format!("failed to archive {}", path.to_string_lossy()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Write `server` into an archive at `dest`.
+pub fn export(server: &Server, dest: &Path) -> Result<ArchiveManifest> {
+    let config = &server.config;
+    let jar = match JarKey::of(config) {
+        Some(key) => cacher::get_cached(&config.paths, &key)?,
+        None => None,
+    };
+    let manifest = ArchiveManifest {
+        format: FORMAT_VERSION,
+        name: config.name.clone(),
+        kind: config.kind.clone(),
+        version: config.version,
+        jar_sha256: jar.map(|(_, record)| record.sha256),
+        created: SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+    };
+    // The archive is restored into rustone's data directory
+    let mut portable = config.clone();
+    portable.directory = None;
+
+    let partial = dest.with_file_name(format!(
+        ".{}.part",
+        dest.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    let written = (|| {
+        let file = fs::File::create(&partial)
+            .chain_err(|| format!("failed to create {}", partial.to_string_lossy()))?;
+        let mut zip = zip::ZipWriter::new(file);
+        let options = zip::write::FileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .large_file(true);
+        zip.start_file(MANIFEST_NAME, options)
+            .chain_err(|| "failed to write archive")?;
+        serde_json::to_writer_pretty(&mut zip, &manifest)
+            .chain_err(|| "failed to write archive manifest")?;
+        zip.start_file(CONFIG_NAME, options)
+            .chain_err(|| "failed to write archive")?;
+        zip.write_all(
+            toml::to_string_pretty(&portable)
+                .chain_err(|| "failed to generate configuration")?
+                .as_bytes(),
+        )
+        .chain_err(|| "failed to write archive")?;
+        let path = config.path();
+        let flat = config.layout == ServerLayout::Flat;
+        add_dir(&mut zip, &path, &path, flat, options)?;
+        zip.finish().chain_err(|| "failed to finish archive")?;
+        Ok(())
+    })();
+    if let Err(e) = written {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+    fs::rename(&partial, dest)
+        .chain_err(|| format!("failed to write {}", dest.to_string_lossy()))?;
+    Ok(manifest)
+}
+
+fn open_archive(path: &Path) -> Result<zip::ZipArchive<fs::File>> {
+    let file =
+        fs::File::open(path).chain_err(|| format!("failed to open {}", path.to_string_lossy()))?;
+    zip::ZipArchive::new(file)
+        .chain_err(|| format!("{} is not a valid server archive", path.to_string_lossy()))
+}
+
+fn read_entry(archive: &mut zip::ZipArchive<fs::File>, name: &str) -> Result<String> {
+    let mut entry = archive
+        .by_name(name)
+        .chain_err(|| format!("server archive has no {}", name))?;
+    let mut text = String::new();
+    entry
+        .read_to_string(&mut text)
+        .chain_err(|| format!("failed to read {}", name))?;
+    Ok(text)
+}
+
+/// Read the manifest and the configuration of an archive.
+pub fn read(path: &Path) -> Result<(ArchiveManifest, ServerConfig)> {
+    let mut archive = open_archive(path)?;
+    let manifest: ArchiveManifest = serde_json::from_str(&read_entry(&mut archive, MANIFEST_NAME)?)
+        .chain_err(|| "invalid archive manifest")?;
+    if manifest.format > FORMAT_VERSION {
+        bail!(
+            "server archive has format {}, this version of rustone only supports up to {}",
+            manifest.format,
+            FORMAT_VERSION
+        );
+    }
+    let config = ServerConfig::new(&read_entry(&mut archive, CONFIG_NAME)?)?;
+    Ok((manifest, config))
+}
+
+/// Restore the server in the archive at `path` as `name`, in the instance in
+/// `paths`. An existing server with the same name is only replaced if
+/// `overwrite` is set, once the archive was extracted in full.
+pub fn restore(
+    paths: &Paths,
+    name: &str,
+    path: &Path,
+    overwrite: bool,
+) -> Result<(Server, ArchiveManifest)> {
+    let (manifest, mut config) = read(path)?;
+    config.name = name.to_owned();
+    config.directory = None;
+    config.paths = paths.clone();
+
+    let exists = config.config_path().exists();
+    if exists && !overwrite {
+        bail!(ErrorKind::AlreadyExists(format!("a server named {}", name)));
+    }
+    let target = config.path();
+    if target.exists() && !overwrite {
+        bail!(ErrorKind::AlreadyExists(format!(
+            "the directory of server {} ({})",
+            name,
+            target.to_string_lossy()
+        )));
+    }
+
+    // Extracted next to the server, which is only replaced once that worked
+    let staging = target.with_file_name(format!(".{}.restoring", name));
+    if staging.exists() {
+        fs::remove_dir_all(&staging).chain_err(|| "failed to clean restore directory")?;
+    }
+    let mut archive = open_archive(path)?;
+    let extracted = (|| {
+        fs::create_dir_all(&staging).chain_err(|| "failed to create restore directory")?;
+        for i in 0..archive.len() {
+            let mut entry = archive
+                .by_index(i)
+                .chain_err(|| "failed to read archive entry")?;
+            let relative: PathBuf = match entry.enclosed_name() {
+                Some(name) => match name.strip_prefix(FILES_PREFIX) {
+                    Ok(relative) => relative.to_owned(),
+                    Err(_) => continue,
+                },
+                None => bail!("server archive contains an unsafe path: {}", entry.name()),
+            };
+            let dest = staging.join(&relative);
+            if entry.is_dir() {
+                fs::create_dir_all(&dest)
+                    .chain_err(|| format!("failed to create {}", dest.to_string_lossy()))?;
+                continue;
+            }
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)
+                    .chain_err(|| format!("failed to create {}", parent.to_string_lossy()))?;
+            }
+            let mut file = fs::File::create(&dest)
+                .chain_err(|| format!("failed to create {}", dest.to_string_lossy()))?;
+            io::copy(&mut entry, &mut file)
+                .chain_err(|| format!("failed to extract {}", relative.to_string_lossy()))?;
+        }
+        if exists {
+            Server::get(paths, name)?.remove()?;
+        }
+        if target.exists() {
+            fs::remove_dir_all(&target).chain_err(|| "failed to remove server directory")?;
+        }
+        fs::rename(&staging, &target).chain_err(|| "failed to move restored server into place")
+    })();
+    if let Err(e) = extracted {
+        let _ = fs::remove_dir_all(&staging);
+        return Err(e);
+    }
+
+    let server = Server { config };
+    server.save()?;
+    Ok((server, manifest))
+}
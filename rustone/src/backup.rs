@@ -0,0 +1,568 @@
+//! Deduplicating, optionally encrypted backup repositories.
+//!
+//! A repository is a directory with the following layout:
+//! ```text
+//! config.json          chunker and encryption parameters
+//! chunks/ab/ab12...    content-addressed file chunks
+//! snapshots/<id>.json  snapshot manifests (which files consist of which chunks)
+//! ```
+//! Files are split with a content-defined chunker, so a new snapshot of
+//! a world only stores the chunks that changed since the previous one.
+//! In encrypted repositories, chunks and snapshots are encrypted with a key
+//! derived from the passphrase, and only `config.json` is stored in plaintext.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::Write,
+    path::{Component, Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use error_chain::bail;
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::errors::*;
+
+pub mod chunker;
+pub mod crypto;
+
+use chunker::{Chunker, ChunkerParams};
+use crypto::{EncryptionParams, Keys};
+
+const REPOSITORY_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct RepositoryConfig {
+    version: u32,
+    chunker: ChunkerParams,
+    encryption: Option<EncryptionParams>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum EntryKind {
+    Dir,
+    File {
+        size: u64,
+        mtime_ns: u64,
+        chunks: Vec<String>,
+    },
+}
+
+/// A file or directory in a snapshot. Paths are relative to the backed up
+/// directory and always use `/` as separator.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Entry {
+    pub path: String,
+    #[serde(flatten)]
+    pub kind: EntryKind,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Snapshot {
+    pub id: String,
+    /// Name of the server this snapshot was taken of
+    pub server: String,
+    /// Seconds since the UNIX epoch
+    pub time: u64,
+    /// The snapshot unchanged files were taken from, if any
+    pub parent: Option<String>,
+    pub entries: Vec<Entry>,
+}
+
+impl Snapshot {
+    /// Sum of the sizes of all files in the snapshot
+    pub fn size(&self) -> u64 {
+        self.entries
+            .iter()
+            .map(|e| match e.kind {
+                EntryKind::File { size, .. } => size,
+                EntryKind::Dir => 0,
+            })
+            .sum()
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct BackupStats {
+    pub files: usize,
+    /// Files taken over from the parent snapshot without reading them
+    pub unchanged_files: usize,
+    pub new_chunks: usize,
+    pub new_bytes: u64,
+    pub total_bytes: u64,
+}
+
+#[derive(Default, Debug)]
+pub struct PruneStats {
+    pub removed_snapshots: usize,
+    pub removed_chunks: usize,
+}
+
+#[derive(Default, Debug)]
+pub struct CheckReport {
+    pub snapshots: usize,
+    pub chunks: usize,
+    /// Human-readable descriptions of every problem found
+    pub errors: Vec<String>,
+}
+
+pub struct Repository {
+    path: PathBuf,
+    config: RepositoryConfig,
+    keys: Option<Keys>,
+    chunker: Chunker,
+}
+
+fn unix_time(time: SystemTime) -> Duration {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let dir = path.parent().unwrap();
+    fs::create_dir_all(dir)
+        .chain_err(|| format!("failed to create directory {}", dir.to_string_lossy()))?;
+    let tmp = path.with_extension("tmp");
+    let mut file = fs::File::create(&tmp)
+        .chain_err(|| format!("failed to create {}", tmp.to_string_lossy()))?;
+    file.write_all(data)
+        .and_then(|_| file.sync_all())
+        .chain_err(|| format!("failed to write {}", tmp.to_string_lossy()))?;
+    fs::rename(&tmp, path).chain_err(|| format!("failed to rename {}", tmp.to_string_lossy()))
+}
+
+impl Repository {
+    /// Check whether `path` contains a repository.
+    pub fn exists(path: &Path) -> bool {
+        path.join("config.json").is_file()
+    }
+
+    /// Create a new repository in `path`. If `passphrase` is given, the
+    /// repository is encrypted with it.
+    pub fn init(path: &Path, passphrase: Option<&str>) -> Result<Self> {
+        if Self::exists(path) {
+            bail!(ErrorKind::AlreadyExists(format!(
+                "a backup repository at {}",
+                path.to_string_lossy()
+            )));
+        }
+        let (keys, encryption) = match passphrase {
+            Some(passphrase) => {
+                let (keys, params) = Keys::create(passphrase)?;
+                (Some(keys), Some(params))
+            }
+            None => (None, None),
+        };
+        let config = RepositoryConfig {
+            version: REPOSITORY_VERSION,
+            chunker: ChunkerParams::default(),
+            encryption,
+        };
+        for dir in &["chunks", "snapshots"] {
+            fs::create_dir_all(path.join(dir))
+                .chain_err(|| format!("failed to create repository directory '{}'", dir))?;
+        }
+        write_atomic(
+            &path.join("config.json"),
+            &serde_json::to_vec_pretty(&config)
+                .chain_err(|| "failed to serialize repository config")?,
+        )?;
+        Ok(Self::with_config(path, config, keys))
+    }
+
+    /// Open an existing repository. Encrypted repositories need the passphrase
+    /// they were created with.
+    pub fn open(path: &Path, passphrase: Option<&str>) -> Result<Self> {
+        let text = fs::read(path.join("config.json")).chain_err(|| {
+            format!(
+                "no backup repository found at {} (not initialized?)",
+                path.to_string_lossy()
+            )
+        })?;
+        let config: RepositoryConfig =
+            serde_json::from_slice(&text).chain_err(|| "failed to parse repository config")?;
+        if config.version != REPOSITORY_VERSION {
+            bail!("unsupported backup repository version {}", config.version);
+        }
+        let keys = match (&config.encryption, passphrase) {
+            (Some(params), Some(passphrase)) => Some(Keys::open(passphrase, params)?),
+            (Some(_), None) => bail!("the backup repository is encrypted, a passphrase is needed"),
+            (None, _) => None,
+        };
+        Ok(Self::with_config(path, config, keys))
+    }
+
+    fn with_config(path: &Path, config: RepositoryConfig, keys: Option<Keys>) -> Self {
+        let chunker = Chunker::new(config.chunker, keys.as_ref().map_or(0, Keys::chunker_seed));
+        Self {
+            path: path.to_owned(),
+            config,
+            keys,
+            chunker,
+        }
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.config.encryption.is_some()
+    }
+
+    fn chunk_path(&self, id: &str) -> PathBuf {
+        self.path.join("chunks").join(&id[..2]).join(id)
+    }
+
+    fn snapshot_path(&self, id: &str) -> PathBuf {
+        self.path.join("snapshots").join(format!("{}.json", id))
+    }
+
+    fn write_blob(&self, path: &Path, data: &[u8]) -> Result<()> {
+        match &self.keys {
+            Some(keys) => write_atomic(path, &keys.encrypt(data)?),
+            None => write_atomic(path, data),
+        }
+    }
+
+    fn read_blob(&self, path: &Path) -> Result<Vec<u8>> {
+        let data =
+            fs::read(path).chain_err(|| format!("failed to read {}", path.to_string_lossy()))?;
+        match &self.keys {
+            Some(keys) => keys
+                .decrypt(&data)
+                .chain_err(|| format!("failed to decrypt {}", path.to_string_lossy())),
+            None => Ok(data),
+        }
+    }
+
+    fn read_chunk(&self, id: &str) -> Result<Vec<u8>> {
+        let data = self.read_blob(&self.chunk_path(id))?;
+        if crypto::chunk_id(self.keys.as_ref(), &data) != id {
+            bail!("chunk {} is corrupted", id);
+        }
+        Ok(data)
+    }
+
+    /// List all snapshots, oldest first. Unreadable ones are skipped with a
+    /// warning, [`Repository::check`] reports them.
+    pub fn snapshots(&self) -> Result<Vec<Snapshot>> {
+        self.read_snapshots(true)
+    }
+
+    /// All snapshots, oldest first, failing on unreadable ones unless
+    /// `skip_bad`.
+    fn read_snapshots(&self, skip_bad: bool) -> Result<Vec<Snapshot>> {
+        let dir = fs::read_dir(self.path.join("snapshots"))
+            .chain_err(|| "failed to list snapshots directory")?;
+        let mut snapshots = vec![];
+        for entry in dir {
+            let path = entry.chain_err(|| "failed to read directory entry")?.path();
+            if path.extension().is_none_or(|e| e != "json") {
+                continue;
+            }
+            match self.load_snapshot(&path) {
+                Ok(snapshot) => snapshots.push(snapshot),
+                Err(e) if skip_bad => warn!(error = %e, "skipping unreadable snapshot"),
+                Err(e) => return Err(e),
+            }
+        }
+        snapshots.sort_by(|a, b| a.time.cmp(&b.time).then_with(|| a.id.cmp(&b.id)));
+        Ok(snapshots)
+    }
+
+    fn load_snapshot(&self, path: &Path) -> Result<Snapshot> {
+        serde_json::from_slice(&self.read_blob(path)?)
+            .chain_err(|| format!("failed to parse snapshot {}", path.to_string_lossy()))
+    }
+
+    /// Find a snapshot by a (unique) prefix of its ID.
+    pub fn find_snapshot(&self, prefix: &str) -> Result<Snapshot> {
+        let mut found = self
+            .snapshots()?
+            .into_iter()
+            .filter(|s| s.id.starts_with(prefix));
+        match (found.next(), found.next()) {
+            (Some(snapshot), None) => Ok(snapshot),
+            (None, _) => bail!(ErrorKind::NotFound(format!("snapshot {}", prefix))),
+            (Some(_), Some(_)) => bail!("snapshot ID {} is ambiguous", prefix),
+        }
+    }
+
+    /// Take a snapshot of `source`, and tag it with `server`.
+    ///
+    /// Files whose size and modification time match the last snapshot of
+    /// the same server are not read again.
+    pub fn backup(&self, server: &str, source: &Path) -> Result<(Snapshot, BackupStats)> {
+        let parent = self.snapshots()?.into_iter().rfind(|s| s.server == server);
+        let previous: HashMap<&str, &EntryKind> = parent
+            .iter()
+            .flat_map(|p| p.entries.iter().map(|e| (e.path.as_str(), &e.kind)))
+            .collect();
+
+        let mut stats = BackupStats::default();
+        let mut entries = vec![];
+        let mut known_chunks = HashSet::new();
+        let mut stack = vec![PathBuf::new()];
+        while let Some(relative) = stack.pop() {
+            let mut children = fs::read_dir(source.join(&relative))
+                .chain_err(|| {
+                    format!(
+                        "failed to list {}",
+                        source.join(&relative).to_string_lossy()
+                    )
+                })?
+                .collect::<std::io::Result<Vec<_>>>()
+                .chain_err(|| "failed to read directory entry")?;
+            children.sort_by_key(|e| e.file_name());
+            // Pushed in reverse, so the directories are walked in order
+            for child in children.into_iter().rev() {
+                let child_relative = relative.join(child.file_name());
+                let path = child_relative.to_string_lossy().replace('\\', "/");
+                let meta = child
+                    .metadata()
+                    .chain_err(|| format!("failed to stat {}", path))?;
+                if meta.is_dir() {
+                    entries.push(Entry {
+                        path,
+                        kind: EntryKind::Dir,
+                    });
+                    stack.push(child_relative);
+                } else if meta.is_file() {
+                    let size = meta.len();
+                    let mtime_ns = unix_time(
+                        meta.modified()
+                            .chain_err(|| format!("failed to get mtime of {}", path))?,
+                    )
+                    .as_nanos() as u64;
+                    stats.files += 1;
+                    stats.total_bytes += size;
+
+                    let chunks = match previous.get(path.as_str()) {
+                        Some(EntryKind::File {
+                            size: old_size,
+                            mtime_ns: old_mtime,
+                            chunks,
+                        }) if *old_size == size && *old_mtime == mtime_ns => {
+                            stats.unchanged_files += 1;
+                            chunks.clone()
+                        }
+                        _ => self.store_file(&child.path(), &mut known_chunks, &mut stats)?,
+                    };
+                    entries.push(Entry {
+                        path,
+                        kind: EntryKind::File {
+                            size,
+                            mtime_ns,
+                            chunks,
+                        },
+                    });
+                }
+                // Anything else (symlinks, sockets...) is skipped
+            }
+        }
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let mut id = [0u8; 8];
+        OsRng.fill_bytes(&mut id);
+        let snapshot = Snapshot {
+            id: hex::encode(id),
+            server: server.to_owned(),
+            time: unix_time(SystemTime::now()).as_secs(),
+            parent: parent.map(|p| p.id),
+            entries,
+        };
+        self.write_blob(
+            &self.snapshot_path(&snapshot.id),
+            &serde_json::to_vec(&snapshot).chain_err(|| "failed to serialize snapshot")?,
+        )?;
+        Ok((snapshot, stats))
+    }
+
+    fn store_file(
+        &self,
+        path: &Path,
+        known_chunks: &mut HashSet<String>,
+        stats: &mut BackupStats,
+    ) -> Result<Vec<String>> {
+        let file = fs::File::open(path)
+            .chain_err(|| format!("failed to open {}", path.to_string_lossy()))?;
+        let mut chunks = vec![];
+        self.chunker.chunk_reader(file, |data| {
+            let id = crypto::chunk_id(self.keys.as_ref(), data);
+            if !known_chunks.contains(&id) {
+                let chunk_path = self.chunk_path(&id);
+                if !chunk_path.exists() {
+                    self.write_blob(&chunk_path, data)?;
+                    stats.new_chunks += 1;
+                    stats.new_bytes += data.len() as u64;
+                }
+                known_chunks.insert(id.clone());
+            }
+            chunks.push(id);
+            Ok(())
+        })?;
+        Ok(chunks)
+    }
+
+    /// Restore `snapshot` into `target`, which must not exist or be empty.
+    pub fn restore(&self, snapshot: &Snapshot, target: &Path) -> Result<()> {
+        if target.exists()
+            && fs::read_dir(target)
+                .chain_err(|| "failed to list restore target")?
+                .next()
+                .is_some()
+        {
+            bail!("restore target {} is not empty", target.to_string_lossy());
+        }
+        // Manifests can't be trusted to stay inside the target
+        if let Some(entry) = snapshot.entries.iter().find(|e| {
+            e.path.is_empty()
+                || !Path::new(&e.path)
+                    .components()
+                    .all(|c| matches!(c, Component::Normal(_)))
+        }) {
+            bail!(
+                "snapshot {} has an invalid path '{}'",
+                snapshot.id,
+                entry.path
+            );
+        }
+        fs::create_dir_all(target).chain_err(|| "failed to create restore target")?;
+        for entry in &snapshot.entries {
+            let path = target.join(&entry.path);
+            match &entry.kind {
+                EntryKind::Dir => fs::create_dir_all(&path)
+                    .chain_err(|| format!("failed to create directory {}", entry.path))?,
+                EntryKind::File {
+                    mtime_ns, chunks, ..
+                } => {
+                    if let Some(parent) = path.parent() {
+                        fs::create_dir_all(parent).chain_err(|| {
+                            format!("failed to create directory {}", parent.to_string_lossy())
+                        })?;
+                    }
+                    let mut file = fs::File::create(&path)
+                        .chain_err(|| format!("failed to create {}", entry.path))?;
+                    for id in chunks {
+                        file.write_all(&self.read_chunk(id)?)
+                            .chain_err(|| format!("failed to write {}", entry.path))?;
+                    }
+                    // Keep the mtime, so the next backup can skip the file
+                    file.set_modified(UNIX_EPOCH + Duration::from_nanos(*mtime_ns))
+                        .chain_err(|| format!("failed to set mtime of {}", entry.path))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Keep the `keep_last` newest snapshots of every server, remove the
+    /// rest, and delete the chunks no snapshot references anymore.
+    ///
+    /// Must not run while a backup is in progress, because it would remove
+    /// the chunks of the unfinished snapshot. Fails if any snapshot is
+    /// unreadable.
+    pub fn prune(&self, keep_last: usize) -> Result<PruneStats> {
+        let mut stats = PruneStats::default();
+        let mut by_server: HashMap<String, Vec<Snapshot>> = HashMap::new();
+        // The chunks of an unreadable snapshot are unknown, they could be
+        // removed with it skipped
+        for snapshot in self.read_snapshots(false)? {
+            by_server
+                .entry(snapshot.server.clone())
+                .or_default()
+                .push(snapshot);
+        }
+
+        let mut referenced = HashSet::new();
+        for snapshots in by_server.values() {
+            let split = snapshots.len().saturating_sub(keep_last);
+            for snapshot in &snapshots[..split] {
+                fs::remove_file(self.snapshot_path(&snapshot.id))
+                    .chain_err(|| format!("failed to remove snapshot {}", snapshot.id))?;
+                stats.removed_snapshots += 1;
+            }
+            for snapshot in &snapshots[split..] {
+                referenced.extend(snapshot.entries.iter().flat_map(|e| match &e.kind {
+                    EntryKind::File { chunks, .. } => chunks.clone(),
+                    EntryKind::Dir => vec![],
+                }));
+            }
+        }
+
+        for id in self.chunk_ids()? {
+            if !referenced.contains(&id) {
+                fs::remove_file(self.chunk_path(&id))
+                    .chain_err(|| format!("failed to remove chunk {}", id))?;
+                stats.removed_chunks += 1;
+            }
+        }
+        Ok(stats)
+    }
+
+    fn chunk_ids(&self) -> Result<Vec<String>> {
+        let mut ids = vec![];
+        for prefix in
+            fs::read_dir(self.path.join("chunks")).chain_err(|| "failed to list chunks")?
+        {
+            let prefix = prefix.chain_err(|| "failed to read directory entry")?;
+            for chunk in fs::read_dir(prefix.path()).chain_err(|| "failed to list chunks")? {
+                let name = chunk
+                    .chain_err(|| "failed to read directory entry")?
+                    .file_name()
+                    .to_string_lossy()
+                    .into_owned();
+                // Leftovers of interrupted writes are not chunks
+                if !name.ends_with(".tmp") {
+                    ids.push(name);
+                }
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Verify that every chunk referenced by a snapshot exists. With
+    /// `read_data`, every chunk is also read back and its hash verified.
+    pub fn check(&self, read_data: bool) -> Result<CheckReport> {
+        let mut report = CheckReport::default();
+        let mut referenced = HashSet::new();
+        for entry in fs::read_dir(self.path.join("snapshots"))
+            .chain_err(|| "failed to list snapshots directory")?
+        {
+            let path = entry.chain_err(|| "failed to read directory entry")?.path();
+            // Leftovers of interrupted writes are not snapshots
+            if path.extension().is_none_or(|e| e != "json") {
+                continue;
+            }
+            match self.load_snapshot(&path) {
+                Ok(snapshot) => {
+                    report.snapshots += 1;
+                    for entry in snapshot.entries {
+                        if let EntryKind::File { chunks, .. } = entry.kind {
+                            referenced.extend(chunks);
+                        }
+                    }
+                }
+                Err(e) => report.errors.push(e.to_string()),
+            }
+        }
+
+        let existing: HashSet<String> = self.chunk_ids()?.into_iter().collect();
+        report.chunks = existing.len();
+        let mut missing: Vec<_> = referenced.difference(&existing).collect();
+        missing.sort();
+        for id in missing {
+            report.errors.push(format!("chunk {} is missing", id));
+        }
+        if read_data {
+            let mut present: Vec<_> = referenced.intersection(&existing).collect();
+            present.sort();
+            for id in present {
+                if let Err(e) = self.read_chunk(id) {
+                    report.errors.push(e.to_string());
+                }
+            }
+        }
+        Ok(report)
+    }
+}
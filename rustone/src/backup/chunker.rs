@@ -0,0 +1,131 @@
+use std::io::Read;
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::*;
+
+/// Chunk size limits of a repository, in bytes.
+///
+/// These are stored in the repository config, because changing them would
+/// move every chunk boundary and ruin deduplication against older snapshots.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct ChunkerParams {
+    pub min_size: usize,
+    pub avg_size: usize,
+    pub max_size: usize,
+}
+
+impl Default for ChunkerParams {
+    fn default() -> Self {
+        // Region files are a few MiBs at most, and a single Minecraft chunk
+        // usually takes a couple of 4 KiB sectors - small chunks dedup better
+        Self {
+            min_size: 32 * 1024,
+            avg_size: 128 * 1024,
+            max_size: 1024 * 1024,
+        }
+    }
+}
+
+/// Content-defined chunker, based on FastCDC's gear hash and normalized
+/// chunking.
+///
+/// Cut points only depend on the bytes around them, so inserting or changing
+/// data in a file only changes the chunks around the modification.
+pub struct Chunker {
+    gear: [u64; 256],
+    params: ChunkerParams,
+    mask_small: u64,
+    mask_large: u64,
+}
+
+impl Chunker {
+    /// Create a chunker. Different seeds give different cut points, which
+    /// encrypted repositories use to avoid leaking file contents through
+    /// chunk sizes.
+    pub fn new(params: ChunkerParams, seed: u64) -> Self {
+        let mut gear = [0u64; 256];
+        let mut state = seed;
+        for entry in gear.iter_mut() {
+            // splitmix64
+            state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            *entry = z ^ (z >> 31);
+        }
+        let bits = (params.avg_size.max(2) as f64).log2().round() as u32;
+        Self {
+            gear,
+            params,
+            // Harder to match before the average size, easier after it
+            mask_small: !0u64 << (64 - (bits + 1).min(63)),
+            mask_large: !0u64 << (64 - (bits - 1).max(1)),
+        }
+    }
+
+    /// Return the length of the first chunk in `data`.
+    ///
+    /// # Examples
+    /// ```
+    /// use rustone::backup::chunker::{Chunker, ChunkerParams};
+    /// let chunker = Chunker::new(ChunkerParams { min_size: 16, avg_size: 64, max_size: 256 }, 0);
+    /// assert_eq!(chunker.cut(&[0u8; 10]), 10);
+    /// assert!(chunker.cut(&[7u8; 1000]) <= 256);
+    /// ```
+    pub fn cut(&self, data: &[u8]) -> usize {
+        let ChunkerParams {
+            min_size,
+            avg_size,
+            max_size,
+        } = self.params;
+        if data.len() <= min_size {
+            return data.len();
+        }
+        let end = data.len().min(max_size);
+        let normal = avg_size.min(end);
+        let mut hash = 0u64;
+        for (i, byte) in data.iter().enumerate().take(end).skip(min_size) {
+            hash = (hash << 1).wrapping_add(self.gear[*byte as usize]);
+            let mask = if i < normal {
+                self.mask_small
+            } else {
+                self.mask_large
+            };
+            if hash & mask == 0 {
+                return i + 1;
+            }
+        }
+        end
+    }
+
+    /// Split everything `reader` returns into chunks, calling `f` for each.
+    pub fn chunk_reader<R, F>(&self, mut reader: R, mut f: F) -> Result<()>
+    where
+        R: Read,
+        F: FnMut(&[u8]) -> Result<()>,
+    {
+        let max_size = self.params.max_size;
+        let mut buffer = vec![0u8; max_size * 2];
+        let mut filled = 0;
+        let mut eof = false;
+        loop {
+            while !eof && filled < max_size {
+                let read = reader
+                    .read(&mut buffer[filled..])
+                    .chain_err(|| "failed to read file for chunking")?;
+                if read == 0 {
+                    eof = true;
+                }
+                filled += read;
+            }
+            if filled == 0 {
+                return Ok(());
+            }
+            let len = self.cut(&buffer[..filled]);
+            f(&buffer[..len])?;
+            buffer.copy_within(len..filled, 0);
+            filled -= len;
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use error_chain::bail;
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::errors::*;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const NONCE_LEN: usize = 12;
+const KEY_CHECK: &[u8] = b"rustone backup repository";
+
+/// Key derivation parameters, stored in the repository config.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EncryptionParams {
+    pub salt: String,
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+    /// A known plaintext encrypted with the derived key, used to tell
+    /// a wrong passphrase apart from a corrupted repository.
+    pub key_check: String,
+}
+
+/// Keys derived from the repository passphrase.
+pub struct Keys {
+    cipher: ChaCha20Poly1305,
+    id_key: [u8; 32],
+    chunker_seed: u64,
+}
+
+fn subkey(master: &[u8], purpose: &str) -> [u8; 32] {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(master).expect("HMAC accepts any key length");
+    mac.update(purpose.as_bytes());
+    mac.finalize().into_bytes().into()
+}
+
+impl Keys {
+    fn derive(passphrase: &str, params: &EncryptionParams) -> Result<Self> {
+        let salt = hex::decode(&params.salt).chain_err(|| "invalid salt in repository config")?;
+        let argon = Argon2::new(
+            argon2::Algorithm::Argon2id,
+            argon2::Version::V0x13,
+            argon2::Params::new(
+                params.memory_kib,
+                params.iterations,
+                params.parallelism,
+                None,
+            )
+            .map_err(|e| format!("invalid key derivation parameters: {}", e))?,
+        );
+        let mut master = [0u8; 32];
+        argon
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut master)
+            .map_err(|e| format!("failed to derive key: {}", e))?;
+
+        let seed = subkey(&master, "chunker");
+        let mut seed_bytes = [0u8; 8];
+        seed_bytes.copy_from_slice(&seed[..8]);
+        Ok(Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&subkey(&master, "encryption"))),
+            id_key: subkey(&master, "chunk-id"),
+            chunker_seed: u64::from_le_bytes(seed_bytes),
+        })
+    }
+
+    /// Generate a new salt and derive keys from `passphrase`.
+    pub fn create(passphrase: &str) -> Result<(Self, EncryptionParams)> {
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        let mut params = EncryptionParams {
+            salt: hex::encode(salt),
+            memory_kib: 64 * 1024,
+            iterations: 3,
+            parallelism: 1,
+            key_check: String::new(),
+        };
+        let keys = Self::derive(passphrase, &params)?;
+        params.key_check = hex::encode(keys.encrypt(KEY_CHECK)?);
+        Ok((keys, params))
+    }
+
+    /// Derive keys from `passphrase` and verify them against the repository.
+    pub fn open(passphrase: &str, params: &EncryptionParams) -> Result<Self> {
+        let keys = Self::derive(passphrase, params)?;
+        let check = hex::decode(&params.key_check).chain_err(|| "invalid key check value")?;
+        match keys.decrypt(&check) {
+            Ok(plain) if plain == KEY_CHECK => Ok(keys),
+            _ => bail!("wrong backup passphrase"),
+        }
+    }
+
+    pub fn chunker_seed(&self) -> u64 {
+        self.chunker_seed
+    }
+
+    /// Encrypt `data`, prepending the random nonce.
+    pub fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut out = nonce.to_vec();
+        out.extend(
+            self.cipher
+                .encrypt(&nonce, data)
+                .map_err(|_| Error::from("failed to encrypt data"))?,
+        );
+        Ok(out)
+    }
+
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        if data.len() < NONCE_LEN {
+            bail!("encrypted blob is truncated");
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| "failed to decrypt data (corrupted or tampered)".into())
+    }
+}
+
+/// Compute the ID of a chunk. Encrypted repositories use a keyed hash, so
+/// chunk IDs do not reveal whether a repository contains some known file.
+pub fn chunk_id(keys: Option<&Keys>, data: &[u8]) -> String {
+    match keys {
+        Some(keys) => {
+            let mut mac = <HmacSha256 as Mac>::new_from_slice(&keys.id_key)
+                .expect("HMAC accepts any key length");
+            mac.update(data);
+            hex::encode(mac.finalize().into_bytes())
+        }
+        None => hex::encode(Sha256::digest(data)),
+    }
+}
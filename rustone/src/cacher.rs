@@ -1,137 +1,411 @@
-use std::{collections::HashMap, fs, io::Write, path::PathBuf};
+//! The store of downloaded server jars.
+//!
+//! Jars are kept by the SHA-256 of their contents, in `blobs/`, so every
+//! build of a version can be cached side by side, and identical jars are
+//! stored once. `index.toml` maps each build to its blob, with where and
+//! when it was downloaded.
 
-use crate::config::MinecraftVersion;
-use crate::{config::ServerVersion, errors::*, global::project_dirs};
-use fs::File;
-use lazy_static::lazy_static;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Display,
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+    str::FromStr,
+    time::SystemTime,
+};
+
+use error_chain::bail;
+use rand::{rngs::OsRng, RngCore};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use tracing::{debug, info, warn};
 
-/// Structure representing a cached server jar file
-pub struct CachedJar {
-    pub version: ServerVersion,
-}
+use crate::{
+    archive::file_sha256,
+    config::{MinecraftVersion, ServerConfig, ServerVersion},
+    errors::*,
+    paths::Paths,
+    server_kinds::ServerKind,
+};
 
-#[derive(PartialEq, Hash, Eq, Clone)]
-pub struct CachedJarMetaKey {
-    pub version: MinecraftVersion,
+/// A build of a server kind, written like `paper@1.20.4-496`.
+#[derive(Debug, PartialEq, Hash, Eq, Clone, PartialOrd, Ord)]
+pub struct JarKey {
     pub kind: String,
+    pub version: MinecraftVersion,
+    pub build: u32,
+}
+
+impl JarKey {
+    /// The jar `config` runs, if it has a build yet.
+    pub fn of(config: &ServerConfig) -> Option<Self> {
+        Some(Self {
+            kind: config.kind.clone(),
+            version: config.version.minecraft,
+            build: config.version.patch?,
+        })
+    }
+
+    pub fn server_version(&self) -> ServerVersion {
+        ServerVersion {
+            minecraft: self.version,
+            patch: Some(self.build),
+        }
+    }
+}
+
+impl Display for JarKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}@{}-{}", self.kind, self.version, self.build)
+    }
 }
 
-impl CachedJarMetaKey {
-    pub fn path(&self) -> PathBuf {
-        project_dirs()
-            .unwrap()
-            .cache_dir()
-            .join(format!("{}-{}.jar", self.kind, self.version))
+impl FromStr for JarKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (kind, version) = s
+            .split_once('@')
+            .ok_or_else(|| format!("jar key '{}' must contain a '@'", s))?;
+        let version = ServerVersion::new(version)?;
+        match version.patch {
+            Some(build) => Ok(Self {
+                kind: kind.to_owned(),
+                version: version.minecraft,
+                build,
+            }),
+            None => bail!("jar key '{}' has no build", s),
+        }
     }
 }
 
-impl Serialize for CachedJarMetaKey {
+impl Serialize for JarKey {
     fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        serializer.serialize_str(&format!("{}@{}", self.kind, self.version))
+        serializer.serialize_str(&self.to_string())
     }
 }
 
-impl<'de> Deserialize<'de> for CachedJarMetaKey {
+impl<'de> Deserialize<'de> for JarKey {
     fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
         use serde::de::Error;
-        let str = <&str>::deserialize(deserializer)?;
-        let mut parts = str.splitn(2, '@');
-        Ok(Self {
-            kind: parts.next().unwrap().to_owned(),
-            version: parts
-                .next()
-                .ok_or_else(|| D::Error::custom("cache key must contain a '@'"))?
-                .parse()
-                .map_err(D::Error::custom)?,
-        })
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
     }
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
-pub struct CacheMeta {
-    pub jars: HashMap<CachedJarMetaKey, u32>,
+/// Where a cached build is stored and where it came from.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct JarRecord {
+    /// The SHA-256 of the jar, which names its blob
+    pub sha256: String,
+    /// Where it was downloaded from, unknown for jars cached by older
+    /// versions of rustone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// When it was downloaded, in seconds since the Unix epoch
+    pub downloaded: u64,
+    /// The SHA-256 upstream published for the build, if any. The download
+    /// was checked against it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream_sha256: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct CacheIndex {
+    #[serde(default)]
+    pub jars: BTreeMap<JarKey, JarRecord>,
 }
 
-lazy_static! {
-    static ref CACHE_META_PATH: PathBuf = project_dirs().unwrap().cache_dir().join("cache.toml");
+fn blob_path(paths: &Paths, sha256: &str) -> PathBuf {
+    paths.jar_blobs().join(format!("{}.jar", sha256))
 }
 
-pub fn get_cached(key: CachedJarMetaKey) -> Result<Option<CachedJar>> {
-    let meta = read_cache_meta()?.jars;
-    let patch = match meta.get(&key) {
-        Some(p) => *p,
-        None => return Ok(None),
+/// The cached jar of `key`, if it is in the store.
+pub fn get_cached(paths: &Paths, key: &JarKey) -> Result<Option<(PathBuf, JarRecord)>> {
+    let record = match read_index(paths)?.jars.remove(key) {
+        Some(record) => record,
+        None => {
+            debug!(%key, "jar not cached");
+            return Ok(None);
+        }
     };
-    let path = key.path();
+    let path = blob_path(paths, &record.sha256);
+    Ok(if path.is_file() {
+        debug!(%key, sha256 = %record.sha256, "jar cached");
+        Some((path, record))
+    } else {
+        warn!(%key, sha256 = %record.sha256, "indexed jar is missing its blob");
+        None
+    })
+}
 
-    if !(path.exists() && path.is_file()) {
-        return Ok(None);
+/// The jar `config` is launched with. Fails if it is not cached yet.
+pub fn server_jar(config: &ServerConfig) -> Result<PathBuf> {
+    let key = JarKey::of(config)
+        .ok_or_else(|| format!("{} has no build yet, start it to download one", config.name))?;
+    match get_cached(&config.paths, &key)? {
+        Some((path, _)) => Ok(path),
+        None => bail!(ErrorKind::NotFound(format!("cached jar {}", key))),
     }
-    Ok(Some(CachedJar {
-        version: ServerVersion {
-            minecraft: key.version,
-            patch: Some(patch),
-        },
-    }))
 }
 
-pub fn get_cached_patch(key: &CachedJarMetaKey) -> Result<Option<u32>> {
-    Ok(read_cache_meta()?.jars.get(&key).copied())
+/// A jar being written into the store. It is hashed while it is written,
+/// and only added to the index by [`JarWriter::finish`].
+pub struct JarWriter {
+    paths: Paths,
+    key: JarKey,
+    url: Option<String>,
+    upstream_sha256: Option<String>,
+    partial: PathBuf,
+    file: fs::File,
+    hasher: Sha256,
 }
 
-pub fn cache_jar(
-    version: MinecraftVersion,
-    patch: u32,
-    kind: String,
-) -> Result<(fs::File, CachedJar)> {
-    let mut meta = read_cache_meta()?.jars;
-    let key = CachedJarMetaKey { version, kind };
-    if let Some(meta) = meta.get_mut(&key) {
-        *meta = patch;
-    } else {
-        meta.insert(key.clone(), patch);
+impl Write for JarWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
     }
-    write_cache_meta(&CacheMeta { jars: meta })?;
-    Ok((
-        fs::File::create(key.path()).chain_err(|| "failed to cache file")?,
-        CachedJar {
-            version: ServerVersion {
-                minecraft: version,
-                patch: Some(patch),
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl JarWriter {
+    /// Move the jar into place and record it. Fails, leaving the store as
+    /// it was, if it does not match the checksum upstream published.
+    pub fn finish(mut self) -> Result<PathBuf> {
+        self.file
+            .flush()
+            .chain_err(|| "failed to write into jar file")?;
+        let sha256 = hex::encode(self.hasher.finalize_reset());
+        if let Some(expected) = &self.upstream_sha256 {
+            if !expected.eq_ignore_ascii_case(&sha256) {
+                warn!(key = %self.key, %sha256, %expected, "downloaded jar is corrupt");
+                let _ = fs::remove_file(&self.partial);
+                bail!(
+                    "downloaded jar {} is corrupt: its SHA-256 is {}, upstream published {}",
+                    self.key,
+                    sha256,
+                    expected
+                );
+            }
+        }
+        let path = blob_path(&self.paths, &sha256);
+        if path.is_file() {
+            let _ = fs::remove_file(&self.partial);
+        } else {
+            fs::rename(&self.partial, &path).chain_err(|| "failed to move jar into the cache")?;
+        }
+        let mut index = read_index(&self.paths)?;
+        index.jars.insert(
+            self.key.clone(),
+            JarRecord {
+                sha256,
+                url: self.url.clone(),
+                downloaded: now(),
+                upstream_sha256: self.upstream_sha256.clone(),
             },
+        );
+        write_index(&self.paths, &index)?;
+        info!(key = %self.key, path = %path.display(), "jar cached");
+        Ok(path)
+    }
+}
+
+impl Drop for JarWriter {
+    fn drop(&mut self) {
+        // Abandoned downloads leave nothing behind. After `finish` the file
+        // is gone already.
+        let _ = fs::remove_file(&self.partial);
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Start adding `key` to the store. `url` and `upstream_sha256` are the
+/// provenance of the jar.
+pub fn cache_jar(
+    paths: &Paths,
+    key: JarKey,
+    url: Option<String>,
+    upstream_sha256: Option<String>,
+) -> Result<JarWriter> {
+    fs::create_dir_all(paths.jar_blobs()).chain_err(|| "failed to create cache directory")?;
+    let mut name = [0u8; 8];
+    OsRng.fill_bytes(&mut name);
+    let partial = paths
+        .jar_blobs()
+        .join(format!("{}.part", hex::encode(name)));
+    let file = fs::File::create(&partial).chain_err(|| "failed to cache file")?;
+    Ok(JarWriter {
+        paths: paths.clone(),
+        key,
+        url,
+        upstream_sha256,
+        partial,
+        file,
+        hasher: Sha256::new(),
+    })
+}
+
+/// Request the jar of `version` (its latest build if it has none) and
+/// prepare its place in the store. The response is to be written into the
+/// writer, which is then finished. `version` is given the build.
+pub async fn download(
+    paths: &Paths,
+    kind: &ServerKind,
+    version: &mut ServerVersion,
+) -> Result<(reqwest::Response, JarWriter)> {
+    let response = kind.download_response(paths, version).await?;
+    let build = version
+        .patch
+        .ok_or_else(|| format!("{}: no build chosen for {}", kind, version))?;
+    let upstream_sha256 = kind
+        .get_build_sha256(paths, &version.minecraft, build)
+        .await
+        .chain_err(|| format!("{}: failed to get the checksum of {}", kind, version))?;
+    let writer = cache_jar(
+        paths,
+        JarKey {
+            kind: kind.to_string(),
+            version: version.minecraft,
+            build,
         },
-    ))
+        Some(response.url().to_string()),
+        upstream_sha256,
+    )?;
+    Ok((response, writer))
 }
 
-pub fn read_cache_meta() -> Result<CacheMeta> {
-    if !CACHE_META_PATH.exists() {
-        return Ok(CacheMeta {
-            jars: HashMap::new(),
-        });
+/// The cache metadata of rustone versions without the store: the build of
+/// `kind-version.jar` files, keyed by `kind@version`.
+#[derive(Deserialize)]
+struct LegacyCacheMeta {
+    jars: HashMap<String, u32>,
+}
+
+/// Move jars cached by older versions of rustone into the store.
+fn migrate_legacy(paths: &Paths) -> Result<CacheIndex> {
+    let path = paths.cache_meta();
+    let text = fs::read_to_string(&path).chain_err(|| "failed to read cache metadata")?;
+    let legacy: LegacyCacheMeta =
+        toml::from_str(&text).chain_err(|| "failed to parse cache metadata")?;
+    fs::create_dir_all(paths.jar_blobs()).chain_err(|| "failed to create cache directory")?;
+    info!(jars = legacy.jars.len(), "migrating legacy jar cache");
+    let mut index = CacheIndex::default();
+    for (key, build) in legacy.jars {
+        let (kind, version) = match key.split_once('@') {
+            Some(parts) => parts,
+            None => continue,
+        };
+        let jar = paths.cache.join(format!("{}-{}.jar", kind, version));
+        let version = match version.parse::<MinecraftVersion>() {
+            Ok(version) if jar.is_file() => version,
+            _ => continue,
+        };
+        let sha256 = file_sha256(&jar)?;
+        fs::rename(&jar, blob_path(paths, &sha256))
+            .chain_err(|| format!("failed to move {} into the cache", jar.to_string_lossy()))?;
+        let downloaded = fs::metadata(blob_path(paths, &sha256))
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+        index.jars.insert(
+            JarKey {
+                kind: kind.to_owned(),
+                version,
+                build,
+            },
+            JarRecord {
+                sha256,
+                url: None,
+                downloaded,
+                upstream_sha256: None,
+            },
+        );
+    }
+    write_index(paths, &index)?;
+    fs::remove_file(&path).chain_err(|| "failed to remove old cache metadata")?;
+    Ok(index)
+}
+
+pub fn read_index(paths: &Paths) -> Result<CacheIndex> {
+    let path = paths.jar_index();
+    if !path.exists() {
+        if paths.cache_meta().exists() {
+            return migrate_legacy(paths);
+        }
+        return Ok(CacheIndex::default());
     }
-    let text = fs::read_to_string(CACHE_META_PATH.clone().into_os_string())
-        .chain_err(|| "failed to read cache metadata")?;
-    toml::from_str(&text).chain_err(|| "failed to parse cache metadata")
+    let text = fs::read_to_string(path).chain_err(|| "failed to read cache index")?;
+    toml::from_str(&text).chain_err(|| "failed to parse cache index")
 }
 
-fn write_cache_meta(meta: &CacheMeta) -> Result<()> {
-    let ser = toml::to_string(meta).chain_err(|| "failed to serialize cache metadata")?;
-    let mut file = File::create(CACHE_META_PATH.clone().into_os_string())
-        .chain_err(|| "failed to create cache metadata file")?;
-    file.write_all(ser.as_bytes())
-        .chain_err(|| "failed to write into cache metadata file")?;
+fn write_index(paths: &Paths, index: &CacheIndex) -> Result<()> {
+    let ser = toml::to_string(index).chain_err(|| "failed to serialize cache index")?;
+    fs::create_dir_all(&paths.cache).chain_err(|| "failed to create cache directory")?;
+    fs::write(paths.jar_index(), ser).chain_err(|| "failed to write cache index")?;
     Ok(())
 }
 
-pub fn erase_cache() -> Result<()> {
-    write_cache_meta(&CacheMeta {
-        jars: HashMap::new(),
-    })
+/// Forget the builds not in `keep`, and delete the blobs no build uses
+/// anymore. Returns the forgotten builds.
+pub fn prune(paths: &Paths, keep: &HashSet<JarKey>) -> Result<Vec<JarKey>> {
+    let mut index = read_index(paths)?;
+    let removed: Vec<JarKey> = index
+        .jars
+        .keys()
+        .filter(|key| !keep.contains(*key))
+        .cloned()
+        .collect();
+    for key in &removed {
+        debug!(%key, "forgetting jar");
+        index.jars.remove(key);
+    }
+    write_index(paths, &index)?;
+    let used: HashSet<&str> = index.jars.values().map(|r| r.sha256.as_str()).collect();
+    if let Ok(entries) = fs::read_dir(paths.jar_blobs()) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let unused = path.extension().is_some_and(|e| e == "jar")
+                && path
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .is_some_and(|s| !used.contains(s));
+            if unused {
+                debug!(path = %path.display(), "deleting unused blob");
+                fs::remove_file(&path)
+                    .chain_err(|| format!("failed to delete {}", path.to_string_lossy()))?;
+            }
+        }
+    }
+    info!(
+        removed = removed.len(),
+        kept = index.jars.len(),
+        "pruned jar cache"
+    );
+    Ok(removed)
+}
+
+/// Remove every cached jar.
+pub fn erase_cache(paths: &Paths) -> Result<()> {
+    prune(paths, &HashSet::new())?;
+    Ok(())
 }
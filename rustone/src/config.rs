@@ -1,52 +1,161 @@
 extern crate toml;
-use std::{fmt::Display, num::ParseIntError, result, str::FromStr};
+use std::{fmt::Display, result, str::FromStr};
 
 use crate::errors::*;
+use error_chain::bail;
 use serde::{de::Visitor, Deserialize, Deserializer, Serialize, Serializer};
 
-/// Represents a Minecraft version
+mod migrations;
+
+/// How far along a release is.
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, PartialOrd, Ord)]
+pub enum Stage {
+    /// `1.20.5-pre1`
+    PreRelease(u32),
+    /// `1.20.5-rc1`
+    ReleaseCandidate(u32),
+    Release,
+}
+
+/// Represents a Minecraft version: a release (or one of its pre-releases
+/// and release candidates) or a weekly snapshot.
 /// # Examples
 /// ```
-/// use crate::rustone::config::MinecraftVersion;
+/// use crate::rustone::config::{MinecraftVersion, Stage};
 /// let ver = "1.12.2".parse::<MinecraftVersion>().unwrap();
-/// assert_eq!(ver, MinecraftVersion(1, 12, Some(2)))
+/// assert_eq!(ver, MinecraftVersion::release(1, 12, Some(2)));
+///
+/// let rc = "1.20.5-rc1".parse::<MinecraftVersion>().unwrap();
+/// assert_eq!(rc, MinecraftVersion::Release(1, 20, Some(5), Stage::ReleaseCandidate(1)));
+/// assert!("1.20.5-pre3".parse::<MinecraftVersion>().unwrap() < rc);
+/// assert!(rc < "1.20.5".parse().unwrap());
+///
+/// let snapshot = "23w31a".parse::<MinecraftVersion>().unwrap();
+/// assert_eq!(snapshot, MinecraftVersion::Snapshot(23, 31, 'a'));
+/// assert_eq!(snapshot.to_string(), "23w31a");
+/// assert!("1.20.1".parse::<MinecraftVersion>().unwrap() < snapshot);
+/// assert!(snapshot < "1.20.2-pre1".parse().unwrap());
+///
+/// // A missing patch is the same as patch 0
+/// assert_eq!("1.20".parse::<MinecraftVersion>().unwrap(), "1.20.0".parse().unwrap());
 /// ```
-#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
-pub struct MinecraftVersion(pub u32, pub u32, pub Option<u32>);
+#[derive(Debug, Clone, Copy)]
+pub enum MinecraftVersion {
+    /// Major, minor and patch numbers, the patch may be missing (for example: 1.17)
+    Release(u32, u32, Option<u32>, Stage),
+    /// Year, week and letter of a snapshot, like `23w31a`
+    Snapshot(u32, u32, char),
+}
 
-impl<'a> MinecraftVersion {
-    fn from_splitted<I>(splitted: &mut I) -> result::Result<Self, ParseIntError>
-    where
-        I: Iterator<Item = &'a str>,
-    {
-        Ok(Self(
-            splitted.next().unwrap().parse()?,
-            splitted.next().unwrap_or("").parse()?,
-            match splitted.next() {
-                Some(s) => {
-                    if let Some(s) = s.strip_prefix("pre") {
-                        s[3..].parse::<u32>()?;
-                        // Pre-release versions are treated like the minor release,
-                        // I didn't want to fiddle with handling them differently
-                        // (might change over time)
-                        // TODO ?
-                        None
-                    } else {
-                        Some(s.parse()?)
-                    }
-                }
-                None => None,
-            },
+/// Major, minor and patch numbers of a release.
+type ReleaseNumbers = (u32, u32, u32);
+
+/// The year and week of the first snapshot of releases, to place snapshots
+/// between them.
+/// See <https://minecraft.wiki/w/Java_Edition_version_history>.
+const FIRST_SNAPSHOTS: &[(u32, u32, u32, u32, u32)] = &[
+    (15, 31, 1, 9, 0),
+    (16, 20, 1, 10, 0),
+    (16, 32, 1, 11, 0),
+    (17, 6, 1, 12, 0),
+    (17, 31, 1, 12, 1),
+    (17, 43, 1, 13, 0),
+    (18, 30, 1, 13, 1),
+    (18, 43, 1, 14, 0),
+    (19, 34, 1, 15, 0),
+    (20, 6, 1, 16, 0),
+    (20, 27, 1, 16, 2),
+    (20, 45, 1, 17, 0),
+    (21, 37, 1, 18, 0),
+    (22, 3, 1, 18, 2),
+    (22, 11, 1, 19, 0),
+    (22, 24, 1, 19, 1),
+    (22, 42, 1, 19, 3),
+    (23, 3, 1, 19, 4),
+    (23, 12, 1, 20, 0),
+    (23, 31, 1, 20, 2),
+    (23, 40, 1, 20, 3),
+    (23, 51, 1, 20, 5),
+    (24, 18, 1, 21, 0),
+    (24, 33, 1, 21, 2),
+    (24, 44, 1, 21, 4),
+    (25, 2, 1, 21, 5),
+    (25, 15, 1, 21, 6),
+];
+
+impl MinecraftVersion {
+    /// The major and minor numbers of releases, like `(1, 20)` for all of
+    /// 1.20, 1.20.5-rc1 and 1.20.6.
+    pub fn minor(&self) -> Option<(u32, u32)> {
+        match *self {
+            Self::Release(major, minor, ..) => Some((major, minor)),
+            Self::Snapshot(..) => None,
+        }
+    }
+
+    /// A full release.
+    pub const fn release(major: u32, minor: u32, patch: Option<u32>) -> Self {
+        Self::Release(major, minor, patch, Stage::Release)
+    }
+
+    /// The release a snapshot leads to. Snapshots newer than the table are
+    /// taken to lead to its last release.
+    fn snapshot_target(year: u32, week: u32) -> ReleaseNumbers {
+        let known = FIRST_SNAPSHOTS
+            .iter()
+            .take_while(|(y, w, ..)| (*y, *w) <= (year, week))
+            .count();
+        let (.., major, minor, patch) = FIRST_SNAPSHOTS[known.max(1) - 1];
+        (major, minor, patch)
+    }
+
+    /// The key versions are ordered by: snapshots come right before the
+    /// pre-releases of the release they lead to.
+    fn order_key(&self) -> (ReleaseNumbers, Option<Stage>, (u32, u32, char)) {
+        match *self {
+            Self::Release(major, minor, patch, stage) => {
+                ((major, minor, patch.unwrap_or(0)), Some(stage), (0, 0, 'a'))
+            }
+            Self::Snapshot(year, week, letter) => (
+                Self::snapshot_target(year, week),
+                None,
+                (year, week, letter),
+            ),
+        }
+    }
+
+    fn parse_snapshot(s: &str) -> Option<Self> {
+        let (year, rest) = s.split_once('w')?;
+        let letter = rest.chars().last()?;
+        let week = &rest[..rest.len() - letter.len_utf8()];
+        if year.len() != 2 || week.len() != 2 || !letter.is_ascii_lowercase() {
+            return None;
+        }
+        Some(Self::Snapshot(
+            year.parse().ok()?,
+            week.parse().ok()?,
+            letter,
         ))
     }
 }
 
+impl PartialEq for MinecraftVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.order_key() == other.order_key()
+    }
+}
+
+impl Eq for MinecraftVersion {}
+
+impl std::hash::Hash for MinecraftVersion {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.order_key().hash(state)
+    }
+}
+
 impl Ord for MinecraftVersion {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.0
-            .cmp(&other.0)
-            .then_with(|| self.1.cmp(&other.1))
-            .then_with(|| self.2.unwrap_or(0).cmp(&other.2.unwrap_or(0)))
+        self.order_key().cmp(&other.order_key())
     }
 }
 
@@ -58,20 +167,60 @@ impl PartialOrd for MinecraftVersion {
 
 impl Display for MinecraftVersion {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}.{}", self.0, self.1)?;
-        if let Some(patch) = self.2 {
-            write!(f, ".{}", patch)?;
+        match *self {
+            Self::Release(major, minor, patch, stage) => {
+                write!(f, "{}.{}", major, minor)?;
+                if let Some(patch) = patch {
+                    write!(f, ".{}", patch)?;
+                }
+                match stage {
+                    Stage::PreRelease(n) => write!(f, "-pre{}", n),
+                    Stage::ReleaseCandidate(n) => write!(f, "-rc{}", n),
+                    Stage::Release => Ok(()),
+                }
+            }
+            Self::Snapshot(year, week, letter) => write!(f, "{:02}w{:02}{}", year, week, letter),
         }
-        Ok(())
     }
 }
 
 impl FromStr for MinecraftVersion {
-    type Err = ParseIntError;
+    type Err = Error;
 
     fn from_str(s: &str) -> result::Result<Self, Self::Err> {
-        let mut splitted = s.split(&['-', '.'][..]);
-        Self::from_splitted(&mut splitted)
+        if let Some(snapshot) = Self::parse_snapshot(s) {
+            return Ok(snapshot);
+        }
+        let invalid = || format!("invalid Minecraft version '{}'", s);
+        let (numbers, stage) = match s.split_once('-') {
+            Some((numbers, stage)) => (numbers, Some(stage)),
+            None => (s, None),
+        };
+        let mut numbers = numbers.split('.');
+        let major = numbers.next().unwrap_or("").parse().chain_err(invalid)?;
+        let minor = numbers.next().unwrap_or("").parse().chain_err(invalid)?;
+        let patch = numbers
+            .next()
+            .map(str::parse)
+            .transpose()
+            .chain_err(invalid)?;
+        if numbers.next().is_some() {
+            bail!(invalid());
+        }
+        let stage = match stage {
+            None => Stage::Release,
+            Some(stage) => {
+                if let Some(n) = stage.strip_prefix("pre") {
+                    Stage::PreRelease(n.parse().chain_err(invalid)?)
+                } else if let Some(n) = stage.strip_prefix("rc") {
+                    Stage::ReleaseCandidate(n.parse().chain_err(invalid)?)
+                } else {
+                    bail!("{} (unknown suffix '{}')", invalid(), stage);
+                }
+            }
+        };
+        let version = Self::Release(major, minor, patch, stage);
+        Ok(version)
     }
 }
 
@@ -93,7 +242,10 @@ impl<'de> Deserialize<'de> for MinecraftVersion {
         impl<'de> Visitor<'de> for MinecraftVersionVisitor {
             type Value = MinecraftVersion;
             fn expecting(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
-                write!(fmt, "Minecraft (semantic) version number")
+                write!(
+                    fmt,
+                    "Minecraft version (release, pre-release, release candidate or snapshot)"
+                )
             }
 
             fn visit_str<E: serde::de::Error>(self, v: &str) -> result::Result<Self::Value, E> {
@@ -118,7 +270,7 @@ pub struct ServerVersion {
     /// # Examples
     /// ```
     /// let version = crate::rustone::config::ServerVersion::new("1.12.2").unwrap();
-    /// assert_eq!(version.minecraft, crate::rustone::config::MinecraftVersion(1, 12, Some(2)));
+    /// assert_eq!(version.minecraft, crate::rustone::config::MinecraftVersion::release(1, 12, Some(2)));
     /// ```
     pub minecraft: MinecraftVersion,
 
@@ -134,21 +286,32 @@ pub struct ServerVersion {
 }
 
 impl ServerVersion {
+    /// Parse a Minecraft version, optionally followed by a dash and a build
+    /// number. Only a suffix of digits is a build, `1.20.5-rc1` has none.
+    ///
+    /// # Examples
+    /// ```
+    /// use rustone::config::{MinecraftVersion, ServerVersion, Stage};
+    /// let version = ServerVersion::new("1.20.5-rc1-12").unwrap();
+    /// assert_eq!(version.minecraft, MinecraftVersion::Release(1, 20, Some(5), Stage::ReleaseCandidate(1)));
+    /// assert_eq!(version.patch, Some(12));
+    /// assert_eq!(ServerVersion::new("1.17-5").unwrap().to_string(), "1.17-5");
+    /// ```
     pub fn new(data: &str) -> Result<Self> {
-        let mut splitted = data.split(&['.', '-'][..]);
-        let minecraft = MinecraftVersion::from_splitted(&mut splitted)
-            .chain_err(|| "failed to parse Minecraft version")?;
-
-        let patch;
-        if let Some(patch_str) = splitted.next() {
-            patch = Some(
-                patch_str
+        let (minecraft, patch) = match data.rsplit_once('-') {
+            Some((minecraft, build))
+                if !build.is_empty() && build.bytes().all(|b| b.is_ascii_digit()) =>
+            {
+                let build = build
                     .parse::<u32>()
-                    .chain_err(|| "parse error for Paper patch version")?,
-            );
-        } else {
-            patch = None;
-        }
+                    .chain_err(|| "parse error for Paper patch version")?;
+                (minecraft, Some(build))
+            }
+            _ => (data, None),
+        };
+        let minecraft = minecraft
+            .parse()
+            .chain_err(|| "failed to parse Minecraft version")?;
 
         Ok(Self { minecraft, patch })
     }
@@ -208,19 +371,370 @@ impl<'de> Deserialize<'de> for ServerVersion {
     }
 }
 
+/// The Minecraft versions a [`VersionSelector`] allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MinecraftSelector {
+    /// `latest`: the newest release
+    Latest,
+    /// `1.20.x`: the newest release of a minor version
+    Minor(u32, u32),
+    /// `1.20.4`
+    Exact(MinecraftVersion),
+}
+
+/// A version as users write it: an exact version like `1.20.4` or
+/// `1.20.4-496`, or a selector like `latest`, `1.20.x` or `1.20.4@latest`
+/// which is resolved to one.
+///
+/// # Examples
+/// ```
+/// use rustone::config::{MinecraftSelector, MinecraftVersion, VersionSelector};
+/// let selector: VersionSelector = "1.20.x".parse().unwrap();
+/// assert_eq!(selector.minecraft, MinecraftSelector::Minor(1, 20));
+/// assert!(selector.allows(&"1.20.4".parse().unwrap()));
+/// assert!(!selector.allows(&"1.20.5-rc1".parse().unwrap()));
+/// assert!(!selector.allows(&"1.21".parse().unwrap()));
+///
+/// let pinned: VersionSelector = "1.20.4@496".parse().unwrap();
+/// assert_eq!(pinned.build, Some(496));
+/// assert_eq!(pinned.to_string(), "1.20.4-496");
+/// assert!(pinned.exact().is_some());
+/// assert_eq!("1.20.4@latest".parse::<VersionSelector>().unwrap().to_string(), "1.20.4");
+/// assert!("latest@496".parse::<VersionSelector>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionSelector {
+    pub minecraft: MinecraftSelector,
+    /// A pinned build, or `None` for the newest one
+    pub build: Option<u32>,
+}
+
+impl VersionSelector {
+    /// Whether the selector allows `version`. Only `1.20.4`-style exact
+    /// selectors allow pre-releases and snapshots.
+    pub fn allows(&self, version: &MinecraftVersion) -> bool {
+        let release = matches!(version, MinecraftVersion::Release(.., Stage::Release));
+        match self.minecraft {
+            MinecraftSelector::Latest => release,
+            MinecraftSelector::Minor(major, minor) => {
+                release && version.minor() == Some((major, minor))
+            }
+            MinecraftSelector::Exact(exact) => *version == exact,
+        }
+    }
+
+    /// The version, if the selector pins both the Minecraft version and the
+    /// build.
+    pub fn exact(&self) -> Option<ServerVersion> {
+        match (self.minecraft, self.build) {
+            (MinecraftSelector::Exact(minecraft), Some(patch)) => Some(ServerVersion {
+                minecraft,
+                patch: Some(patch),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for VersionSelector {
+    type Err = Error;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        let (minecraft, build) = match s.split_once('@') {
+            Some((minecraft, "latest")) => (minecraft, None),
+            Some((minecraft, build)) => (
+                minecraft,
+                Some(
+                    build
+                        .parse()
+                        .chain_err(|| format!("invalid build number '{}'", build))?,
+                ),
+            ),
+            None => (s, None),
+        };
+        let selector = if minecraft == "latest" {
+            Self {
+                minecraft: MinecraftSelector::Latest,
+                build,
+            }
+        } else if let Some(minor) = minecraft.strip_suffix(".x") {
+            let version: MinecraftVersion = minor
+                .parse()
+                .chain_err(|| format!("invalid version selector '{}'", s))?;
+            match version {
+                MinecraftVersion::Release(major, minor, None, Stage::Release) => Self {
+                    minecraft: MinecraftSelector::Minor(major, minor),
+                    build,
+                },
+                _ => bail!("invalid version selector '{}', use one like 1.20.x", s),
+            }
+        } else {
+            let version = ServerVersion::new(minecraft)?;
+            if version.patch.is_some() && build.is_some() {
+                bail!("invalid version selector '{}', it has two builds", s);
+            }
+            Self {
+                minecraft: MinecraftSelector::Exact(version.minecraft),
+                build: build.or(version.patch),
+            }
+        };
+        if selector.build.is_some() && !matches!(selector.minecraft, MinecraftSelector::Exact(_)) {
+            bail!(
+                "invalid version selector '{}', a build needs an exact Minecraft version",
+                s
+            );
+        }
+        Ok(selector)
+    }
+}
+
+impl Display for VersionSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.minecraft {
+            MinecraftSelector::Latest => write!(f, "latest")?,
+            MinecraftSelector::Minor(major, minor) => write!(f, "{}.{}.x", major, minor)?,
+            MinecraftSelector::Exact(version) => write!(f, "{}", version)?,
+        }
+        if let Some(build) = self.build {
+            write!(f, "-{}", build)?;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for VersionSelector {
+    fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for VersionSelector {
+    fn deserialize<D>(deserializer: D) -> result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// An amount of memory, written like the JVM's `-Xmx` values: `512M`, `4G`.
+///
+/// # Examples
+/// ```
+/// use rustone::config::MemorySize;
+/// let size: MemorySize = "4G".parse().unwrap();
+/// assert_eq!(size.megabytes(), 4096);
+/// assert_eq!(size.to_string(), "4G");
+/// assert_eq!("1536m".parse::<MemorySize>().unwrap().to_string(), "1536M");
+/// assert!("18446744073709551615G".parse::<MemorySize>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MemorySize(u64);
+
+impl MemorySize {
+    pub fn from_megabytes(megabytes: u64) -> Self {
+        Self(megabytes)
+    }
+
+    pub fn megabytes(&self) -> u64 {
+        self.0
+    }
+}
+
+impl FromStr for MemorySize {
+    type Err = Error;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        let s = s.trim();
+        let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+        let number: u64 = number
+            .parse()
+            .chain_err(|| format!("invalid memory size '{}'", s))?;
+        let megabytes = match unit.to_ascii_uppercase().as_str() {
+            "M" | "MB" | "MIB" => Some(number),
+            "G" | "GB" | "GIB" => number.checked_mul(1024),
+            _ => bail!("invalid memory size '{}' (use M or G, like 512M or 4G)", s),
+        };
+        // The JVM takes sizes in bytes, so they have to fit in them too
+        let megabytes = match megabytes {
+            Some(megabytes) if megabytes.checked_mul(1024 * 1024).is_some() => megabytes,
+            _ => bail!("memory size '{}' is too large", s),
+        };
+        if megabytes == 0 {
+            bail!("memory size can not be zero");
+        }
+        Ok(Self(megabytes))
+    }
+}
+
+impl Display for MemorySize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_multiple_of(1024) {
+            write!(f, "{}G", self.0 / 1024)
+        } else {
+            write!(f, "{}M", self.0)
+        }
+    }
+}
+
+impl serde::Serialize for MemorySize {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for MemorySize {
+    fn deserialize<D>(deserializer: D) -> result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        use serde::de::Error;
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(|e: crate::errors::Error| D::Error::custom(e.to_string()))
+    }
+}
+
+/// A set of tuned JVM flags.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum JvmPreset {
+    #[default]
+    None,
+    /// Aikar's G1 flags, the usual recommendation for Paper servers
+    Aikar,
+    /// The Z garbage collector, for large heaps on Java 17+
+    Zgc,
+}
+
+/// How the files of a server are laid out in its directory.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerLayout {
+    /// Separate `configs`, `worlds` and `plugins` (or `mods`) directories
+    #[default]
+    Standard,
+    /// Everything in one directory, like servers set up by hand
+    Flat,
+}
+
+/// How far a server's version is updated when it starts (or with `cache
+/// upgrade`), within what its selector allows.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum UpdatePolicy {
+    /// Never change the version
+    Pinned,
+    /// The newest build of the same Minecraft version
+    #[default]
+    LatestBuild,
+    /// The newest patch release of the same minor version, like 1.20.1 to
+    /// 1.20.4
+    LatestPatch,
+    /// Like `latest-build`, but only when asked for, with `cache upgrade`
+    Manual,
+}
+
+impl FromStr for UpdatePolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        use serde::de::IntoDeserializer;
+        let deserializer: serde::de::value::StrDeserializer<serde::de::value::Error> =
+            s.into_deserializer();
+        Self::deserialize(deserializer).chain_err(|| format!("invalid update policy '{}'", s))
+    }
+}
+
+/// The schema version of server configurations written by this version of
+/// rustone.
+pub const SCHEMA_VERSION: u32 = 2;
+
 #[derive(serde::Serialize, serde::Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct ServerConfig {
+    /// Older configurations are migrated when they are loaded
+    #[serde(default)]
+    pub schema_version: u32,
+    /// The version the server runs, the last resolution of `selector`
     pub version: ServerVersion,
+    /// The version asked for, like `latest` or `1.20.x`, if it was not an
+    /// exact one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selector: Option<VersionSelector>,
+    #[serde(default)]
+    pub update_policy: UpdatePolicy,
+    /// The build the server ran before it was updated, while the new one has
+    /// not started successfully yet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rollback_to: Option<ServerVersion>,
+    /// Builds which failed to start, the server is not updated to them again
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bad_builds: Vec<ServerVersion>,
+    /// Seconds the server may take to start before it is considered failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub startup_timeout: Option<u64>,
     pub name: String,
     #[serde(default)]
     pub extra_java_args: Vec<String>,
     #[serde(default)]
     pub extra_server_args: Vec<String>,
     pub kind: String,
+    /// Initial heap size (`-Xms`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_heap: Option<MemorySize>,
+    /// Maximum heap size (`-Xmx`). Defaults to a share of the host's memory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_heap: Option<MemorySize>,
+    /// Defaults to the preset of the global configuration
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jvm_preset: Option<JvmPreset>,
+    #[serde(default)]
+    pub layout: ServerLayout,
+    /// The directory of the server, if it is not in rustone's data directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub directory: Option<std::path::PathBuf>,
+    /// The `java` executable to use, instead of a discovered one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub java_path: Option<std::path::PathBuf>,
+    /// The instance the server belongs to, set when the configuration is
+    /// loaded
+    #[serde(skip)]
+    pub paths: crate::paths::Paths,
 }
 
 impl ServerConfig {
     pub fn new(str_config: &str) -> Result<Self> {
-        Ok(toml::from_str(str_config).chain_err(|| "invalid server configuration")?)
+        Ok(Self::parse(str_config)?.0)
+    }
+
+    /// Parse a configuration, migrating it to the current schema version.
+    /// Also returns the schema version it had.
+    ///
+    /// # Examples
+    /// ```
+    /// use rustone::config::{ServerConfig, SCHEMA_VERSION};
+    /// let (config, from) = ServerConfig::parse(
+    ///     "name = 'old'\nversion = '1.16.5'\nkind = 'paper'\nextra_java_args = ['-Xmx4G']",
+    /// )
+    /// .unwrap();
+    /// assert_eq!(from, 0);
+    /// assert_eq!(config.schema_version, SCHEMA_VERSION);
+    /// assert_eq!(config.max_heap.unwrap().to_string(), "4G");
+    /// assert!(config.extra_java_args.is_empty());
+    /// ```
+    pub fn parse(str_config: &str) -> Result<(Self, u32)> {
+        let invalid = || ErrorKind::InvalidConfig("server configuration".to_owned());
+        let mut table: toml::value::Table = toml::from_str(str_config).chain_err(invalid)?;
+        let from = migrations::migrate(&mut table).chain_err(invalid)?;
+        let config = toml::Value::Table(table).try_into().chain_err(invalid)?;
+        Ok((config, from))
     }
 }
@@ -0,0 +1,85 @@
+//! Upgrading server configurations written by older versions of rustone.
+//!
+//! Each step takes a configuration from one schema version to the next.
+//! Configurations from before schema versions existed are version 0.
+
+use error_chain::bail;
+use toml::value::{Table, Value};
+
+use super::{MemorySize, SCHEMA_VERSION};
+use crate::errors::*;
+
+type Migration = fn(&mut Table) -> Result<()>;
+
+/// `MIGRATIONS[n]` upgrades a configuration from version `n` to `n + 1`.
+const MIGRATIONS: &[Migration] = &[heap_from_java_args, inherit_jvm_preset];
+
+/// Heap sizes used to be passed with `extra_java_args`, they have their own
+/// settings since `min_heap` and `max_heap`, which conflict with them.
+fn heap_from_java_args(config: &mut Table) -> Result<()> {
+    let args = match config.get("extra_java_args") {
+        Some(Value::Array(args)) => args.clone(),
+        _ => return Ok(()),
+    };
+    let mut kept = vec![];
+    for arg in args {
+        let moved = arg.as_str().and_then(|arg| {
+            [("-Xms", "min_heap"), ("-Xmx", "max_heap")]
+                .iter()
+                .find_map(|(flag, key)| Some((*key, arg.strip_prefix(flag)?)))
+        });
+        match moved {
+            // Sizes rustone can't express, like `-Xmx4194304k`, and flags
+            // which contradict a heap setting are left for the user to fix
+            Some((key, size)) if !config.contains_key(key) => match size.parse::<MemorySize>() {
+                Ok(size) => {
+                    config.insert(key.to_owned(), Value::String(size.to_string()));
+                }
+                Err(_) => kept.push(arg),
+            },
+            _ => kept.push(arg),
+        }
+    }
+    config.insert("extra_java_args".to_owned(), Value::Array(kept));
+    Ok(())
+}
+
+/// `jvm_preset` was always written, as `none` unless it was changed. It is
+/// inherited from the global configuration when it is not set now, which
+/// `none` would prevent.
+fn inherit_jvm_preset(config: &mut Table) -> Result<()> {
+    if config.get("jvm_preset").and_then(Value::as_str) == Some("none") {
+        config.remove("jvm_preset");
+    }
+    Ok(())
+}
+
+/// Upgrade a configuration to the current schema version. Returns the
+/// version it had.
+pub fn migrate(config: &mut Table) -> Result<u32> {
+    let version = match config.get("schema_version") {
+        None => 0,
+        Some(Value::Integer(version)) if *version >= 0 => *version as u32,
+        Some(version) => bail!("invalid schema_version: {}", version),
+    };
+    if version > SCHEMA_VERSION {
+        bail!(
+            "the configuration has schema version {}, this version of rustone only supports up to {}",
+            version,
+            SCHEMA_VERSION
+        );
+    }
+    for (step, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        migration(config).chain_err(|| {
+            format!(
+                "failed to migrate configuration to schema version {}",
+                step + 1
+            )
+        })?;
+    }
+    config.insert(
+        "schema_version".to_owned(),
+        Value::Integer(SCHEMA_VERSION.into()),
+    );
+    Ok(version)
+}
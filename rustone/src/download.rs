@@ -0,0 +1,311 @@
+//! Downloading files: streamed to their destination, resumed with `Range`
+//! requests after network errors, and retried with exponential backoff.
+
+use std::{
+    io::Write,
+    time::{Duration, Instant},
+};
+
+use error_chain::bail;
+use reqwest::{
+    header::{CONTENT_RANGE, RANGE},
+    Response, StatusCode, Url,
+};
+
+use tracing::{debug, info, instrument, warn};
+
+use crate::{errors::*, paths::Paths, plugins::sources::download_client, settings};
+
+/// How hard to try downloading a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DownloadOptions {
+    /// Attempts after the first one failed, for each piece of progress
+    pub retries: u32,
+    /// The wait before the first retry, doubled for each one after it
+    pub backoff: Duration,
+    /// How long a request, or a read of its body, may take. `None` for the
+    /// `read_timeout` network setting.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            retries: 5,
+            backoff: Duration::from_secs(1),
+            timeout: None,
+        }
+    }
+}
+
+impl DownloadOptions {
+    fn timeout(&self, paths: &Paths) -> Duration {
+        self.timeout
+            .unwrap_or_else(|| Duration::from_secs(settings::network(paths).read_timeout.value))
+    }
+}
+
+/// How far a download is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Progress {
+    pub downloaded: u64,
+    /// The size of the file, if the server told it
+    pub total: Option<u64>,
+}
+
+/// The first byte and the size of the whole file from the `Content-Range` of
+/// a partial response, like `bytes 100-199/200`. The size may be unknown
+/// (`bytes 100-199/*`).
+fn content_range(response: &Response) -> Option<(u64, Option<u64>)> {
+    let range = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    let (range, total) = range.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, _) = range.split_once('-')?;
+    Some((start.trim().parse().ok()?, total.trim().parse().ok()))
+}
+
+/// The size of the whole file, from a response to a request for it or the
+/// rest of it.
+fn total_size(response: &Response) -> Option<u64> {
+    if response.status() == StatusCode::PARTIAL_CONTENT {
+        content_range(response)?.1
+    } else {
+        // The whole file, however much was asked for
+        response.content_length()
+    }
+}
+
+async fn request(
+    client: &reqwest::Client,
+    url: &Url,
+    offset: u64,
+    timeout: Duration,
+) -> Result<Response> {
+    debug!(%url, offset, "requesting");
+    let mut request = client.get(url.clone());
+    if offset > 0 {
+        request = request.header(RANGE, format!("bytes={}-", offset));
+    }
+    match tokio::time::timeout(timeout, request.send()).await {
+        Ok(response) => response.chain_err(|| format!("failed to request {}", url)),
+        Err(_) => bail!(ErrorKind::UpstreamUnavailable(format!(
+            "{} (timed out)",
+            url
+        ))),
+    }
+}
+
+/// Why an attempt failed, and whether trying again may help.
+enum Failure {
+    Retry(Error),
+    Fatal(Error),
+}
+
+/// Write the body of `response` into `out`. If the connection fails, the
+/// rest is requested again from where it stopped, with the network settings
+/// of the instance in `paths`. Returns the size of the file.
+#[instrument(skip_all, fields(url = %response.url()))]
+pub async fn stream<W, F>(
+    paths: &Paths,
+    response: Response,
+    out: &mut W,
+    options: &DownloadOptions,
+    mut on_progress: F,
+) -> Result<u64>
+where
+    W: Write + ?Sized,
+    F: FnMut(Progress),
+{
+    let url = response.url().clone();
+    let client = download_client(paths)?;
+    let timeout = options.timeout(paths);
+    let begun = Instant::now();
+    let mut response = Some(response);
+    let mut progress = Progress::default();
+    let mut attempt = 0;
+    loop {
+        let before = progress.downloaded;
+        let failure = match attempt_stream(
+            &client,
+            &url,
+            response.take(),
+            out,
+            timeout,
+            &mut progress,
+            &mut on_progress,
+        )
+        .await
+        {
+            Ok(()) => {
+                info!(
+                    bytes = progress.downloaded,
+                    elapsed = ?begun.elapsed(),
+                    "downloaded"
+                );
+                return Ok(progress.downloaded);
+            }
+            Err(Failure::Fatal(e)) => return Err(e),
+            Err(Failure::Retry(e)) => e,
+        };
+        // Every piece of progress earns a fresh set of retries
+        attempt = if progress.downloaded > before {
+            1
+        } else {
+            attempt + 1
+        };
+        if attempt > options.retries {
+            return Err(failure).chain_err(|| {
+                format!(
+                    "failed to download {} after {} retries",
+                    url, options.retries
+                )
+            });
+        }
+        let delay = options.backoff * 2u32.saturating_pow(attempt - 1);
+        warn!(
+            attempt,
+            downloaded = progress.downloaded,
+            error = %failure,
+            ?delay,
+            "download interrupted, retrying"
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// The first attempt of [`stream`] comes with a response already, the next
+/// ones request the rest of the file.
+async fn attempt_stream<W, F>(
+    client: &reqwest::Client,
+    url: &Url,
+    response: Option<Response>,
+    out: &mut W,
+    timeout: Duration,
+    progress: &mut Progress,
+    on_progress: &mut F,
+) -> std::result::Result<(), Failure>
+where
+    W: Write + ?Sized,
+    F: FnMut(Progress),
+{
+    let offset = progress.downloaded;
+    let mut response = match response {
+        Some(response) => response,
+        None => request(client, url, offset, timeout)
+            .await
+            .map_err(Failure::Retry)?,
+    };
+    let status = response.status();
+    // Servers ignoring the range send the whole file again, and partial
+    // responses may start earlier than asked for. What was written can't be
+    // taken back, so ones starting later are refused.
+    let mut skip = if status == StatusCode::PARTIAL_CONTENT {
+        match content_range(&response) {
+            Some((start, _)) if start <= offset => offset - start,
+            Some((start, _)) => {
+                return Err(Failure::Fatal(
+                    ErrorKind::UpstreamUnavailable(format!(
+                        "{} (resumed at byte {} instead of {})",
+                        url, start, offset
+                    ))
+                    .into(),
+                ))
+            }
+            None => {
+                return Err(Failure::Fatal(
+                    ErrorKind::UpstreamUnavailable(format!(
+                        "{} (partial response without a valid Content-Range)",
+                        url
+                    ))
+                    .into(),
+                ))
+            }
+        }
+    } else {
+        offset
+    };
+    if skip > 0 {
+        debug!(skip, "server sent bytes already downloaded");
+    }
+    if status == StatusCode::NOT_FOUND {
+        return Err(Failure::Fatal(ErrorKind::NotFound(url.to_string()).into()));
+    }
+    if !status.is_success() {
+        let error = ErrorKind::UpstreamUnavailable(format!("{} ({})", url, status)).into();
+        return Err(
+            if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+                Failure::Retry(error)
+            } else {
+                Failure::Fatal(error)
+            },
+        );
+    }
+    progress.total = total_size(&response).or(progress.total);
+    on_progress(*progress);
+    loop {
+        let chunk = match tokio::time::timeout(timeout, response.chunk()).await {
+            Ok(Ok(Some(chunk))) => chunk,
+            Ok(Ok(None)) => break,
+            Ok(Err(e)) => {
+                return Err(Failure::Retry(Error::with_chain(
+                    e,
+                    format!("failed to download {}", url),
+                )))
+            }
+            Err(_) => {
+                return Err(Failure::Retry(
+                    ErrorKind::UpstreamUnavailable(format!("{} (timed out)", url)).into(),
+                ))
+            }
+        };
+        let skipped = skip.min(chunk.len() as u64);
+        skip -= skipped;
+        let chunk = &chunk[skipped as usize..];
+        out.write_all(chunk)
+            .chain_err(|| "failed to write into file")
+            .map_err(Failure::Fatal)?;
+        progress.downloaded += chunk.len() as u64;
+        on_progress(*progress);
+    }
+    match progress.total {
+        Some(total) if progress.downloaded < total => Err(Failure::Retry(
+            ErrorKind::UpstreamUnavailable(format!("{} (ended early)", url)).into(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Download `url` into `out`, with the network settings of the instance in
+/// `paths`.
+pub async fn get<W, F>(
+    paths: &Paths,
+    url: &str,
+    out: &mut W,
+    options: &DownloadOptions,
+    on_progress: F,
+) -> Result<u64>
+where
+    W: Write + ?Sized,
+    F: FnMut(Progress),
+{
+    let parsed = Url::parse(url).chain_err(|| format!("invalid URL {}", url))?;
+    let client = download_client(paths)?;
+    let timeout = options.timeout(paths);
+    let mut attempt = 0;
+    let response = loop {
+        let error = match request(&client, &parsed, 0, timeout).await {
+            Ok(response) if !response.status().is_server_error() => break response,
+            Ok(response) => {
+                ErrorKind::UpstreamUnavailable(format!("{} ({})", url, response.status())).into()
+            }
+            Err(e) => e,
+        };
+        attempt += 1;
+        if attempt > options.retries {
+            return Err(error);
+        }
+        let delay = options.backoff * 2u32.saturating_pow(attempt - 1);
+        warn!(url, attempt, %error, ?delay, "request failed, retrying");
+        tokio::time::sleep(delay).await;
+    };
+    stream(paths, response, out, options, on_progress).await
+}
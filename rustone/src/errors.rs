@@ -1,4 +1,85 @@
 use error_chain::error_chain;
 use std::boxed::Box;
 
-error_chain! {}
+error_chain! {
+    foreign_links {
+        Io(std::io::Error);
+    }
+
+    errors {
+        /// A server, version, project or other named thing does not exist
+        NotFound(what: String) {
+            description("not found")
+            display("{} not found", what)
+        }
+        AlreadyExists(what: String) {
+            description("already exists")
+            display("{} already exists", what)
+        }
+        /// A remote service (or the network as a whole) could not be used
+        UpstreamUnavailable(what: String) {
+            description("upstream unavailable")
+            display("{} is unavailable", what)
+        }
+        /// A configuration file or setting could not be understood
+        InvalidConfig(what: String) {
+            description("invalid configuration")
+            display("invalid {}", what)
+        }
+        /// A process could not be run, or it failed
+        ProcessFailed(what: String) {
+            description("process failed")
+            display("{} failed", what)
+        }
+    }
+}
+
+/// What kind of failure an error is, so that frontends can tell them apart
+/// (for exit codes or HTTP statuses).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    NotFound,
+    AlreadyExists,
+    UpstreamUnavailable,
+    InvalidConfig,
+    Io,
+    ProcessFailed,
+    Other,
+}
+
+impl Error {
+    /// The category of the error. Errors are usually given some context on
+    /// their way up with `chain_err`, so the outermost specific kind in the
+    /// chain is used; I/O and HTTP errors count even if they were only
+    /// chained as causes.
+    ///
+    /// # Examples
+    /// ```
+    /// use rustone::errors::{Category, ErrorKind, ResultExt, Result};
+    /// let result: Result<()> = Err(ErrorKind::NotFound("server survival".to_owned()).into());
+    /// let error = result.chain_err(|| "failed to start survival").unwrap_err();
+    /// assert_eq!(error.category(), Category::NotFound);
+    /// ```
+    pub fn category(&self) -> Category {
+        let mut next: Option<&(dyn std::error::Error + 'static)> = Some(self);
+        while let Some(error) = next {
+            if let Some(error) = error.downcast_ref::<Error>() {
+                match error.kind() {
+                    ErrorKind::NotFound(_) => return Category::NotFound,
+                    ErrorKind::AlreadyExists(_) => return Category::AlreadyExists,
+                    ErrorKind::UpstreamUnavailable(_) => return Category::UpstreamUnavailable,
+                    ErrorKind::InvalidConfig(_) => return Category::InvalidConfig,
+                    ErrorKind::ProcessFailed(_) => return Category::ProcessFailed,
+                    ErrorKind::Io(_) => return Category::Io,
+                    _ => {}
+                }
+            } else if error.is::<std::io::Error>() {
+                return Category::Io;
+            } else if error.is::<reqwest::Error>() {
+                return Category::UpstreamUnavailable;
+            }
+            next = error.source();
+        }
+        Category::Other
+    }
+}
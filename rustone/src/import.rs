@@ -0,0 +1,333 @@
+//! Importing servers which were set up by hand, with everything in one
+//! directory: `server.properties`, `world/`, `plugins/` and the server jar.
+
+use std::{
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use error_chain::bail;
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::{
+    config::{ServerConfig, ServerLayout, ServerVersion, UpdatePolicy, SCHEMA_VERSION},
+    errors::*,
+    paths::Paths,
+    properties,
+    server_kinds::{fabric, ServerKind},
+    servers::Server,
+};
+
+/// What to do with the files of the imported server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportMode {
+    /// Copy the files into rustone's layout, leaving the original alone
+    Copy,
+    /// Move the files into rustone's layout
+    Move,
+    /// Keep the files where they are, in the flat layout
+    Flat,
+}
+
+/// Files which are not copied: server jars and what they download.
+const SKIPPED: &[&str] = &["cache", "libraries", "versions", ".fabric"];
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VersionHistory {
+    current_version: String,
+}
+
+/// Parse a Paper version string, as found in `version_history.json`.
+///
+/// # Examples
+/// ```
+/// use rustone::import::parse_paper_version;
+/// let version = parse_paper_version("git-Paper-196 (MC: 1.20.1)").unwrap();
+/// assert_eq!(version.to_string(), "1.20.1-196");
+/// ```
+pub fn parse_paper_version(text: &str) -> Option<ServerVersion> {
+    let build = text.split("Paper-").nth(1)?.split_whitespace().next()?;
+    let minecraft = text.split("MC: ").nth(1)?.split(')').next()?;
+    Some(ServerVersion {
+        minecraft: minecraft.trim().parse().ok()?,
+        patch: build.parse().ok(),
+    })
+}
+
+/// Detect the server kind and version from a server jar.
+fn detect_jar(jar: &Path) -> Option<(ServerKind, ServerVersion)> {
+    let mut archive = zip::ZipArchive::new(fs::File::open(jar).ok()?).ok()?;
+    let mut read = |name: &str| -> Option<String> {
+        let mut text = String::new();
+        archive.by_name(name).ok()?.read_to_string(&mut text).ok()?;
+        Some(text)
+    };
+
+    // The Fabric server launcher
+    if let Some(install) = read("install.properties") {
+        let props: Vec<_> = properties::parse(&install).collect();
+        let get = |key: &str| props.iter().find(|(k, _)| *k == key).map(|(_, v)| *v);
+        let minecraft = get("game-version")?.parse().ok()?;
+        let patch = get("fabric-loader-version").and_then(fabric::encode_loader_version);
+        return Some((ServerKind::Fabric, ServerVersion { minecraft, patch }));
+    }
+
+    // Older Paper jars carry their version in the manifest
+    if let Some(manifest) = read("META-INF/MANIFEST.MF") {
+        let version = properties::parse(&manifest.replace(": ", "="))
+            .find(|(k, _)| *k == "Implementation-Version")
+            .and_then(|(_, v)| parse_paper_version(v));
+        if let Some(version) = version {
+            return Some((ServerKind::Paper, version));
+        }
+    }
+    // Paperclip lists the bundled server as `<hash>\t<id>\t<path>`, like
+    // `...\tpaper-1.20.1\tpaper-1.20.1.jar`
+    let versions = read("META-INF/versions.list")?;
+    let id = versions.lines().next()?.split('\t').nth(1)?;
+    let minecraft = id.rsplit('-').next()?.parse().ok()?;
+    Some((
+        ServerKind::Paper,
+        ServerVersion {
+            minecraft,
+            patch: None,
+        },
+    ))
+}
+
+/// Detect the kind and version of the server in `dir`, from Paper's
+/// `version_history.json` or the server jar.
+pub fn detect(dir: &Path) -> Result<Option<(ServerKind, ServerVersion)>> {
+    let history = dir.join("version_history.json");
+    if history.is_file() {
+        let text = fs::read_to_string(&history).chain_err(|| "failed to read version history")?;
+        let history: VersionHistory =
+            serde_json::from_str(&text).chain_err(|| "invalid version_history.json")?;
+        if let Some(version) = parse_paper_version(&history.current_version) {
+            return Ok(Some((ServerKind::Paper, version)));
+        }
+    }
+    let mut jars: Vec<PathBuf> = fs::read_dir(dir)
+        .chain_err(|| format!("failed to list {}", dir.to_string_lossy()))?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_file() && p.extension().is_some_and(|e| e == "jar"))
+        .collect();
+    jars.sort();
+    Ok(jars.iter().find_map(|jar| detect_jar(jar)))
+}
+
+/// Copy a file, or a directory with its content, merging into existing
+/// directories.
+pub(crate) fn copy_recursive(from: &Path, to: &Path) -> Result<()> {
+    if from.is_dir() {
+        fs::create_dir_all(to)
+            .chain_err(|| format!("failed to create {}", to.to_string_lossy()))?;
+        for entry in
+            fs::read_dir(from).chain_err(|| format!("failed to list {}", from.to_string_lossy()))?
+        {
+            let entry = entry.chain_err(|| "failed to read directory entry")?;
+            copy_recursive(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else {
+        fs::copy(from, to).chain_err(|| format!("failed to copy {}", from.to_string_lossy()))?;
+    }
+    Ok(())
+}
+
+/// Where an entry of the source ended up.
+enum Transferred {
+    /// Renamed into the server, it's gone from the source
+    Renamed,
+    /// Copied into the server, the source is still there
+    Copied,
+}
+
+/// Copy or move `from` to `to`, recording what was transferred. Moves across
+/// filesystems copy, and leave removing the source to the caller. What is
+/// already at the destination is kept: directories are merged, and files
+/// there are not replaced.
+fn transfer(
+    from: &Path,
+    to: &Path,
+    mode: ImportMode,
+    transferred: &mut Vec<(PathBuf, PathBuf, Transferred)>,
+) -> Result<()> {
+    if to.exists() {
+        if from.is_dir() && to.is_dir() {
+            for entry in fs::read_dir(from)
+                .chain_err(|| format!("failed to list {}", from.to_string_lossy()))?
+            {
+                let entry = entry.chain_err(|| "failed to read directory entry")?;
+                transfer(
+                    &entry.path(),
+                    &to.join(entry.file_name()),
+                    mode,
+                    transferred,
+                )?;
+            }
+        }
+        return Ok(());
+    }
+    let how = if mode == ImportMode::Move && fs::rename(from, to).is_ok() {
+        Transferred::Renamed
+    } else {
+        copy_recursive(from, to)?;
+        Transferred::Copied
+    };
+    transferred.push((from.to_owned(), to.to_owned(), how));
+    Ok(())
+}
+
+fn remove_path(path: &Path) -> Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+    .chain_err(|| format!("failed to remove {}", path.to_string_lossy()))
+}
+
+/// Whether a top-level entry of a flat server comes with the server jar:
+/// the jar itself, or what it downloads.
+pub(crate) fn is_server_file(entry: &Path) -> bool {
+    let name = match entry.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => return false,
+    };
+    SKIPPED.contains(&name.as_ref()) || (entry.is_file() && name.ends_with(".jar"))
+}
+
+/// Where a top-level entry of a flat server goes in the standard layout.
+fn destination(config: &ServerConfig, kind: &ServerKind, entry: &Path) -> Option<PathBuf> {
+    let name = entry.file_name()?.to_string_lossy().into_owned();
+    if is_server_file(entry) {
+        return None;
+    }
+    let (content_dir, content_path) = match kind.mod_loader() {
+        Some(_) => ("mods", config.mods_path()),
+        None => ("plugins", config.path().join("plugins")),
+    };
+    Some(if entry.join("level.dat").is_file() {
+        config.worlds_path().join(name)
+    } else if name == content_dir {
+        content_path
+    } else {
+        config.configs_path().join(name)
+    })
+}
+
+/// Import the server in `source` as `name`, into the instance in `paths`.
+/// The kind and version are detected unless given.
+pub fn import(
+    paths: &Paths,
+    name: &str,
+    source: &Path,
+    mode: ImportMode,
+    kind: Option<ServerKind>,
+    version: Option<ServerVersion>,
+) -> Result<Server> {
+    if paths.server_config(name).exists() {
+        bail!(ErrorKind::AlreadyExists(format!("a server named {}", name)));
+    }
+    let source = source
+        .canonicalize()
+        .chain_err(|| ErrorKind::NotFound(source.to_string_lossy().into_owned()))?;
+    if !source.is_dir() {
+        bail!("{} is not a directory", source.to_string_lossy());
+    }
+    let (kind, version) = match (kind, version) {
+        (Some(kind), Some(version)) => (kind, version),
+        (kind, version) => match detect(&source)? {
+            Some((detected_kind, detected_version)) => (
+                kind.unwrap_or(detected_kind),
+                version.unwrap_or(detected_version),
+            ),
+            None => bail!(
+                "could not detect the server kind and version of {}, set them explicitly",
+                source.to_string_lossy()
+            ),
+        },
+    };
+
+    let mut config = ServerConfig {
+        schema_version: SCHEMA_VERSION,
+        name: name.to_owned(),
+        version,
+        extra_java_args: vec![],
+        extra_server_args: vec![],
+        kind: kind.to_string(),
+        min_heap: None,
+        max_heap: None,
+        jvm_preset: None,
+        layout: ServerLayout::Standard,
+        directory: None,
+        java_path: None,
+        selector: None,
+        update_policy: UpdatePolicy::default(),
+        rollback_to: None,
+        bad_builds: vec![],
+        startup_timeout: None,
+        paths: paths.clone(),
+    };
+    if mode == ImportMode::Flat {
+        config.layout = ServerLayout::Flat;
+        config.directory = Some(source);
+        kind.initialize(&config)?;
+        let server = Server { config };
+        server.save()?;
+        return Ok(server);
+    }
+
+    if paths.server(name).exists() {
+        bail!("the directory of server {} already exists", name);
+    }
+    let mut transferred = vec![];
+    let imported = (|| {
+        kind.initialize(&config)?;
+        let entries = fs::read_dir(&source)
+            .chain_err(|| format!("failed to list {}", source.to_string_lossy()))?;
+        for entry in entries {
+            let entry = entry.chain_err(|| "failed to read directory entry")?.path();
+            if let Some(dest) = destination(&config, &kind, &entry) {
+                transfer(&entry, &dest, mode, &mut transferred)?;
+            }
+        }
+        let server = Server {
+            config: config.clone(),
+        };
+        server.save()?;
+        Ok(server)
+    })();
+    match imported {
+        Ok(server) => {
+            // The source only goes once the server is registered, which
+            // stays imported if that fails
+            if mode == ImportMode::Move {
+                for (from, _, how) in &transferred {
+                    if let Transferred::Copied = how {
+                        if let Err(e) = remove_path(from) {
+                            warn!(server = name, error = %e, "failed to remove moved source");
+                        }
+                    }
+                }
+            }
+            Ok(server)
+        }
+        Err(e) => {
+            // Put back what was moved, and drop the half-imported server
+            for (from, to, how) in transferred.iter().rev() {
+                if let Transferred::Renamed = how {
+                    if fs::rename(to, from).is_err() {
+                        let _ = copy_recursive(to, from);
+                    }
+                }
+            }
+            let _ = fs::remove_dir_all(paths.server(name));
+            Err(e)
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//! Discovery of installed Java runtimes, and choosing the one a server needs.
+//!
+//! Old Minecraft versions only run on Java 8, while newer ones need a
+//! recent Java, so using whatever `java` is on `PATH` is not enough.
+
+use std::{
+    collections::HashSet,
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use error_chain::bail;
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::{
+    config::{MinecraftVersion, ServerConfig},
+    errors::*,
+    metadata,
+    paths::Paths,
+    settings,
+};
+
+const VERSION_MANIFEST_PATH: &str = "/mc/game/version_manifest_v2.json";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JavaRuntime {
+    /// Path of the `java` executable
+    pub path: PathBuf,
+    /// Full version, like `17.0.9` or `1.8.0_392`
+    pub version: String,
+    pub major: u32,
+}
+
+/// Get the major version from a Java version string.
+///
+/// # Examples
+/// ```
+/// use rustone::java::parse_major;
+/// assert_eq!(parse_major("1.8.0_392"), Some(8));
+/// assert_eq!(parse_major("17.0.9"), Some(17));
+/// assert_eq!(parse_major("21"), Some(21));
+/// assert_eq!(parse_major("21-ea"), Some(21));
+/// ```
+pub fn parse_major(version: &str) -> Option<u32> {
+    let mut parts = version
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|p| p.parse::<u32>());
+    match parts.next()?.ok()? {
+        1 => parts.next()?.ok(),
+        major => Some(major),
+    }
+}
+
+/// Read the version of the Java installation at `home` from its `release`
+/// file, without running it.
+fn release_version(home: &Path) -> Option<String> {
+    let release = fs::read_to_string(home.join("release")).ok()?;
+    release.lines().find_map(|line| {
+        line.strip_prefix("JAVA_VERSION=")
+            .map(|v| v.trim_matches('"').to_owned())
+    })
+}
+
+/// Ask a `java` executable for its version. It prints something like
+/// `openjdk version "17.0.9" 2023-10-17` to stderr.
+fn run_version(java: &Path) -> Option<String> {
+    let output = Command::new(java).arg("-version").output().ok()?;
+    let text = String::from_utf8_lossy(&output.stderr);
+    let line = text.lines().find(|l| l.contains("version"))?;
+    Some(line.split('"').nth(1)?.to_owned())
+}
+
+fn probe(java: &Path, home: Option<&Path>) -> Option<JavaRuntime> {
+    if !java.is_file() {
+        return None;
+    }
+    let version = home
+        .and_then(release_version)
+        .or_else(|| run_version(java))?;
+    Some(JavaRuntime {
+        path: java.to_owned(),
+        major: parse_major(&version)?,
+        version,
+    })
+}
+
+/// Directories which contain Java installations, one per subdirectory.
+fn install_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![
+        PathBuf::from("/usr/lib/jvm"),
+        PathBuf::from("/usr/lib64/jvm"),
+        PathBuf::from("/Library/Java/JavaVirtualMachines"),
+    ];
+    match env::var_os("SDKMAN_DIR") {
+        Some(sdkman) => dirs.push(PathBuf::from(sdkman).join("candidates/java")),
+        None => {
+            if let Some(home) = directories::BaseDirs::new() {
+                dirs.push(home.home_dir().join(".sdkman/candidates/java"));
+            }
+        }
+    }
+    dirs
+}
+
+/// Find the Java runtimes installed on this machine: `JAVA_HOME`, the
+/// system JVM directories, SDKMAN installations and `java` on `PATH`.
+pub fn discover() -> Vec<JavaRuntime> {
+    let mut homes = vec![];
+    if let Some(home) = env::var_os("JAVA_HOME") {
+        homes.push(PathBuf::from(home));
+    }
+    for dir in install_dirs() {
+        let mut entries: Vec<PathBuf> = match fs::read_dir(&dir) {
+            Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+            Err(_) => continue,
+        };
+        entries.sort();
+        for entry in entries {
+            // macOS bundles keep the actual home in Contents/Home
+            let bundle_home = entry.join("Contents/Home");
+            homes.push(if bundle_home.is_dir() {
+                bundle_home
+            } else {
+                entry
+            });
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut runtimes = vec![];
+    let mut add = |java: PathBuf, home: Option<&Path>| {
+        // Distributions link the same installation under several names
+        let canonical = java.canonicalize().unwrap_or_else(|_| java.clone());
+        if seen.insert(canonical) {
+            if let Some(runtime) = probe(&java, home) {
+                runtimes.push(runtime);
+            }
+        }
+    };
+    for home in &homes {
+        add(home.join("bin").join("java"), Some(home));
+    }
+    if let Some(path) = env::var_os("PATH") {
+        for dir in env::split_paths(&path) {
+            let java = dir.join("java");
+            if java.is_file() {
+                add(java, None);
+                break;
+            }
+        }
+    }
+    runtimes
+}
+
+/// The Java version Minecraft versions need when Mojang's manifest can not
+/// be reached.
+///
+/// # Examples
+/// ```
+/// use rustone::java::fallback_required_java;
+/// assert_eq!(fallback_required_java(&"1.12.2".parse().unwrap()), 8);
+/// assert_eq!(fallback_required_java(&"1.20.4".parse().unwrap()), 17);
+/// assert_eq!(fallback_required_java(&"1.20.5".parse().unwrap()), 21);
+/// ```
+pub fn fallback_required_java(version: &MinecraftVersion) -> u32 {
+    if *version >= MinecraftVersion::release(1, 20, Some(5)) {
+        21
+    } else if *version >= MinecraftVersion::release(1, 18, None) {
+        17
+    } else if *version >= MinecraftVersion::release(1, 17, None) {
+        16
+    } else {
+        8
+    }
+}
+
+#[derive(Deserialize)]
+struct ManifestVersion {
+    id: String,
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct VersionManifest {
+    versions: Vec<ManifestVersion>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JavaVersion {
+    major_version: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VersionInfo {
+    java_version: Option<JavaVersion>,
+}
+
+/// Look up the Java version a Minecraft version needs in Mojang's version
+/// manifest. Versions from before the manifest listed it need Java 8.
+pub async fn required_java(paths: &Paths, version: &MinecraftVersion) -> Result<u32> {
+    let mirror = settings::mirrors(paths).mojang.value;
+    let url = format!("{}{}", mirror, VERSION_MANIFEST_PATH);
+    let manifest: VersionManifest = metadata::get_json(paths, &url)
+        .await
+        .chain_err(|| "mojang: failed to get version manifest")?
+        .ok_or_else(|| ErrorKind::NotFound("mojang: version manifest".to_owned()))?;
+    let id = version.to_string();
+    let entry = match manifest.versions.iter().find(|v| v.id == id) {
+        Some(entry) => entry,
+        None => bail!(ErrorKind::NotFound(format!(
+            "mojang: minecraft version {}",
+            version
+        ))),
+    };
+    let url = entry
+        .url
+        .replacen(settings::DEFAULT_MOJANG_MIRROR, &mirror, 1);
+    let info: VersionInfo = metadata::get_json(paths, &url)
+        .await
+        .chain_err(|| format!("mojang: failed to get version {}", version))?
+        .ok_or_else(|| ErrorKind::NotFound(format!("mojang: minecraft version {}", version)))?;
+    Ok(info.java_version.map_or(8, |j| j.major_version))
+}
+
+/// Choose the runtime for a server needing Java `required`. An exact match
+/// is preferred. Since Java 16, newer runtimes work too, so the closest
+/// newer one is used otherwise; Java 8 servers break on anything newer.
+pub fn select(runtimes: &[JavaRuntime], required: u32) -> Option<&JavaRuntime> {
+    runtimes.iter().find(|r| r.major == required).or_else(|| {
+        runtimes
+            .iter()
+            .filter(|r| required >= 16 && r.major > required)
+            .min_by_key(|r| r.major)
+    })
+}
+
+/// The `java` executable to launch a server with: the configured
+/// `java_path`, or a discovered runtime matching its Minecraft version.
+pub async fn java_for(config: &ServerConfig) -> Result<PathBuf> {
+    if let Some(path) = settings::effective(&config.paths, Some(config))?
+        .java_path
+        .value
+    {
+        return Ok(path);
+    }
+    let minecraft = &config.version.minecraft;
+    let required = match required_java(&config.paths, minecraft).await {
+        Ok(required) => required,
+        Err(_) => fallback_required_java(minecraft),
+    };
+    let runtimes = discover();
+    debug!(required, found = runtimes.len(), "looking for Java");
+    match select(&runtimes, required) {
+        Some(runtime) => {
+            debug!(path = %runtime.path.display(), major = runtime.major, "selected Java");
+            Ok(runtime.path.clone())
+        }
+        None => bail!(
+            "Minecraft {} needs Java {}, but it was not found (found: {}); install it or set java_path in {}",
+            minecraft,
+            required,
+            if runtimes.is_empty() {
+                "none".to_owned()
+            } else {
+                runtimes
+                    .iter()
+                    .map(|r| r.major.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            },
+            config.config_path().to_string_lossy()
+        ),
+    }
+}
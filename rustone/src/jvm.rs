@@ -0,0 +1,159 @@
+//! Building the JVM command line of a server from its memory and preset
+//! settings.
+
+use std::fs;
+
+use error_chain::bail;
+
+use crate::{
+    config::{JvmPreset, MemorySize, ServerConfig},
+    errors::*,
+    settings,
+};
+
+/// Used as the maximum heap when the host's memory can not be read.
+const FALLBACK_MAX_HEAP: u64 = 2048;
+/// The default maximum heap never goes above this.
+const DEFAULT_MAX_HEAP_CAP: u64 = 16 * 1024;
+
+/// Aikar's flags, tuned for heaps of up to 12G.
+/// See <https://docs.papermc.io/paper/aikars-flags>.
+const AIKAR_FLAGS: &[&str] = &[
+    "-XX:+UseG1GC",
+    "-XX:+ParallelRefProcEnabled",
+    "-XX:MaxGCPauseMillis=200",
+    "-XX:+UnlockExperimentalVMOptions",
+    "-XX:+DisableExplicitGC",
+    "-XX:+AlwaysPreTouch",
+    "-XX:G1HeapWastePercent=5",
+    "-XX:G1MixedGCCountTarget=4",
+    "-XX:G1MixedGCLiveThresholdPercent=90",
+    "-XX:G1RSetUpdatingPauseTimePercent=5",
+    "-XX:SurvivorRatio=32",
+    "-XX:+PerfDisableSharedMem",
+    "-XX:MaxTenuringThreshold=1",
+    "-Dusing.aikars.flags=https://mcflags.emc.gs",
+    "-Daikars.new.flags=true",
+];
+const AIKAR_SMALL_HEAP_FLAGS: &[&str] = &[
+    "-XX:G1NewSizePercent=30",
+    "-XX:G1MaxNewSizePercent=40",
+    "-XX:G1HeapRegionSize=8M",
+    "-XX:G1ReservePercent=20",
+    "-XX:InitiatingHeapOccupancyPercent=15",
+];
+const AIKAR_LARGE_HEAP_FLAGS: &[&str] = &[
+    "-XX:G1NewSizePercent=40",
+    "-XX:G1MaxNewSizePercent=50",
+    "-XX:G1HeapRegionSize=16M",
+    "-XX:G1ReservePercent=15",
+    "-XX:InitiatingHeapOccupancyPercent=20",
+];
+const ZGC_FLAGS: &[&str] = &[
+    "-XX:+UseZGC",
+    "-XX:+AlwaysPreTouch",
+    "-XX:+DisableExplicitGC",
+    "-XX:+PerfDisableSharedMem",
+];
+
+/// Total memory of the host in megabytes, from `/proc/meminfo`.
+fn host_memory() -> Option<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo.lines().find(|l| l.starts_with("MemTotal:"))?;
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes / 1024)
+}
+
+/// The maximum heap of servers which don't set one: half of the host's
+/// memory, between 1G and 16G.
+pub fn default_max_heap() -> MemorySize {
+    MemorySize::from_megabytes(host_memory().map_or(FALLBACK_MAX_HEAP, |total| {
+        (total / 2).clamp(1024, DEFAULT_MAX_HEAP_CAP)
+    }))
+}
+
+fn preset_flags(preset: JvmPreset, max_heap: MemorySize) -> Vec<&'static str> {
+    match preset {
+        JvmPreset::None => vec![],
+        JvmPreset::Aikar => {
+            let sized = if max_heap.megabytes() > 12 * 1024 {
+                AIKAR_LARGE_HEAP_FLAGS
+            } else {
+                AIKAR_SMALL_HEAP_FLAGS
+            };
+            AIKAR_FLAGS.iter().chain(sized).copied().collect()
+        }
+        JvmPreset::Zgc => ZGC_FLAGS.to_vec(),
+    }
+}
+
+/// Check that none of the extra arguments fight over a setting with the
+/// generated ones.
+fn check_conflicts(extra: &[String], preset: JvmPreset) -> Result<()> {
+    for arg in extra {
+        if arg.starts_with("-Xmx") || arg.starts_with("-Xms") {
+            bail!(
+                "extra_java_args contains '{}', use min_heap and max_heap instead",
+                arg
+            );
+        }
+        let selects_gc = arg.starts_with("-XX:+Use") && arg.ends_with("GC");
+        if preset != JvmPreset::None && selects_gc {
+            bail!(
+                "extra_java_args contains '{}', which conflicts with the {:?} preset (set jvm_preset to \"none\" to choose a garbage collector yourself)",
+                arg,
+                preset
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The arguments to start the JVM of a server with, before `-jar`: heap
+/// sizes, preset flags and then the server's `extra_java_args`. Heap sizes
+/// and the preset are inherited from the global settings.
+///
+/// # Examples
+/// ```
+/// let config = rustone::config::ServerConfig::new(
+///     "name = 'test'\nversion = '1.20.4'\nkind = 'paper'\nmax_heap = '4G'\njvm_preset = 'aikar'",
+/// )
+/// .unwrap();
+/// let args = rustone::jvm::java_args(&config).unwrap();
+/// assert_eq!(&args[..2], ["-Xms4G", "-Xmx4G"]);
+/// assert!(args.contains(&"-XX:+UseG1GC".to_owned()));
+/// ```
+pub fn java_args(config: &ServerConfig) -> Result<Vec<String>> {
+    let settings = settings::effective(&config.paths, Some(config))?;
+    let preset = settings.jvm_preset.value;
+    check_conflicts(&config.extra_java_args, preset)?;
+    let max_heap = settings.max_heap.value;
+    // Aikar's flags pre-touch the whole heap, so it starts out at full size
+    let min_heap = match (settings.min_heap.value, preset) {
+        (Some(min_heap), _) => Some(min_heap),
+        (None, JvmPreset::Aikar) => Some(max_heap),
+        (None, _) => None,
+    };
+    if let Some(min_heap) = min_heap {
+        if min_heap > max_heap {
+            bail!(
+                "min_heap ({}) is larger than max_heap ({})",
+                min_heap,
+                max_heap
+            );
+        }
+    }
+
+    let mut args: Vec<String> = min_heap
+        .map(|min| format!("-Xms{}", min))
+        .into_iter()
+        .chain(std::iter::once(format!("-Xmx{}", max_heap)))
+        .collect();
+    args.extend(
+        preset_flags(preset, max_heap)
+            .into_iter()
+            .map(str::to_owned),
+    );
+    args.extend(config.extra_java_args.iter().cloned());
+    Ok(args)
+}
@@ -1,9 +1,26 @@
 extern crate error_chain;
+pub mod archive;
+pub mod backup;
 pub mod cacher;
 pub mod config;
+pub mod download;
 pub mod errors;
+pub mod import;
+pub mod java;
+pub mod jvm;
+pub mod metadata;
+pub mod modpacks;
+pub mod mods;
+pub mod paths;
+pub mod plugins;
+pub mod properties;
+pub mod provision;
 pub mod server_kinds;
 pub mod servers;
+pub mod settings;
+pub mod templates;
+pub mod updates;
+pub mod upgrade;
 
 pub mod global {
     use crate::errors;
@@ -23,7 +40,51 @@ mod tests {
     fn version_parse() {
         let version = config::ServerVersion::new("1.12.2-4").unwrap();
         assert_eq!(version.patch.unwrap(), 4);
-        assert_eq!(version.minecraft, config::MinecraftVersion(1, 12, Some(2)));
+        assert_eq!(
+            version.minecraft,
+            config::MinecraftVersion::release(1, 12, Some(2))
+        );
+    }
+
+    #[test]
+    fn version_stages() {
+        let mut versions: Vec<config::MinecraftVersion> = [
+            "1.20.5",
+            "1.20.5-rc1",
+            "24w14a",
+            "1.20.4",
+            "1.20.5-pre2",
+            "23w51b",
+            "1.20.5-pre1",
+        ]
+        .iter()
+        .map(|v| v.parse().unwrap())
+        .collect();
+        versions.sort();
+        let sorted: Vec<String> = versions.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            sorted,
+            [
+                "1.20.4",
+                "23w51b",
+                "24w14a",
+                "1.20.5-pre1",
+                "1.20.5-pre2",
+                "1.20.5-rc1",
+                "1.20.5"
+            ]
+        );
+
+        for text in &["23w31a", "1.20.5-pre1", "1.20.5-rc1-3", "1.21-7"] {
+            let version = config::ServerVersion::new(text).unwrap();
+            assert_eq!(
+                &toml::to_string(&version).unwrap(),
+                &format!("\"{}\"", text)
+            );
+        }
+        assert_eq!(config::ServerVersion::new("23w31a").unwrap().patch, None);
+        assert!("1.20.5-beta1".parse::<config::MinecraftVersion>().is_err());
+        assert!("1.20.5.1".parse::<config::MinecraftVersion>().is_err());
     }
 
     #[test]
@@ -34,7 +95,9 @@ mod tests {
 
     #[test]
     fn config_parse() {
-        let config = config::ServerConfig::new("name = 'something'\nversion = '1.12.2-4'\nkind='paper'").unwrap();
+        let config =
+            config::ServerConfig::new("name = 'something'\nversion = '1.12.2-4'\nkind='paper'")
+                .unwrap();
         assert_eq!(config.name, "something");
         assert_eq!(
             config.version,
@@ -43,7 +106,25 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected = "invalid server configuration")]
+    fn config_schema() {
+        let base = "name = 'a'\nversion = '1.20.4'\nkind = 'paper'\n";
+        let (_, from) =
+            config::ServerConfig::parse(&format!("{}schema_version = 1", base)).unwrap();
+        assert_eq!(from, 1);
+        assert!(config::ServerConfig::new(&format!("{}max_heep = '4G'", base)).is_err());
+        assert!(config::ServerConfig::new(&format!("{}schema_version = 99", base)).is_err());
+        // A heap flag contradicting a heap setting is left alone
+        let config = config::ServerConfig::new(&format!(
+            "{}max_heap = '2G'\nextra_java_args = ['-Xmx4G', '-Xms1G']",
+            base
+        ))
+        .unwrap();
+        assert_eq!(config.extra_java_args, ["-Xmx4G"]);
+        assert_eq!(config.min_heap.unwrap().to_string(), "1G");
+    }
+
+    #[test]
+    #[should_panic(expected = "InvalidConfig(\"server configuration\")")]
     fn incorrect_config_parse() {
         let config = config::ServerConfig::new("version = '1.12.2-4'").unwrap();
         assert_eq!(config.name, "something");
@@ -52,4 +133,851 @@ mod tests {
             config::ServerVersion::new("1.12.2-4").unwrap()
         );
     }
+
+    #[test]
+    fn chunker_is_content_defined() {
+        use backup::chunker::{Chunker, ChunkerParams};
+        let chunker = Chunker::new(
+            ChunkerParams {
+                min_size: 64,
+                avg_size: 256,
+                max_size: 1024,
+            },
+            0,
+        );
+        let data: Vec<u8> = (0..20_000u32)
+            .map(|i| (i * 7919 % 251) as u8 ^ (i >> 5) as u8)
+            .collect();
+        let split = |data: &[u8]| {
+            let mut chunks = vec![];
+            chunker
+                .chunk_reader(data, |c| {
+                    chunks.push(c.to_vec());
+                    Ok(())
+                })
+                .unwrap();
+            chunks
+        };
+        let original = split(&data);
+        assert_eq!(original.concat(), data);
+
+        // Inserting bytes at the start only changes the first few chunks
+        let mut shifted = vec![1, 2, 3];
+        shifted.extend(&data);
+        let changed = split(&shifted);
+        let common = original.iter().filter(|c| changed.contains(c)).count();
+        assert!(common >= original.len() - 2);
+    }
+
+    #[test]
+    fn backup_roundtrip() {
+        let source = tempfile::tempdir().unwrap();
+        let repo_dir = tempfile::tempdir().unwrap();
+        let restored = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(source.path().join("worlds/world")).unwrap();
+        std::fs::write(
+            source.path().join("worlds/world/level.dat"),
+            vec![42u8; 300_000],
+        )
+        .unwrap();
+        std::fs::write(source.path().join("eula.txt"), "eula=true\n").unwrap();
+
+        let repo = backup::Repository::init(repo_dir.path(), Some("hunter2")).unwrap();
+        let (first, stats) = repo.backup("survival", source.path()).unwrap();
+        assert_eq!(stats.files, 2);
+        let (second, stats) = repo.backup("survival", source.path()).unwrap();
+        assert_eq!(stats.unchanged_files, 2);
+        assert_eq!(stats.new_chunks, 0);
+        assert_eq!(second.parent.as_ref(), Some(&first.id));
+
+        assert!(backup::Repository::open(repo_dir.path(), Some("wrong")).is_err());
+        let repo = backup::Repository::open(repo_dir.path(), Some("hunter2")).unwrap();
+        std::fs::write(repo_dir.path().join("snapshots/interrupted.tmp"), "").unwrap();
+        assert!(repo.check(true).unwrap().errors.is_empty());
+        let mut escaping = repo.find_snapshot(&first.id).unwrap();
+        escaping.entries.push(backup::Entry {
+            path: "../escaped".to_owned(),
+            kind: backup::EntryKind::Dir,
+        });
+        assert!(repo
+            .restore(&escaping, &restored.path().join("escaping"))
+            .is_err());
+        assert!(!restored.path().join("escaped").exists());
+        repo.restore(
+            &repo.find_snapshot(&first.id).unwrap(),
+            &restored.path().join("out"),
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read(restored.path().join("out/worlds/world/level.dat")).unwrap(),
+            vec![42u8; 300_000]
+        );
+
+        let stats = repo.prune(1).unwrap();
+        assert_eq!(stats.removed_snapshots, 1);
+        assert_eq!(stats.removed_chunks, 0);
+        assert_eq!(repo.snapshots().unwrap().len(), 1);
+
+        // Listing skips a corrupt snapshot, check reports it, and pruning
+        // refuses to guess which chunks it used
+        std::fs::write(repo_dir.path().join("snapshots/corrupt.json"), "garbage").unwrap();
+        assert_eq!(repo.snapshots().unwrap().len(), 1);
+        assert_eq!(repo.check(false).unwrap().errors.len(), 1);
+        assert!(repo.prune(1).is_err());
+    }
+
+    struct StandInRepository;
+
+    #[async_trait::async_trait]
+    impl plugins::sources::PluginRepository for StandInRepository {
+        async fn versions(
+            &self,
+            _project: &str,
+        ) -> errors::Result<Vec<plugins::sources::RemoteVersion>> {
+            let version = |version: &str, game_versions: &[&str], published: &str| {
+                plugins::sources::RemoteVersion {
+                    version: version.to_owned(),
+                    game_versions: game_versions.iter().map(|v| v.to_string()).collect(),
+                    published: Some(published.to_owned()),
+                    ..Default::default()
+                }
+            };
+            // Not in any particular order, like some APIs list them
+            Ok(vec![
+                version("2.1.0", &["1.19.4", "1.20.1"], "2023-06-10T12:00:00Z"),
+                version("3.0.0", &["1.20.4"], "2024-01-02T09:30:00.5Z"),
+                version("2.0.0", &["1.18.2-1.19.4"], "2022-12-01T18:45:00Z"),
+            ])
+        }
+
+        async fn download(
+            &self,
+            _file: &plugins::sources::RemoteFile,
+            _dest: &std::path::Path,
+        ) -> errors::Result<()> {
+            Err("resolving never downloads".into())
+        }
+    }
+
+    #[tokio::test]
+    async fn plugin_resolution() {
+        let resolve = |constraint: &str, minecraft: &str| {
+            let constraint = constraint.parse().unwrap();
+            let minecraft = minecraft.parse().unwrap();
+            async move {
+                plugins::resolve("test", &constraint, &StandInRepository, "test", &minecraft)
+                    .await
+                    .map(|v| v.version)
+            }
+        };
+        assert_eq!(resolve("*", "1.20.4").await.unwrap(), "3.0.0");
+        assert_eq!(resolve("*", "1.19.4").await.unwrap(), "2.1.0");
+        assert_eq!(resolve("2.0.x", "1.19").await.unwrap(), "2.0.0");
+        assert!(resolve("<2", "1.20.4").await.is_err());
+        assert!(resolve("2.x", "1.20.4").await.is_err());
+    }
+
+    #[test]
+    fn mod_metadata_from_jar() {
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().unwrap();
+        let jar = dir.path().join("test.jar");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&jar).unwrap());
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        zip.start_file("META-INF/mods.toml", options).unwrap();
+        zip.write_all(
+            b"[[mods]]\nmodId = \"first\"\nversion = \"1.0\"\n\
+              [[mods]]\nmodId = \"second\"\n\
+              [[dependencies.second]]\nmodId = \"minecraft\"\nside = \"BOTH\"\n",
+        )
+        .unwrap();
+        zip.finish().unwrap();
+
+        let metadata = mods::ModMetadata::read(&jar).unwrap();
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(metadata[0].id, "first");
+        assert_eq!(metadata[0].version, "1.0");
+        assert!(!metadata[1].client_only);
+    }
+
+    #[test]
+    fn mod_removal() {
+        let home = tempfile::tempdir().unwrap();
+        let paths = paths::Paths::from_home(home.path());
+        let version = config::ServerVersion::new("1.20.1").unwrap();
+        let server =
+            servers::Server::create(&paths, "modded", version, "fabric".to_owned()).unwrap();
+        let mods_dir = server.config.mods_path();
+        std::fs::create_dir_all(&mods_dir).unwrap();
+        for jar in &["sodium.jar", "lithium.jar", "api.jar"] {
+            std::fs::write(mods_dir.join(jar), "").unwrap();
+        }
+        std::fs::write(
+            server.config.mods_manifest_path(),
+            r#"
+            [mods.sodium]
+            source = "modrinth"
+            project = "sodium"
+            [mods.lithium]
+            source = "modrinth"
+            project = "lithium"
+            [installed."modrinth:a"]
+            name = "sodium"
+            version = "1.0"
+            file = "sodium.jar"
+            [installed."modrinth:b"]
+            name = "lithium"
+            version = "1.0"
+            file = "lithium.jar"
+            [installed."modrinth:c"]
+            name = "api"
+            version = "1.0"
+            file = "api.jar"
+            required_by = ["sodium"]
+            "#,
+        )
+        .unwrap();
+
+        // Nothing is resolved again, so this works offline
+        let report = mods::remove(&server.config, "sodium").unwrap();
+        assert_eq!(report.removed, vec!["sodium", "api"]);
+        assert!(report.installed.is_empty());
+        assert!(mods_dir.join("lithium.jar").exists());
+        assert!(!mods_dir.join("sodium.jar").exists());
+        assert!(!mods_dir.join("api.jar").exists());
+        let manifest = mods::ModManifest::load(&server.config).unwrap();
+        assert_eq!(manifest.installed.len(), 1);
+    }
+
+    #[test]
+    fn mod_loader() {
+        let home = tempfile::tempdir().unwrap();
+        let paths = paths::Paths::from_home(home.path());
+        let version = config::ServerVersion::new("1.20.1").unwrap();
+        let forge = servers::Server::create(&paths, "forge", version, "forge".to_owned()).unwrap();
+        let paper = servers::Server::create(&paths, "paper", version, "paper".to_owned()).unwrap();
+        // Forge only loads mods from its working directory
+        assert_eq!(
+            forge.config.mods_path(),
+            forge.config.configs_path().join("mods")
+        );
+        assert!(forge.config.mods_path().is_dir());
+
+        let mut manifest = mods::ModManifest::default();
+        assert_eq!(manifest.loader(&forge.config).unwrap(), "forge");
+        assert!(manifest.loader(&paper.config).is_err());
+        manifest.loader = Some("fabric".to_owned());
+        assert!(manifest.loader(&forge.config).is_err());
+        assert!(manifest.loader(&paper.config).is_err());
+        manifest.loader = Some("forge".to_owned());
+        assert_eq!(manifest.loader(&forge.config).unwrap(), "forge");
+    }
+
+    #[test]
+    fn modpack_from_archive() {
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.mrpack");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        zip.start_file("modrinth.index.json", options).unwrap();
+        zip.write_all(
+            br#"{"formatVersion": 1, "game": "minecraft", "versionId": "1.0", "name": "Test",
+                "files": [], "dependencies": {"minecraft": "1.20.1", "fabric-loader": "0.15.7"}}"#,
+        )
+        .unwrap();
+        zip.start_file("overrides/config/test.json", options)
+            .unwrap();
+        zip.finish().unwrap();
+
+        let pack = modpacks::Modpack::read(&path).unwrap();
+        assert_eq!(pack.format, modpacks::ModpackFormat::Modrinth);
+        let (kind, version) = pack.server_version().unwrap();
+        assert_eq!(kind.to_string(), "fabric");
+        assert_eq!(version.to_string(), "1.20.1-15007");
+    }
+
+    #[test]
+    fn java_selection() {
+        let runtime = |major: u32| java::JavaRuntime {
+            path: format!("/usr/lib/jvm/java-{}/bin/java", major).into(),
+            version: major.to_string(),
+            major,
+        };
+        let runtimes = vec![runtime(8), runtime(17), runtime(21)];
+        let select = |required| java::select(&runtimes, required).map(|r| r.major);
+        assert_eq!(select(8), Some(8));
+        assert_eq!(select(16), Some(17));
+        assert_eq!(select(21), Some(21));
+        assert_eq!(java::select(&runtimes[1..], 8), None);
+    }
+
+    #[test]
+    fn jvm_args_validation() {
+        let config = |extra: &str| {
+            config::ServerConfig::new(&format!(
+                "name = 't'\nversion = '1.20.4'\nkind = 'paper'\nmax_heap = '16G'\n\
+                 jvm_preset = 'zgc'\nextra_java_args = [{}]",
+                extra
+            ))
+            .unwrap()
+        };
+        let args = jvm::java_args(&config("'-Dfoo=bar'")).unwrap();
+        assert_eq!(args[0], "-Xmx16G");
+        assert!(args.contains(&"-XX:+UseZGC".to_owned()));
+        assert_eq!(args.last().unwrap(), "-Dfoo=bar");
+        assert!(jvm::java_args(&config("'-Xmx2G'")).is_err());
+        assert!(jvm::java_args(&config("'-XX:+UseG1GC'")).is_err());
+    }
+
+    #[test]
+    fn level_dat_version() {
+        use std::io::Write;
+
+        fn named(kind: u8, name: &str, out: &mut Vec<u8>) {
+            out.push(kind);
+            out.extend_from_slice(&(name.len() as u16).to_be_bytes());
+            out.extend_from_slice(name.as_bytes());
+        }
+        let mut nbt = vec![];
+        named(10, "", &mut nbt);
+        named(10, "Data", &mut nbt);
+        named(3, "DataVersion", &mut nbt);
+        nbt.extend_from_slice(&3465i32.to_be_bytes());
+        named(10, "Version", &mut nbt);
+        named(8, "Name", &mut nbt);
+        nbt.extend_from_slice(&6u16.to_be_bytes());
+        nbt.extend_from_slice(b"1.20.1");
+        nbt.extend_from_slice(&[0, 0, 0]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("level.dat");
+        let mut encoder = flate2::write::GzEncoder::new(
+            std::fs::File::create(&path).unwrap(),
+            flate2::Compression::default(),
+        );
+        encoder.write_all(&nbt).unwrap();
+        encoder.finish().unwrap();
+
+        let (data_version, name) = upgrade::read_level_dat(&path).unwrap();
+        assert_eq!(data_version, 3465);
+        assert_eq!(name.as_deref(), Some("1.20.1"));
+        assert!(data_version > upgrade::data_version(&"1.20".parse().unwrap()).unwrap());
+    }
+
+    #[test]
+    fn import_detection() {
+        use std::io::Write;
+
+        let paper = tempfile::tempdir().unwrap();
+        std::fs::write(
+            paper.path().join("version_history.json"),
+            r#"{"currentVersion": "git-Paper-550 (MC: 1.19.4)"}"#,
+        )
+        .unwrap();
+        let (kind, version) = import::detect(paper.path()).unwrap().unwrap();
+        assert_eq!(kind.to_string(), "paper");
+        assert_eq!(version.to_string(), "1.19.4-550");
+
+        let fabric = tempfile::tempdir().unwrap();
+        let jar = std::fs::File::create(fabric.path().join("fabric-server-launch.jar")).unwrap();
+        let mut zip = zip::ZipWriter::new(jar);
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        zip.start_file("install.properties", options).unwrap();
+        zip.write_all(b"fabric-loader-version=0.15.7\ngame-version=1.20.1\n")
+            .unwrap();
+        zip.finish().unwrap();
+        let (kind, version) = import::detect(fabric.path()).unwrap().unwrap();
+        assert_eq!(kind.to_string(), "fabric");
+        assert_eq!(version.to_string(), "1.20.1-15007");
+
+        assert!(import::detect(tempfile::tempdir().unwrap().path())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn archive_export() {
+        let source = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(source.path().join("worlds/world")).unwrap();
+        std::fs::write(source.path().join("worlds/world/level.dat"), b"level").unwrap();
+        std::fs::write(source.path().join("plugins.toml.part"), b"partial").unwrap();
+        let mut config =
+            config::ServerConfig::new("name = 'exported'\nversion = '1.20.1-100'\nkind = 'paper'")
+                .unwrap();
+        config.directory = Some(source.path().to_owned());
+        let server = servers::Server { config };
+
+        let dest = tempfile::tempdir().unwrap();
+        let path = dest.path().join("exported.zip");
+        archive::export(&server, &path).unwrap();
+
+        let (manifest, config) = archive::read(&path).unwrap();
+        assert_eq!(manifest.name, "exported");
+        assert_eq!(manifest.version.to_string(), "1.20.1-100");
+        assert_eq!(config.directory, None);
+        let mut zip = zip::ZipArchive::new(std::fs::File::open(&path).unwrap()).unwrap();
+        assert!(zip.by_name("files/worlds/world/level.dat").is_ok());
+        assert!(zip.by_name("files/plugins.toml.part").is_err());
+
+        let home = tempfile::tempdir().unwrap();
+        let paths = paths::Paths::from_home(home.path());
+        archive::restore(&paths, "restored", &path, false).unwrap();
+        assert!(archive::restore(&paths, "restored", &path, false).is_err());
+        let (restored, _) = archive::restore(&paths, "restored", &path, true).unwrap();
+        assert!(restored
+            .config
+            .path()
+            .join("worlds/world/level.dat")
+            .is_file());
+        assert!(!paths.server(".restored.restoring").exists());
+
+        // Flat servers keep their jar next to everything else
+        let mut server = server;
+        server.config.layout = config::ServerLayout::Flat;
+        std::fs::write(source.path().join("paper-1.20.1.jar"), b"jar").unwrap();
+        std::fs::create_dir_all(source.path().join("libraries")).unwrap();
+        archive::export(&server, &path).unwrap();
+        let mut zip = zip::ZipArchive::new(std::fs::File::open(&path).unwrap()).unwrap();
+        assert!(zip.by_name("files/worlds/world/level.dat").is_ok());
+        assert!(zip.by_name("files/paper-1.20.1.jar").is_err());
+        assert!(zip.by_name("files/libraries/").is_err());
+    }
+
+    #[test]
+    fn template_config() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("template.toml"),
+            "kind = 'paper'\nmax_heap = '4G'\njvm_preset = 'aikar'\ndirectory = '/elsewhere'",
+        )
+        .unwrap();
+        assert!(templates::config(dir.path(), "minigame", None).is_err());
+
+        let version = config::ServerVersion::new("1.20.4").unwrap();
+        let config = templates::config(dir.path(), "minigame", Some(version)).unwrap();
+        assert_eq!(config.name, "minigame");
+        assert_eq!(config.version, version);
+        assert_eq!(config.max_heap.unwrap().to_string(), "4G");
+        assert_eq!(config.jvm_preset, Some(config::JvmPreset::Aikar));
+        assert_eq!(config.directory, None);
+
+        let mut config = config;
+        config.directory = Some(dir.path().to_owned());
+        std::fs::create_dir(dir.path().join("configs")).unwrap();
+        assert_eq!(properties::server_port(&config).unwrap(), 25565);
+        properties::set_in_file(
+            &config.configs_path().join("server.properties"),
+            &[("server-port", "25570")],
+        )
+        .unwrap();
+        assert_eq!(properties::server_port(&config).unwrap(), 25570);
+    }
+
+    #[test]
+    fn settings_layers() {
+        use settings::{GlobalConfig, Layers, Origin};
+        let layers = Layers {
+            paths: paths::Paths::from_home("/srv/rustone".as_ref()),
+            global: toml::from_str("max_heap = '8G'\njvm_preset = 'aikar'\noffline = true")
+                .unwrap(),
+            env: GlobalConfig::from_vars(vec![
+                ("RUSTONE_JVM_PRESET".to_owned(), "zgc".to_owned()),
+                ("RUSTONE_BACKUP_PASSPHRASE".to_owned(), "secret".to_owned()),
+            ])
+            .unwrap(),
+        };
+        let server = config::ServerConfig::new(
+            "name = 'a'\nversion = '1.20.4'\nkind = 'paper'\nmax_heap = '2G'",
+        )
+        .unwrap();
+
+        let effective = layers.effective(Some(&server));
+        assert_eq!(effective.max_heap.value.to_string(), "2G");
+        assert_eq!(effective.max_heap.origin, Origin::Server);
+        assert_eq!(effective.jvm_preset.value, config::JvmPreset::Zgc);
+        assert_eq!(
+            effective.jvm_preset.origin,
+            Origin::Environment("RUSTONE_JVM_PRESET")
+        );
+        assert!(effective.offline.value);
+        assert_eq!(effective.min_heap.origin, Origin::Default);
+        assert_eq!(layers.effective(None).max_heap.value.to_string(), "8G");
+        assert_eq!(
+            effective.backup_target.value,
+            std::path::Path::new("/srv/rustone/data/.backups")
+        );
+
+        let network = Layers {
+            global: toml::from_str("[network]\nproxy = 'http://proxy:3128'\nread_timeout = 60")
+                .unwrap(),
+            env: GlobalConfig::from_vars(vec![
+                ("RUSTONE_NO_PROXY".to_owned(), "localhost, .lan".to_owned()),
+                ("RUSTONE_IP_VERSION".to_owned(), "ipv4".to_owned()),
+            ])
+            .unwrap(),
+            ..Default::default()
+        }
+        .effective(None)
+        .network;
+        assert_eq!(network.proxy.value.as_deref(), Some("http://proxy:3128"));
+        assert_eq!(network.read_timeout.value, 60);
+        assert_eq!(network.connect_timeout.origin, Origin::Default);
+        assert_eq!(network.no_proxy.value, vec!["localhost", ".lan"]);
+        assert_eq!(network.ip_version.value, settings::IpVersion::Ipv4);
+        assert!(GlobalConfig::from_vars(vec![(
+            "RUSTONE_IP_VERSION".to_owned(),
+            "ipv5".to_owned()
+        )])
+        .is_err());
+
+        assert!(toml::from_str::<GlobalConfig>("max_heep = '8G'").is_err());
+        assert!(
+            GlobalConfig::from_vars(vec![("RUSTONE_OFFLINE".to_owned(), "maybe".to_owned())])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn separate_homes() {
+        let (a, b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let (a, b) = (
+            paths::Paths::from_home(a.path()),
+            paths::Paths::from_home(b.path()),
+        );
+        assert!(servers::get_servers(&a).unwrap().is_empty());
+
+        let version = config::ServerVersion::new("1.20.4").unwrap();
+        servers::Server::create(&a, "survival", version, "paper".to_owned()).unwrap();
+        assert!(a.server_config("survival").is_file());
+        assert!(a.server("survival").join("configs/eula.txt").is_file());
+
+        let server = servers::Server::get(&a, "survival").unwrap();
+        assert_eq!(server.config.paths, a);
+        assert_eq!(servers::get_servers(&a).unwrap().len(), 1);
+        assert!(servers::Server::get(&b, "survival").is_err());
+        assert!(servers::get_servers(&b).unwrap().is_empty());
+        servers::Server::create(&b, "survival", version, "paper".to_owned()).unwrap();
+    }
+
+    #[test]
+    fn error_categories() {
+        use errors::{Category, ResultExt};
+        let home = tempfile::tempdir().unwrap();
+        let paths = paths::Paths::from_home(home.path());
+        let category = |result: errors::Result<servers::Server>| match result {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => e.category(),
+        };
+
+        assert_eq!(
+            category(servers::Server::get(&paths, "missing")),
+            Category::NotFound
+        );
+        let version = config::ServerVersion::new("1.20.4").unwrap();
+        servers::Server::create(&paths, "a", version, "paper".to_owned()).unwrap();
+        assert_eq!(
+            category(servers::Server::create(
+                &paths,
+                "a",
+                version,
+                "paper".to_owned()
+            )),
+            Category::AlreadyExists
+        );
+        std::fs::write(paths.server_config("broken"), "name = ").unwrap();
+        assert_eq!(
+            category(servers::Server::get(&paths, "broken")),
+            Category::InvalidConfig
+        );
+
+        let io: errors::Result<String> = std::fs::read_to_string(home.path().join("nothing"))
+            .chain_err(|| "failed to read")
+            .chain_err(|| "failed to do something");
+        assert_eq!(io.unwrap_err().category(), Category::Io);
+        assert_eq!(
+            errors::Error::from("just a message").category(),
+            Category::Other
+        );
+    }
+
+    #[tokio::test]
+    async fn metadata_cache() {
+        use metadata::{fetch, read_entry, write_entry, Entry};
+        let home = tempfile::tempdir().unwrap();
+        let paths = paths::Paths::from_home(home.path());
+        let url = "https://example.invalid/versions";
+        assert!(read_entry(&paths, url).is_none());
+
+        let entry = Entry {
+            url: url.to_owned(),
+            fetched: 1000,
+            etag: Some("\"abc\"".to_owned()),
+            last_modified: None,
+            body: "[\"1.20.4\"]".to_owned(),
+        };
+        assert!(entry.is_fresh(1599, 600));
+        assert!(!entry.is_fresh(1600, 600));
+        write_entry(&paths, &entry).unwrap();
+        assert_eq!(read_entry(&paths, url), Some(entry.clone()));
+        assert!(read_entry(&paths, "https://example.invalid/other").is_none());
+
+        // Offline, stale responses are used rather than failing
+        std::fs::create_dir_all(&paths.config).unwrap();
+        std::fs::write(paths.global_config(), "offline = true").unwrap();
+        assert_eq!(fetch(&paths, url).await.unwrap(), Some(entry.body));
+
+        metadata::clear(&paths).unwrap();
+        assert!(read_entry(&paths, url).is_none());
+    }
+
+    #[test]
+    fn jar_store() {
+        use cacher::{cache_jar, get_cached, prune, read_index, JarKey};
+        use std::io::Write;
+        let home = tempfile::tempdir().unwrap();
+        let paths = paths::Paths::from_home(home.path());
+        let key = |build| JarKey {
+            kind: "paper".to_owned(),
+            version: "1.20.4".parse().unwrap(),
+            build,
+        };
+        let store = |build, contents: &[u8], upstream: Option<&str>| {
+            let mut writer = cache_jar(
+                &paths,
+                key(build),
+                Some(format!("https://example.invalid/{}", build)),
+                upstream.map(str::to_owned),
+            )
+            .unwrap();
+            writer.write_all(contents).unwrap();
+            writer.finish()
+        };
+
+        let old = store(495, b"old", None).unwrap();
+        let new = store(496, b"new", None).unwrap();
+        assert_ne!(old, new);
+        assert_eq!(std::fs::read(&old).unwrap(), b"old");
+        let (path, record) = get_cached(&paths, &key(496)).unwrap().unwrap();
+        assert_eq!(path, new);
+        assert_eq!(record.url.as_deref(), Some("https://example.invalid/496"));
+        assert_eq!(key(496).to_string().parse::<JarKey>().unwrap(), key(496));
+
+        // Identical jars share a blob, corrupt ones are not stored
+        assert_eq!(store(497, b"new", None).unwrap(), new);
+        assert!(store(498, b"new", Some("00")).is_err());
+        assert!(get_cached(&paths, &key(498)).unwrap().is_none());
+
+        let keep = [key(496)].iter().cloned().collect();
+        assert_eq!(prune(&paths, &keep).unwrap(), vec![key(495), key(497)]);
+        assert!(!old.exists() && new.exists());
+
+        // Jars cached by older versions are moved into the store
+        std::fs::remove_file(paths.jar_index()).unwrap();
+        std::fs::write(paths.cache.join("fabric-1.20.1.jar"), b"fabric").unwrap();
+        std::fs::write(paths.cache_meta(), "[jars]\n\"fabric@1.20.1\" = 15007\n").unwrap();
+        let index = read_index(&paths).unwrap();
+        let migrated = JarKey {
+            kind: "fabric".to_owned(),
+            version: "1.20.1".parse().unwrap(),
+            build: 15007,
+        };
+        assert!(index.jars.contains_key(&migrated));
+        assert!(get_cached(&paths, &migrated).unwrap().is_some());
+        assert!(!paths.cache_meta().exists());
+    }
+
+    #[tokio::test]
+    async fn update_rollback() {
+        use std::{process::Stdio, time::Duration};
+        use updates::{apply, is_bad, roll_back, watch_startup, Startup};
+        let mut config =
+            config::ServerConfig::new("name = 'a'\nversion = '1.20.4-495'\nkind = 'paper'")
+                .unwrap();
+        let new = config::ServerVersion::new("1.20.4-496").unwrap();
+        apply(&mut config, new);
+        apply(
+            &mut config,
+            config::ServerVersion::new("1.20.4-497").unwrap(),
+        );
+        assert_eq!(config.rollback_to.unwrap().patch, Some(495));
+
+        assert_eq!(roll_back(&mut config).unwrap().patch, Some(497));
+        assert_eq!(config.version.patch, Some(495));
+        assert!(config.rollback_to.is_none() && roll_back(&mut config).is_none());
+        assert!(is_bad(
+            &config,
+            &config::ServerVersion::new("1.20.4-497").unwrap()
+        ));
+        assert!(!is_bad(&config, &new));
+
+        let home = tempfile::tempdir().unwrap();
+        config.paths = paths::Paths::from_home(home.path());
+        assert!(updates::read_log(&config).unwrap().is_empty());
+        let build = server_kinds::Build {
+            number: 495,
+            time: None,
+            channel: "default".to_owned(),
+            changes: vec![server_kinds::Change {
+                commit: Some("abc1234".to_owned()),
+                summary: "Fix chunk loading".to_owned(),
+            }],
+            sha256: None,
+        };
+        updates::log_update(&config, new, &[build]).unwrap();
+        let log = updates::read_log(&config).unwrap();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].to.patch, Some(495));
+        assert_eq!(log[0].changes[0].summary, "Fix chunk loading");
+
+        let spawn = |script: &str| {
+            tokio::process::Command::new("sh")
+                .args(["-c", script])
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap()
+        };
+        let timeout = Duration::from_secs(5);
+        let mut child =
+            spawn("echo '[12:00:00 INFO]: Done (1.0s)! For help, type \"help\"'; sleep 1");
+        assert_eq!(
+            watch_startup(&mut child, timeout, |_| {}).await.unwrap(),
+            Startup::Done
+        );
+        let mut child = spawn("echo 'Starting'; exit 3");
+        assert_eq!(
+            watch_startup(&mut child, timeout, |_| {}).await.unwrap(),
+            Startup::Exited(Some(3))
+        );
+        let mut child = spawn("sleep 10");
+        assert_eq!(
+            watch_startup(&mut child, Duration::from_millis(100), |_| {})
+                .await
+                .unwrap(),
+            Startup::TimedOut(Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    fn upgrade_apply() {
+        let mut config = config::ServerConfig::new(
+            "name = 'a'\nversion = '1.20.4-496'\nkind = 'paper'\nselector = '1.20.x'\nrollback_to = '1.20.4-495'\nbad_builds = ['1.20.4-497', '1.21-10']",
+        )
+        .unwrap();
+        let target = config::ServerVersion::new("1.21.1-20").unwrap();
+        upgrade::apply(&mut config, target);
+        assert_eq!(config.version.patch, Some(20));
+        assert!(config.rollback_to.is_none());
+        assert_eq!(config.bad_builds.len(), 1);
+        assert_eq!(config.selector.unwrap().to_string(), "1.21.x");
+
+        config.selector = Some("1.21.1@20".parse().unwrap());
+        upgrade::apply(&mut config, config::ServerVersion::new("1.21.4-5").unwrap());
+        assert!(config.selector.is_none());
+    }
+
+    #[tokio::test]
+    async fn resumed_download() {
+        use std::time::Duration;
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::TcpListener,
+        };
+        let body: Vec<u8> = (0..200u8).collect();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/server.jar", listener.local_addr().unwrap());
+        let served = body.clone();
+        let server = tokio::spawn(async move {
+            let mut ranges = vec![];
+            for attempt in 0..3 {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = vec![0; 1024];
+                let n = socket.read(&mut request).await.unwrap();
+                let request = String::from_utf8_lossy(&request[..n]).to_lowercase();
+                let from = request
+                    .lines()
+                    .find_map(|l| l.strip_prefix("range: bytes="))
+                    .map_or(0, |r| r.trim_end_matches('-').parse::<usize>().unwrap());
+                ranges.push(from);
+                // The rest of the file comes back from before where it was
+                // asked for
+                let start = from.saturating_sub(50);
+                let head = match attempt {
+                    0 => "HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\n\r\n".to_owned(),
+                    _ if from > 0 => format!(
+                        "HTTP/1.1 206 Partial Content\r\ncontent-length: {}\r\ncontent-range: bytes {}-199/200\r\n\r\n",
+                        200 - start,
+                        start
+                    ),
+                    _ => "HTTP/1.1 200 OK\r\ncontent-length: 200\r\n\r\n".to_owned(),
+                };
+                socket.write_all(head.as_bytes()).await.unwrap();
+                // The first body is cut off halfway
+                let end = if attempt == 1 { 100 } else { 200 };
+                if attempt > 0 {
+                    socket.write_all(&served[start..end]).await.unwrap();
+                }
+                socket.shutdown().await.unwrap();
+            }
+            ranges
+        });
+
+        let options = download::DownloadOptions {
+            backoff: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let home = tempfile::tempdir().unwrap();
+        let paths = paths::Paths::from_home(home.path());
+        let mut out = vec![];
+        let mut last = download::Progress::default();
+        let size = download::get(&paths, &url, &mut out, &options, |p| last = p)
+            .await
+            .unwrap();
+        assert_eq!(size, 200);
+        assert_eq!(out, body);
+        assert_eq!(last.total, Some(200));
+        assert_eq!(server.await.unwrap(), vec![0, 0, 100]);
+    }
+
+    #[tokio::test]
+    async fn prepare_server() {
+        use std::io::Write;
+        let home = tempfile::tempdir().unwrap();
+        let paths = paths::Paths::from_home(home.path());
+        std::fs::create_dir_all(&paths.config).unwrap();
+        std::fs::write(paths.global_config(), "java_path = '/usr/bin/java'").unwrap();
+        let mut server = servers::Server::create(
+            &paths,
+            "survival",
+            config::ServerVersion::new("1.20.4-496").unwrap(),
+            "paper".to_owned(),
+        )
+        .unwrap();
+        let mut writer = cacher::cache_jar(
+            &paths,
+            cacher::JarKey::of(&server.config).unwrap(),
+            None,
+            None,
+        )
+        .unwrap();
+        writer.write_all(b"jar").unwrap();
+        let jar = writer.finish().unwrap();
+        std::fs::remove_file(server.config.configs_path().join("eula.txt")).unwrap();
+
+        let options = provision::ProvisionOptions {
+            update: false,
+            ..Default::default()
+        };
+        let prepared = server.prepare(&options, &()).await.unwrap();
+        assert_eq!(prepared.jar, jar);
+        assert_eq!(prepared.java, std::path::Path::new("/usr/bin/java"));
+        assert!(prepared.update.is_none());
+        assert!(server.config.configs_path().join("eula.txt").is_file());
+        assert!(server.config.path().join("plugins").is_dir());
+    }
 }
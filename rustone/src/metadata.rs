@@ -0,0 +1,181 @@
+//! A cache of upstream API responses, like version and build lists.
+//!
+//! Responses are kept for `metadata_ttl` seconds. After that they are
+//! revalidated with `If-None-Match` and `If-Modified-Since`, so an unchanged
+//! list costs a `304 Not Modified`. In offline mode, or when the upstream
+//! fails, cached responses are used however old they are.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use error_chain::bail;
+use lazy_static::lazy_static;
+use reqwest::{
+    header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    StatusCode,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+
+use crate::{errors::*, paths::Paths, plugins::sources::http_client, settings::Layers};
+
+lazy_static! {
+    /// A lock per URL, so that a burst of requests for the same metadata
+    /// makes a single upstream request.
+    static ref IN_FLIGHT: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// A cached response.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Entry {
+    pub url: String,
+    /// When the response was fetched or last revalidated, in seconds since
+    /// the Unix epoch
+    pub fetched: u64,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub body: String,
+}
+
+impl Entry {
+    pub fn is_fresh(&self, now: u64, ttl: u64) -> bool {
+        now.saturating_sub(self.fetched) < ttl
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+fn entry_path(paths: &Paths, url: &str) -> PathBuf {
+    let hash = hex::encode(Sha256::digest(url.as_bytes()));
+    paths.metadata_cache().join(format!("{}.json", hash))
+}
+
+/// The cached response for `url`. Unreadable entries are treated as
+/// missing, they are replaced by the next response.
+pub(crate) fn read_entry(paths: &Paths, url: &str) -> Option<Entry> {
+    let text = fs::read_to_string(entry_path(paths, url)).ok()?;
+    serde_json::from_str::<Entry>(&text)
+        .ok()
+        .filter(|entry| entry.url == url)
+}
+
+pub(crate) fn write_entry(paths: &Paths, entry: &Entry) -> Result<()> {
+    let path = entry_path(paths, &entry.url);
+    fs::create_dir_all(paths.metadata_cache())
+        .chain_err(|| "failed to create metadata cache directory")?;
+    let text = serde_json::to_string(entry).chain_err(|| "failed to serialize metadata")?;
+    // Written aside and moved into place, so readers never see half of it
+    let temporary = path.with_extension("json.tmp");
+    fs::write(&temporary, text).chain_err(|| "failed to write metadata cache")?;
+    fs::rename(&temporary, &path).chain_err(|| "failed to write metadata cache")?;
+    Ok(())
+}
+
+/// Remove every cached response.
+pub fn clear(paths: &Paths) -> Result<()> {
+    let dir = paths.metadata_cache();
+    if dir.exists() {
+        fs::remove_dir_all(&dir).chain_err(|| "failed to remove metadata cache")?;
+    }
+    Ok(())
+}
+
+/// The body of `url`, from the cache of the instance in `paths` if it is
+/// fresh, or `None` if upstream does not have it.
+pub async fn fetch(paths: &Paths, url: &str) -> Result<Option<String>> {
+    let lock = IN_FLIGHT
+        .lock()
+        .unwrap()
+        .entry(url.to_owned())
+        .or_default()
+        .clone();
+    let _guard = lock.lock().await;
+
+    let config = Layers::load(paths).unwrap_or_default().effective(None);
+    let cached = read_entry(paths, url);
+    match &cached {
+        Some(entry) if config.offline.value || entry.is_fresh(now(), config.metadata_ttl.value) => {
+            debug!(url, "metadata cached");
+            return Ok(Some(entry.body.clone()));
+        }
+        _ => {}
+    }
+
+    let mut request = http_client(paths)?.get(url);
+    if let Some(entry) = &cached {
+        if let Some(etag) = &entry.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &entry.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+    let response = match (request.send().await, &cached) {
+        (Ok(response), _) => response,
+        // A stale list is better than none
+        (Err(e), Some(entry)) => {
+            warn!(url, error = %e, "using stale metadata");
+            return Ok(Some(entry.body.clone()));
+        }
+        (Err(e), None) => return Err(e).chain_err(|| format!("failed to request {}", url)),
+    };
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v: &reqwest::header::HeaderValue| v.to_str().ok())
+            .map(str::to_owned)
+    };
+    let (etag, last_modified) = (header(ETAG), header(LAST_MODIFIED));
+    debug!(url, status = %response.status(), "metadata requested");
+    let entry = match (response.status(), cached) {
+        (StatusCode::NOT_MODIFIED, Some(entry)) => Entry {
+            fetched: now(),
+            etag: etag.or(entry.etag),
+            last_modified: last_modified.or(entry.last_modified),
+            ..entry
+        },
+        (StatusCode::NOT_FOUND, _) => return Ok(None),
+        (status, Some(entry)) if status.is_server_error() => {
+            warn!(url, %status, "using stale metadata");
+            return Ok(Some(entry.body));
+        }
+        (status, _) if !status.is_success() => bail!(ErrorKind::UpstreamUnavailable(format!(
+            "{} (status {})",
+            url, status
+        ))),
+        _ => Entry {
+            url: url.to_owned(),
+            fetched: now(),
+            etag,
+            last_modified,
+            body: response
+                .text()
+                .await
+                .chain_err(|| format!("failed to read response of {}", url))?,
+        },
+    };
+    write_entry(paths, &entry)?;
+    Ok(Some(entry.body))
+}
+
+/// Like [`fetch`], decoding the body as JSON.
+pub async fn get_json<T: DeserializeOwned>(paths: &Paths, url: &str) -> Result<Option<T>> {
+    match fetch(paths, url).await? {
+        Some(body) => Ok(Some(
+            serde_json::from_str(&body).chain_err(|| format!("failed to decode {}", url))?,
+        )),
+        None => Ok(None),
+    }
+}
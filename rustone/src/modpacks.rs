@@ -0,0 +1,461 @@
+//! Creating servers from Modrinth (`.mrpack`) and CurseForge modpacks.
+//!
+//! A modpack lists the files to download, with the Minecraft version and the
+//! mod loader it needs, and ships override files which are copied over the
+//! server. Override paths are relative to the game directory, so `mods/` ends
+//! up in the server's mods directory and everything else in `configs`, the
+//! working directory of the server.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+use error_chain::bail;
+use serde::Deserialize;
+
+use crate::{
+    config::{MinecraftVersion, ServerConfig, ServerVersion},
+    errors::*,
+    mods::ModMetadata,
+    paths::Paths,
+    plugins::sources::{self, CurseForgeRepository, PluginRepository, RemoteFile},
+    server_kinds::{fabric, ServerKind},
+    servers::Server,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModpackFormat {
+    Modrinth,
+    CurseForge,
+}
+
+#[derive(Debug, Clone)]
+pub enum PackFileSource {
+    Download(RemoteFile),
+    CurseForge { project_id: u64, file_id: u64 },
+}
+
+/// A file the modpack needs on the server.
+#[derive(Debug, Clone)]
+pub struct PackFile {
+    /// Path relative to the game directory. Empty for CurseForge files,
+    /// which always go into `mods`.
+    pub path: String,
+    pub source: PackFileSource,
+}
+
+#[derive(Debug, Clone)]
+pub struct Modpack {
+    pub format: ModpackFormat,
+    pub name: String,
+    pub version: String,
+    pub minecraft: MinecraftVersion,
+    pub loader: String,
+    pub loader_version: Option<String>,
+    pub files: Vec<PackFile>,
+    /// Directories inside the archive whose contents are copied over the
+    /// server, in order
+    pub overrides: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct MrpackEnv {
+    server: String,
+}
+
+#[derive(Deserialize)]
+struct MrpackFile {
+    path: String,
+    #[serde(default)]
+    hashes: HashMap<String, String>,
+    env: Option<MrpackEnv>,
+    downloads: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MrpackIndex {
+    name: String,
+    version_id: String,
+    files: Vec<MrpackFile>,
+    dependencies: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct CurseForgeLoader {
+    id: String,
+    #[serde(default)]
+    primary: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CurseForgeMinecraft {
+    version: String,
+    mod_loaders: Vec<CurseForgeLoader>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CurseForgeFileRef {
+    #[serde(rename = "projectID")]
+    project_id: u64,
+    #[serde(rename = "fileID")]
+    file_id: u64,
+    #[serde(default = "default_required")]
+    required: bool,
+}
+
+fn default_required() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+struct CurseForgeManifest {
+    minecraft: CurseForgeMinecraft,
+    name: String,
+    #[serde(default)]
+    version: String,
+    files: Vec<CurseForgeFileRef>,
+    overrides: Option<String>,
+}
+
+impl Modpack {
+    /// Parse the `modrinth.index.json` of a `.mrpack`. Files which are
+    /// unsupported on servers are left out.
+    ///
+    /// # Examples
+    /// ```
+    /// use rustone::modpacks::Modpack;
+    /// let pack = Modpack::from_mrpack_index(r#"{
+    ///     "formatVersion": 1, "game": "minecraft", "versionId": "1.2", "name": "Test",
+    ///     "files": [
+    ///         {"path": "mods/a.jar", "hashes": {"sha1": "ab"}, "downloads": ["https://cdn.modrinth.com/a.jar"], "fileSize": 1},
+    ///         {"path": "mods/b.jar", "hashes": {}, "env": {"client": "required", "server": "unsupported"}, "downloads": ["https://cdn.modrinth.com/b.jar"], "fileSize": 1}
+    ///     ],
+    ///     "dependencies": {"minecraft": "1.20.1", "fabric-loader": "0.15.7"}
+    /// }"#).unwrap();
+    /// assert_eq!(pack.loader, "fabric");
+    /// assert_eq!(pack.loader_version.as_deref(), Some("0.15.7"));
+    /// assert_eq!(pack.files.len(), 1);
+    /// ```
+    pub fn from_mrpack_index(text: &str) -> Result<Self> {
+        let index: MrpackIndex =
+            serde_json::from_str(text).chain_err(|| "invalid modrinth.index.json")?;
+        let minecraft = match index.dependencies.get("minecraft") {
+            Some(version) => version
+                .parse()
+                .chain_err(|| format!("invalid Minecraft version {}", version))?,
+            None => bail!("modpack does not specify a Minecraft version"),
+        };
+        let (loader, loader_version) = [
+            ("fabric-loader", "fabric"),
+            ("quilt-loader", "quilt"),
+            ("forge", "forge"),
+            ("neoforge", "neoforge"),
+        ]
+        .iter()
+        .find_map(|(key, loader)| {
+            index
+                .dependencies
+                .get(*key)
+                .map(|v| (loader.to_string(), Some(v.clone())))
+        })
+        .ok_or_else::<Error, _>(|| "modpack does not specify a mod loader".into())?;
+        let files = index
+            .files
+            .into_iter()
+            .filter(|f| f.env.as_ref().is_none_or(|e| e.server != "unsupported"))
+            .map(|mut f| {
+                let url = match f.downloads.first() {
+                    Some(url) => url.clone(),
+                    None => bail!("modpack file {} has no download", f.path),
+                };
+                let file_name = f.path.rsplit('/').next().unwrap_or(&f.path).to_owned();
+                Ok(PackFile {
+                    source: PackFileSource::Download(RemoteFile {
+                        url,
+                        file_name,
+                        sha1: f.hashes.remove("sha1"),
+                        sha256: f.hashes.remove("sha256"),
+                        sha512: f.hashes.remove("sha512"),
+                    }),
+                    path: f.path,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            format: ModpackFormat::Modrinth,
+            name: index.name,
+            version: index.version_id,
+            minecraft,
+            loader,
+            loader_version,
+            files,
+            overrides: vec!["overrides".to_owned(), "server-overrides".to_owned()],
+        })
+    }
+
+    /// Parse the `manifest.json` of a CurseForge modpack.
+    ///
+    /// # Examples
+    /// ```
+    /// use rustone::modpacks::Modpack;
+    /// let pack = Modpack::from_curseforge_manifest(r#"{
+    ///     "minecraft": {"version": "1.20.1", "modLoaders": [{"id": "fabric-0.15.7", "primary": true}]},
+    ///     "manifestType": "minecraftModpack", "name": "Test", "version": "1.0",
+    ///     "files": [{"projectID": 306612, "fileID": 4872380, "required": true}],
+    ///     "overrides": "overrides"
+    /// }"#).unwrap();
+    /// assert_eq!(pack.loader, "fabric");
+    /// assert_eq!(pack.minecraft, "1.20.1".parse().unwrap());
+    /// ```
+    pub fn from_curseforge_manifest(text: &str) -> Result<Self> {
+        let manifest: CurseForgeManifest =
+            serde_json::from_str(text).chain_err(|| "invalid manifest.json")?;
+        let loaders = &manifest.minecraft.mod_loaders;
+        let loader = match loaders
+            .iter()
+            .find(|l| l.primary)
+            .or_else(|| loaders.first())
+        {
+            Some(loader) => &loader.id,
+            None => bail!("modpack does not specify a mod loader"),
+        };
+        let minecraft =
+            manifest.minecraft.version.parse().chain_err(|| {
+                format!("invalid Minecraft version {}", manifest.minecraft.version)
+            })?;
+        let (loader, loader_version) = match loader.split_once('-') {
+            Some((loader, version)) => (loader.to_owned(), Some(version.to_owned())),
+            None => (loader.clone(), None),
+        };
+        Ok(Self {
+            format: ModpackFormat::CurseForge,
+            name: manifest.name,
+            version: manifest.version,
+            minecraft,
+            loader,
+            loader_version,
+            files: manifest
+                .files
+                .into_iter()
+                .filter(|f| f.required)
+                .map(|f| PackFile {
+                    path: String::new(),
+                    source: PackFileSource::CurseForge {
+                        project_id: f.project_id,
+                        file_id: f.file_id,
+                    },
+                })
+                .collect(),
+            overrides: manifest.overrides.into_iter().collect(),
+        })
+    }
+
+    /// Read a modpack archive, detecting its format.
+    pub fn read(path: &Path) -> Result<Self> {
+        let mut archive = open_archive(path)?;
+        let read = |archive: &mut zip::ZipArchive<fs::File>, name: &str| {
+            let mut text = String::new();
+            io::Read::read_to_string(&mut archive.by_name(name).ok()?, &mut text).ok()?;
+            Some(text)
+        };
+        if let Some(index) = read(&mut archive, "modrinth.index.json") {
+            Self::from_mrpack_index(&index)
+        } else if let Some(manifest) = read(&mut archive, "manifest.json") {
+            Self::from_curseforge_manifest(&manifest)
+        } else {
+            bail!(
+                "{} is neither a Modrinth nor a CurseForge modpack",
+                path.to_string_lossy()
+            )
+        }
+    }
+
+    /// The server version to create: the loader version is the build of
+    /// Fabric and Forge servers. Fails for loaders without a server kind,
+    /// like Quilt and NeoForge.
+    ///
+    /// # Examples
+    /// ```
+    /// use rustone::modpacks::Modpack;
+    /// let index = |loader| format!(r#"{{
+    ///     "formatVersion": 1, "game": "minecraft", "versionId": "1.0", "name": "Test",
+    ///     "files": [], "dependencies": {{"minecraft": "1.20.1", "{}": "47.2.0"}}
+    /// }}"#, loader);
+    /// let pack = Modpack::from_mrpack_index(&index("forge")).unwrap();
+    /// let (kind, version) = pack.server_version().unwrap();
+    /// assert_eq!(kind.to_string(), "forge");
+    /// assert_eq!(version.patch, Some(47002000));
+    /// let pack = Modpack::from_mrpack_index(&index("quilt-loader")).unwrap();
+    /// assert!(pack.server_version().is_err());
+    /// ```
+    pub fn server_version(&self) -> Result<(ServerKind, ServerVersion)> {
+        let kind = match self.loader.parse::<ServerKind>() {
+            Ok(kind) if kind.mod_loader() == Some(self.loader.as_str()) => kind,
+            _ => bail!(ErrorKind::InvalidConfig(format!(
+                "mod loader {} of the modpack, only fabric and forge modpacks are supported",
+                self.loader
+            ))),
+        };
+        // Both builds are encoded the same way. Forge versions may come
+        // with the Minecraft version in front.
+        let prefix = format!("{}-", self.minecraft);
+        let patch = self.loader_version.as_deref().and_then(|version| {
+            fabric::encode_loader_version(version.strip_prefix(&prefix).unwrap_or(version))
+        });
+        Ok((
+            kind,
+            ServerVersion {
+                minecraft: self.minecraft,
+                patch,
+            },
+        ))
+    }
+}
+
+fn open_archive(path: &Path) -> Result<zip::ZipArchive<fs::File>> {
+    let file =
+        fs::File::open(path).chain_err(|| format!("failed to open {}", path.to_string_lossy()))?;
+    zip::ZipArchive::new(file)
+        .chain_err(|| format!("{} is not a valid modpack", path.to_string_lossy()))
+}
+
+/// Map a path relative to the game directory into the server.
+fn target_path(config: &ServerConfig, relative: &Path) -> Result<PathBuf> {
+    if relative
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        bail!(
+            "modpack contains an unsafe path: {}",
+            relative.to_string_lossy()
+        );
+    }
+    Ok(match relative.strip_prefix("mods") {
+        Ok(rest) => config.mods_path().join(rest),
+        Err(_) => config.path().join("configs").join(relative),
+    })
+}
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub downloaded: usize,
+    /// Files which were left out, because they are client-only mods
+    pub client_only: Vec<String>,
+    pub overrides: usize,
+}
+
+async fn download_file(
+    config: &ServerConfig,
+    file: &PackFile,
+    curseforge: &CurseForgeRepository,
+) -> Result<Option<String>> {
+    let (remote, dest) = match &file.source {
+        PackFileSource::Download(remote) => {
+            (remote.clone(), target_path(config, Path::new(&file.path))?)
+        }
+        PackFileSource::CurseForge {
+            project_id,
+            file_id,
+        } => {
+            let remote = curseforge.file(*project_id, *file_id).await?.file;
+            sources::check_file_name(&remote.file_name)?;
+            let dest = target_path(config, &Path::new("mods").join(&remote.file_name))?;
+            (remote, dest)
+        }
+    };
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent).chain_err(|| "failed to create modpack directory")?;
+    }
+    let partial = dest.with_file_name(format!(".{}.part", remote.file_name));
+    let fetched = async {
+        match &file.source {
+            PackFileSource::Download(_) => {
+                sources::download_to(&config.paths, &remote.url, &partial).await?
+            }
+            PackFileSource::CurseForge { .. } => curseforge.download(&remote, &partial).await?,
+        }
+        remote.verify(&partial)
+    }
+    .await;
+    if let Err(e) = fetched {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+    // CurseForge manifests do not say which side a mod is for
+    if file.path.is_empty() && remote.file_name.ends_with(".jar") {
+        let metadata = ModMetadata::read(&partial)?;
+        if !metadata.is_empty() && metadata.iter().all(|m| m.client_only) {
+            let _ = fs::remove_file(&partial);
+            return Ok(Some(remote.file_name));
+        }
+    }
+    fs::rename(&partial, &dest).chain_err(|| format!("failed to install {}", remote.file_name))?;
+    Ok(None)
+}
+
+/// Copy the override directories of the modpack over the server.
+fn apply_overrides(config: &ServerConfig, pack: &Modpack, archive_path: &Path) -> Result<usize> {
+    let mut archive = open_archive(archive_path)?;
+    let mut count = 0;
+    // Later directories win, so server-overrides replace overrides
+    for dir in &pack.overrides {
+        for i in 0..archive.len() {
+            let mut entry = archive
+                .by_index(i)
+                .chain_err(|| "failed to read modpack entry")?;
+            let name = match entry.enclosed_name() {
+                Some(name) => name.to_owned(),
+                None => bail!("modpack contains an unsafe path: {}", entry.name()),
+            };
+            let relative = match name.strip_prefix(dir) {
+                Ok(relative) if !entry.is_dir() && relative.components().next().is_some() => {
+                    relative.to_owned()
+                }
+                _ => continue,
+            };
+            let dest = target_path(config, &relative)?;
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent).chain_err(|| "failed to create override directory")?;
+            }
+            let mut file = fs::File::create(&dest)
+                .chain_err(|| format!("failed to create {}", dest.to_string_lossy()))?;
+            io::copy(&mut entry, &mut file)
+                .chain_err(|| format!("failed to extract {}", relative.to_string_lossy()))?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Create a server named `name` from the modpack at `path`.
+pub async fn import(paths: &Paths, name: &str, path: &Path) -> Result<(Server, ImportReport)> {
+    let pack = Modpack::read(path)?;
+    let (kind, version) = pack.server_version()?;
+    let server = Server::create(paths, name, version, kind.to_string())?;
+    let curseforge = CurseForgeRepository::new(paths, &pack.loader);
+    let filled = async {
+        let mut report = ImportReport::default();
+        for file in &pack.files {
+            match download_file(&server.config, file, &curseforge).await? {
+                Some(skipped) => report.client_only.push(skipped),
+                None => report.downloaded += 1,
+            }
+        }
+        report.overrides = apply_overrides(&server.config, &pack, path)?;
+        Ok(report)
+    }
+    .await;
+    // A half-built server is no use, nothing is left of it
+    match filled {
+        Ok(report) => Ok((server, report)),
+        Err(e) => {
+            let _ = server.remove();
+            Err(e)
+        }
+    }
+}
@@ -0,0 +1,757 @@
+//! Mod management for modded server kinds through a per-server `mods.toml`.
+//!
+//! The `mods` table lists the mods which were asked for, and `installed`
+//! records everything which is installed, including the dependencies:
+//! ```toml
+//! [mods.lithium]
+//! source = "modrinth"
+//! project = "lithium"
+//!
+//! [installed."modrinth:gvQqBUqZ"]
+//! name = "lithium"
+//! version = "mc1.20.1-0.11.2"
+//! file = "lithium-fabric-mc1.20.1-0.11.2.jar"
+//! mod_ids = ["lithium"]
+//! ```
+//! Mods are resolved from Modrinth or CurseForge, following their required
+//! dependencies. Mods whose metadata says they only work on clients are
+//! left out.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use error_chain::bail;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{MinecraftVersion, ServerConfig},
+    errors::*,
+    paths::Paths,
+    plugins::{
+        constraint::VersionConstraint,
+        sources::{
+            self, CurseForgeRepository, ModrinthRepository, PluginRepository, RemoteVersion,
+        },
+    },
+    server_kinds::ServerKind,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "source", rename_all = "lowercase")]
+pub enum ModSource {
+    Modrinth {
+        project: String,
+    },
+    Curseforge {
+        project: String,
+    },
+    /// A jar downloaded from a fixed URL
+    Url {
+        url: String,
+    },
+    /// A jar on the local filesystem
+    Path {
+        path: PathBuf,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ModEntry {
+    #[serde(flatten)]
+    pub source: ModSource,
+    #[serde(default)]
+    pub version: VersionConstraint,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InstalledMod {
+    /// Name of the entry in `mods`, or the file name for dependencies
+    pub name: String,
+    pub version: String,
+    /// File name inside the server's `mods` directory
+    pub file: String,
+    /// Mod IDs found in the jar's metadata
+    #[serde(default)]
+    pub mod_ids: Vec<String>,
+    /// Names of the mods which need this one. Empty for mods in `mods`.
+    #[serde(default)]
+    pub required_by: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ModManifest {
+    /// The mod loader the mods are for, which has to be the one of the
+    /// server kind
+    pub loader: Option<String>,
+    #[serde(default)]
+    pub mods: BTreeMap<String, ModEntry>,
+    /// Installed mods, keyed by `source:project ID`
+    #[serde(default)]
+    pub installed: BTreeMap<String, InstalledMod>,
+}
+
+impl ServerConfig {
+    pub fn mods_manifest_path(&self) -> PathBuf {
+        self.path().join("mods.toml")
+    }
+}
+
+impl ModManifest {
+    pub fn load(config: &ServerConfig) -> Result<Self> {
+        let path = config.mods_manifest_path();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = fs::read_to_string(&path).chain_err(|| "failed to read mod manifest")?;
+        toml::from_str(&text).chain_err(|| "invalid mod manifest")
+    }
+
+    pub fn save(&self, config: &ServerConfig) -> Result<()> {
+        let text = toml::to_string_pretty(self).chain_err(|| "failed to serialize mod manifest")?;
+        fs::write(config.mods_manifest_path(), text).chain_err(|| "failed to write mod manifest")
+    }
+
+    /// The mod loader of the server kind. Fails if the manifest is for
+    /// another loader.
+    pub fn loader(&self, config: &ServerConfig) -> Result<String> {
+        let kind = config.kind.parse::<ServerKind>()?;
+        match (kind.mod_loader(), &self.loader) {
+            (Some(loader), None) => Ok(loader.to_owned()),
+            (Some(loader), Some(wanted)) if loader == wanted => Ok(loader.to_owned()),
+            (_, Some(wanted)) => bail!(ErrorKind::InvalidConfig(format!(
+                "mod loader {} for a {} server",
+                wanted, kind
+            ))),
+            (None, None) => bail!("{} servers do not support mods", kind),
+        }
+    }
+}
+
+/// The repositories mods are resolved from.
+pub struct ModSources {
+    pub modrinth: Box<dyn PluginRepository>,
+    pub curseforge: Box<dyn PluginRepository>,
+}
+
+impl ModSources {
+    /// Modrinth and CurseForge, listing mods for `loader`, as the instance
+    /// in `paths` is set up to reach them
+    pub fn new(paths: &Paths, loader: &str) -> Self {
+        // Quilt loads Fabric mods too
+        let loaders: &[&str] = match loader {
+            "quilt" => &["quilt", "fabric"],
+            loader => &[loader],
+        };
+        Self {
+            modrinth: Box::new(ModrinthRepository::new(paths, loaders)),
+            curseforge: Box::new(CurseForgeRepository::new(paths, loader)),
+        }
+    }
+}
+
+/// Metadata of a mod, read from its jar.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModMetadata {
+    pub id: String,
+    pub version: String,
+    /// The mod only works on clients
+    pub client_only: bool,
+}
+
+#[derive(Deserialize)]
+struct FabricModJson {
+    id: String,
+    version: String,
+    #[serde(default)]
+    environment: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct QuiltLoader {
+    id: String,
+    version: String,
+}
+
+#[derive(Deserialize)]
+struct QuiltMinecraft {
+    environment: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct QuiltModJson {
+    quilt_loader: QuiltLoader,
+    minecraft: Option<QuiltMinecraft>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ForgeMod {
+    mod_id: String,
+    #[serde(default)]
+    version: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ForgeDependency {
+    #[serde(default)]
+    side: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ForgeModsToml {
+    #[serde(default)]
+    mods: Vec<ForgeMod>,
+    #[serde(default)]
+    dependencies: HashMap<String, Vec<ForgeDependency>>,
+}
+
+impl ModMetadata {
+    /// Parse a `fabric.mod.json`.
+    ///
+    /// # Examples
+    /// ```
+    /// use rustone::mods::ModMetadata;
+    /// let meta = ModMetadata::from_fabric(r#"{"id": "zoomify", "version": "2.11", "environment": "client"}"#).unwrap();
+    /// assert!(meta.client_only);
+    /// ```
+    pub fn from_fabric(text: &str) -> Option<Self> {
+        let json: FabricModJson = serde_json::from_str(text).ok()?;
+        Some(Self {
+            client_only: json.environment.as_deref() == Some("client"),
+            id: json.id,
+            version: json.version,
+        })
+    }
+
+    pub fn from_quilt(text: &str) -> Option<Self> {
+        let json: QuiltModJson = serde_json::from_str(text).ok()?;
+        Some(Self {
+            client_only: json.minecraft.and_then(|m| m.environment).as_deref() == Some("client"),
+            id: json.quilt_loader.id,
+            version: json.quilt_loader.version,
+        })
+    }
+
+    /// Parse a (Neo)Forge `mods.toml`. A mod is client-only if all of its
+    /// dependencies are only needed on the client side.
+    ///
+    /// # Examples
+    /// ```
+    /// use rustone::mods::ModMetadata;
+    /// let meta = ModMetadata::from_forge(r#"
+    ///     [[mods]]
+    ///     modId = "oculus"
+    ///     version = "1.6.9"
+    ///     [[dependencies.oculus]]
+    ///     modId = "forge"
+    ///     side = "CLIENT"
+    /// "#);
+    /// assert_eq!(meta[0].id, "oculus");
+    /// assert!(meta[0].client_only);
+    /// ```
+    pub fn from_forge(text: &str) -> Vec<Self> {
+        let toml: ForgeModsToml = match toml::from_str(text) {
+            Ok(toml) => toml,
+            Err(_) => return vec![],
+        };
+        let ForgeModsToml { mods, dependencies } = toml;
+        mods.into_iter()
+            .map(|m| {
+                let client_only = dependencies.get(&m.mod_id).is_some_and(|deps| {
+                    !deps.is_empty()
+                        && deps.iter().all(|d| {
+                            d.side
+                                .as_deref()
+                                .is_some_and(|s| s.eq_ignore_ascii_case("client"))
+                        })
+                });
+                Self {
+                    id: m.mod_id,
+                    version: m.version.unwrap_or_default(),
+                    client_only,
+                }
+            })
+            .collect()
+    }
+
+    /// Read the metadata of every mod in a jar.
+    pub fn read(jar: &Path) -> Result<Vec<Self>> {
+        let file = fs::File::open(jar)
+            .chain_err(|| format!("failed to open {}", jar.to_string_lossy()))?;
+        let mut archive = zip::ZipArchive::new(file)
+            .chain_err(|| format!("{} is not a valid jar", jar.to_string_lossy()))?;
+        let mut read = |name: &str| -> Option<String> {
+            let mut text = String::new();
+            archive.by_name(name).ok()?.read_to_string(&mut text).ok()?;
+            Some(text)
+        };
+        if let Some(meta) = read("fabric.mod.json").and_then(|t| Self::from_fabric(&t)) {
+            return Ok(vec![meta]);
+        }
+        if let Some(meta) = read("quilt.mod.json").and_then(|t| Self::from_quilt(&t)) {
+            return Ok(vec![meta]);
+        }
+        for name in &["META-INF/neoforge.mods.toml", "META-INF/mods.toml"] {
+            if let Some(text) = read(name) {
+                return Ok(Self::from_forge(&text));
+            }
+        }
+        Ok(vec![])
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    /// Mods which were installed or changed version: (name, version)
+    pub installed: Vec<(String, String)>,
+    /// Names of the mods which were removed
+    pub removed: Vec<String>,
+    /// Names of the mods which were left out, because they are client-only
+    pub client_only: Vec<String>,
+}
+
+/// A mod which has to be resolved
+struct Request {
+    name: String,
+    source: ModSource,
+    constraint: VersionConstraint,
+    /// Exact version ID a dependency asked for
+    version_id: Option<String>,
+    required_by: Option<String>,
+}
+
+struct Resolved {
+    installed: InstalledMod,
+    /// Repository version ID, `None` for URL and path sources and for mods
+    /// installed before the resolution
+    version_id: Option<String>,
+    /// Where the jar currently is: the staging or the mods directory
+    jar: PathBuf,
+    staged: bool,
+}
+
+fn describe(required_by: &[String]) -> String {
+    if required_by.is_empty() {
+        "mods.toml".to_owned()
+    } else {
+        required_by.join(", ")
+    }
+}
+
+struct Resolver<'a> {
+    config: &'a ServerConfig,
+    manifest: &'a ModManifest,
+    sources: &'a ModSources,
+    minecraft: MinecraftVersion,
+    staging: PathBuf,
+    resolved: BTreeMap<String, Resolved>,
+    report: SyncReport,
+}
+
+impl<'a> Resolver<'a> {
+    /// Reuse the installed jar if it is the same version, or fetch it into
+    /// the staging directory.
+    async fn fetch(
+        &self,
+        key: &str,
+        version: &str,
+        file_name: &str,
+        download: impl std::future::Future<Output = Result<()>>,
+    ) -> Result<(PathBuf, bool)> {
+        if let Some(old) = self.manifest.installed.get(key) {
+            let path = self.config.mods_path().join(&old.file);
+            if old.version == version && old.file == file_name && path.exists() {
+                return Ok((path, false));
+            }
+        }
+        download.await?;
+        Ok((self.staging.join(file_name), true))
+    }
+
+    async fn resolve(&mut self, request: Request) -> Result<Vec<Request>> {
+        let (repository, project, source) = match &request.source {
+            ModSource::Modrinth { project } => (&self.sources.modrinth, project, "modrinth"),
+            ModSource::Curseforge { project } => (&self.sources.curseforge, project, "curseforge"),
+            ModSource::Url { url } => {
+                let file_name = url
+                    .split(&['?', '#'][..])
+                    .next()
+                    .and_then(|u| u.rsplit('/').next())
+                    .filter(|f| f.ends_with(".jar"))
+                    .map_or_else(|| format!("{}.jar", request.name), |f| f.to_owned());
+                let dest = self.staging.join(&file_name);
+                sources::download_to(&self.config.paths, url, &dest).await?;
+                self.add_local(&request, format!("url:{}", request.name), file_name, dest)?;
+                return Ok(vec![]);
+            }
+            ModSource::Path { path } => {
+                let file_name = path.file_name().map_or_else(
+                    || format!("{}.jar", request.name),
+                    |f| f.to_string_lossy().into(),
+                );
+                let dest = self.staging.join(&file_name);
+                fs::copy(path, &dest)
+                    .chain_err(|| format!("failed to copy {}", path.to_string_lossy()))?;
+                self.add_local(&request, format!("path:{}", request.name), file_name, dest)?;
+                return Ok(vec![]);
+            }
+        };
+
+        let mut versions = repository.versions(project).await?;
+        sources::newest_first(&mut versions);
+        let candidates = || {
+            versions.iter().filter(|v| {
+                request.constraint.matches(&v.version)
+                    && request.version_id.as_ref().is_none_or(|id| *id == v.id)
+            })
+        };
+        let version = match candidates().find(|v| v.supports(&self.minecraft)) {
+            Some(version) => version,
+            None if candidates().next().is_none() => bail!(
+                "no version of {} matches '{}'{}",
+                request.name,
+                request.constraint,
+                request
+                    .required_by
+                    .as_ref()
+                    .map_or_else(String::new, |r| format!(" (required by {})", r))
+            ),
+            None => bail!(
+                "no version of {} matching '{}' supports Minecraft {}",
+                request.name,
+                request.constraint,
+                self.minecraft
+            ),
+        };
+        let key = format!("{}:{}", source, version.project_id);
+
+        if let Some(existing) = self.resolved.get_mut(&key) {
+            // Mods installed before this resolution have no known version
+            // ID, their version has to do
+            let compatible = request.constraint.matches(&existing.installed.version)
+                && request.version_id.as_ref().is_none_or(|id| {
+                    existing
+                        .version_id
+                        .as_ref()
+                        .is_none_or(|existing| existing == id)
+                });
+            if !compatible {
+                bail!(
+                    "conflicting versions of {}: {} (required by {}) and {} '{}' (required by {})",
+                    existing.installed.name,
+                    existing.installed.version,
+                    describe(&existing.installed.required_by),
+                    version.version,
+                    request.constraint,
+                    request.required_by.as_deref().unwrap_or("mods.toml")
+                );
+            }
+            if let Some(by) = request.required_by {
+                existing.installed.required_by.push(by);
+            }
+            return Ok(vec![]);
+        }
+
+        sources::check_file_name(&version.file.file_name)?;
+        let (jar, staged) = self
+            .fetch(&key, &version.version, &version.file.file_name, async {
+                let dest = self.staging.join(&version.file.file_name);
+                repository.download(&version.file, &dest).await?;
+                version.file.verify(&dest)
+            })
+            .await?;
+        let metadata = ModMetadata::read(&jar)?;
+        if !metadata.is_empty() && metadata.iter().all(|m| m.client_only) {
+            self.report.client_only.push(request.name);
+            return Ok(vec![]);
+        }
+        let dependencies = self.dependencies(&request, version);
+        self.resolved.insert(
+            key,
+            Resolved {
+                installed: InstalledMod {
+                    name: request.name,
+                    version: version.version.clone(),
+                    file: version.file.file_name.clone(),
+                    mod_ids: metadata.into_iter().map(|m| m.id).collect(),
+                    required_by: request.required_by.into_iter().collect(),
+                },
+                version_id: Some(version.id.clone()),
+                jar,
+                staged,
+            },
+        );
+        Ok(dependencies)
+    }
+
+    fn dependencies(&self, request: &Request, version: &RemoteVersion) -> Vec<Request> {
+        version
+            .dependencies
+            .iter()
+            .map(|dep| Request {
+                name: dep.project.clone(),
+                source: match request.source {
+                    ModSource::Curseforge { .. } => ModSource::Curseforge {
+                        project: dep.project.clone(),
+                    },
+                    _ => ModSource::Modrinth {
+                        project: dep.project.clone(),
+                    },
+                },
+                constraint: VersionConstraint::Any,
+                version_id: dep.version_id.clone(),
+                required_by: Some(request.name.clone()),
+            })
+            .collect()
+    }
+
+    fn add_local(
+        &mut self,
+        request: &Request,
+        key: String,
+        file_name: String,
+        jar: PathBuf,
+    ) -> Result<()> {
+        let metadata = ModMetadata::read(&jar)?;
+        if !metadata.is_empty() && metadata.iter().all(|m| m.client_only) {
+            self.report.client_only.push(request.name.clone());
+            return Ok(());
+        }
+        let version = metadata
+            .first()
+            .map_or_else(|| "unknown".to_owned(), |m| m.version.clone());
+        if !request.constraint.matches(&version) {
+            bail!(
+                "{} has version {}, which does not match '{}'",
+                request.name,
+                version,
+                request.constraint
+            );
+        }
+        self.resolved.insert(
+            key,
+            Resolved {
+                installed: InstalledMod {
+                    name: request.name.clone(),
+                    version,
+                    file: file_name,
+                    mod_ids: metadata.into_iter().map(|m| m.id).collect(),
+                    required_by: vec![],
+                },
+                version_id: None,
+                jar,
+                staged: true,
+            },
+        );
+        Ok(())
+    }
+
+    /// Two different projects providing the same mod would crash the server
+    fn check_duplicates(&self) -> Result<()> {
+        let mut providers: HashMap<&str, &InstalledMod> = HashMap::new();
+        for resolved in self.resolved.values() {
+            for id in &resolved.installed.mod_ids {
+                if let Some(other) = providers.insert(id, &resolved.installed) {
+                    bail!(
+                        "conflicting mods: {} {} and {} {} both provide '{}'",
+                        other.name,
+                        other.version,
+                        resolved.installed.name,
+                        resolved.installed.version,
+                        id
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Request {
+    fn entry(name: &str, entry: &ModEntry) -> Self {
+        Self {
+            name: name.to_owned(),
+            source: entry.source.clone(),
+            constraint: entry.version.clone(),
+            version_id: None,
+            required_by: None,
+        }
+    }
+}
+
+/// Resolve `requests` with their dependencies on top of the mods in
+/// `resolved`, and make the mods directory match. Nothing is changed if
+/// resolution fails.
+async fn apply(
+    config: &ServerConfig,
+    manifest: &mut ModManifest,
+    sources: &ModSources,
+    resolved: BTreeMap<String, Resolved>,
+    requests: VecDeque<Request>,
+) -> Result<SyncReport> {
+    let mods_dir = config.mods_path();
+    let staging = config.path().join(".mods-staging");
+    if staging.exists() {
+        fs::remove_dir_all(&staging).chain_err(|| "failed to clean mod staging directory")?;
+    }
+    fs::create_dir_all(&staging).chain_err(|| "failed to create mod staging directory")?;
+    fs::create_dir_all(&mods_dir).chain_err(|| "failed to create mods directory")?;
+
+    let mut resolver = Resolver {
+        config,
+        manifest,
+        sources,
+        minecraft: config.version.minecraft,
+        staging: staging.clone(),
+        resolved,
+        report: SyncReport::default(),
+    };
+    let mut queue = requests;
+    let result = async {
+        while let Some(request) = queue.pop_front() {
+            queue.extend(resolver.resolve(request).await?);
+        }
+        resolver.check_duplicates()
+    }
+    .await;
+    if let Err(e) = result {
+        let _ = fs::remove_dir_all(&staging);
+        return Err(e);
+    }
+
+    let Resolver {
+        resolved,
+        mut report,
+        ..
+    } = resolver;
+    // Stale files go first, a new jar may have the name of an old one
+    let kept: HashSet<&str> = resolved
+        .values()
+        .map(|r| r.installed.file.as_str())
+        .collect();
+    for (key, old) in &manifest.installed {
+        if !kept.contains(old.file.as_str()) {
+            let _ = fs::remove_file(mods_dir.join(&old.file));
+        }
+        if !resolved.contains_key(key) {
+            report.removed.push(old.name.clone());
+        }
+    }
+    let mut installed = BTreeMap::new();
+    for (key, resolved) in resolved {
+        if resolved.staged {
+            fs::rename(&resolved.jar, mods_dir.join(&resolved.installed.file))
+                .chain_err(|| format!("failed to install {}", resolved.installed.file))?;
+        }
+        let old = manifest.installed.get(&key);
+        if old.is_none_or(|o| o.version != resolved.installed.version) {
+            report.installed.push((
+                resolved.installed.name.clone(),
+                resolved.installed.version.clone(),
+            ));
+        }
+        installed.insert(key, resolved.installed);
+    }
+    manifest.installed = installed;
+    fs::remove_dir_all(&staging).chain_err(|| "failed to clean mod staging directory")?;
+    manifest.save(config)?;
+    Ok(report)
+}
+
+/// Resolve every mod in `manifest` with its dependencies, and make the mods
+/// directory match. Nothing is changed if resolution fails.
+async fn sync(
+    config: &ServerConfig,
+    manifest: &mut ModManifest,
+    sources: &ModSources,
+) -> Result<SyncReport> {
+    let requests = manifest
+        .mods
+        .iter()
+        .map(|(name, entry)| Request::entry(name, entry))
+        .collect();
+    apply(config, manifest, sources, BTreeMap::new(), requests).await
+}
+
+/// Add a mod to the manifest of a server, and install it with its
+/// dependencies. The mods already installed stay as they are.
+pub async fn add(config: &ServerConfig, name: &str, entry: ModEntry) -> Result<SyncReport> {
+    let mut manifest = ModManifest::load(config)?;
+    if manifest.mods.contains_key(name) {
+        bail!(ErrorKind::AlreadyExists(format!("mod {}", name)));
+    }
+    let sources = ModSources::new(&config.paths, &manifest.loader(config)?);
+    let request = Request::entry(name, &entry);
+    manifest.mods.insert(name.to_owned(), entry);
+    let mods_dir = config.mods_path();
+    let installed = manifest
+        .installed
+        .iter()
+        .map(|(key, installed)| {
+            let resolved = Resolved {
+                installed: installed.clone(),
+                version_id: None,
+                jar: mods_dir.join(&installed.file),
+                staged: false,
+            };
+            (key.clone(), resolved)
+        })
+        .collect();
+    apply(
+        config,
+        &mut manifest,
+        &sources,
+        installed,
+        VecDeque::from(vec![request]),
+    )
+    .await
+}
+
+/// Remove a mod from the manifest, along with the dependencies nothing else
+/// needs anymore. Other mods are left alone, and nothing is downloaded.
+pub fn remove(config: &ServerConfig, name: &str) -> Result<SyncReport> {
+    let mut manifest = ModManifest::load(config)?;
+    if manifest.mods.remove(name).is_none() {
+        bail!(ErrorKind::NotFound(format!("mod {}", name)));
+    }
+    let mods_dir = config.mods_path();
+    let mut report = SyncReport::default();
+    let mut gone = vec![name.to_owned()];
+    while let Some(gone_name) = gone.pop() {
+        let mut orphans = vec![];
+        for (key, installed) in manifest.installed.iter_mut() {
+            installed.required_by.retain(|by| *by != gone_name);
+            if installed.required_by.is_empty() && !manifest.mods.contains_key(&installed.name) {
+                orphans.push(key.clone());
+            }
+        }
+        for key in orphans {
+            let old = manifest.installed.remove(&key).unwrap();
+            let still_used = manifest.installed.values().any(|i| i.file == old.file);
+            if !still_used {
+                let _ = fs::remove_file(mods_dir.join(&old.file));
+            }
+            report.removed.push(old.name.clone());
+            gone.push(old.name);
+        }
+    }
+    manifest.save(config)?;
+    Ok(report)
+}
+
+/// Update every mod to the newest version matching its constraint.
+pub async fn update(config: &ServerConfig) -> Result<SyncReport> {
+    let mut manifest = ModManifest::load(config)?;
+    let sources = ModSources::new(&config.paths, &manifest.loader(config)?);
+    sync(config, &mut manifest, &sources).await
+}
+
+/// Like [`update`], but with custom mod repositories.
+pub async fn update_with(config: &ServerConfig, sources: &ModSources) -> Result<SyncReport> {
+    let mut manifest = ModManifest::load(config)?;
+    sync(config, &mut manifest, sources).await
+}
@@ -0,0 +1,120 @@
+//! Where an instance of rustone keeps its files.
+//!
+//! By default these are the platform's configuration, data and cache
+//! directories. Setting `RUSTONE_HOME` moves all of them into one directory,
+//! so that several independent instances can live side by side.
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+use crate::{errors::*, global::project_dirs};
+
+/// The directories of a rustone instance.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Paths {
+    /// Server configurations, templates and `rustone.toml`
+    pub config: PathBuf,
+    /// Server directories and the backup repository
+    pub data: PathBuf,
+    /// Downloaded server jars and upstream metadata
+    pub cache: PathBuf,
+}
+
+impl Paths {
+    /// An instance entirely inside `home`.
+    ///
+    /// # Examples
+    /// ```
+    /// use rustone::paths::Paths;
+    /// let paths = Paths::from_home("/srv/rustone".as_ref());
+    /// assert_eq!(
+    ///     paths.server_config("survival"),
+    ///     std::path::Path::new("/srv/rustone/config/servers/survival.toml")
+    /// );
+    /// assert_eq!(paths.server("survival"), std::path::Path::new("/srv/rustone/data/survival"));
+    /// ```
+    pub fn from_home(home: &Path) -> Self {
+        Self {
+            config: home.join("config"),
+            data: home.join("data"),
+            cache: home.join("cache"),
+        }
+    }
+
+    /// The platform's directories for rustone.
+    pub fn from_project_dirs() -> Result<Self> {
+        let dirs = project_dirs()?;
+        Ok(Self {
+            config: dirs.config_dir().to_owned(),
+            data: dirs.data_dir().to_owned(),
+            cache: dirs.cache_dir().to_owned(),
+        })
+    }
+
+    /// The instance in `RUSTONE_HOME`, or the platform's directories if it is
+    /// not set.
+    pub fn from_env() -> Result<Self> {
+        match env::var_os("RUSTONE_HOME") {
+            Some(home) if !home.is_empty() => Ok(Self::from_home(Path::new(&home))),
+            _ => Self::from_project_dirs(),
+        }
+    }
+
+    /// The global configuration, `rustone.toml`.
+    pub fn global_config(&self) -> PathBuf {
+        self.config.join("rustone.toml")
+    }
+
+    /// The directory of server configurations.
+    pub fn server_configs(&self) -> PathBuf {
+        self.config.join("servers")
+    }
+
+    pub fn server_config(&self, name: &str) -> PathBuf {
+        self.server_configs().join(format!("{}.toml", name))
+    }
+
+    pub fn templates(&self) -> PathBuf {
+        self.config.join("templates")
+    }
+
+    /// The directory of servers in the standard location.
+    pub fn server(&self, name: &str) -> PathBuf {
+        self.data.join(name)
+    }
+
+    /// The default backup repository. It lives inside the data directory,
+    /// but is hidden, so it is not mistaken for a server.
+    pub fn backups(&self) -> PathBuf {
+        self.data.join(".backups")
+    }
+
+    /// The log of the builds `name` was updated to. Kept in a hidden
+    /// directory, like backups.
+    pub fn update_log(&self, name: &str) -> PathBuf {
+        self.data.join(".updates").join(format!("{}.jsonl", name))
+    }
+
+    /// The jar cache metadata of older versions of rustone, migrated into
+    /// the jar index.
+    pub fn cache_meta(&self) -> PathBuf {
+        self.cache.join("cache.toml")
+    }
+
+    /// The index of cached jars.
+    pub fn jar_index(&self) -> PathBuf {
+        self.cache.join("index.toml")
+    }
+
+    /// Cached jars, named by their SHA-256.
+    pub fn jar_blobs(&self) -> PathBuf {
+        self.cache.join("blobs")
+    }
+
+    /// Cached upstream API responses.
+    pub fn metadata_cache(&self) -> PathBuf {
+        self.cache.join("metadata")
+    }
+}
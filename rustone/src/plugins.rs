@@ -0,0 +1,410 @@
+//! Plugin management through a per-server `plugins.toml` manifest.
+//!
+//! The manifest lists every plugin with its source and a version constraint,
+//! and records the version which is currently installed:
+//! ```toml
+//! [plugins.luckperms]
+//! source = "modrinth"
+//! project = "luckperms"
+//! version = "5.4.x"
+//!
+//! [plugins.luckperms.installed]
+//! version = "5.4.102"
+//! file = "LuckPerms-Bukkit-5.4.102.jar"
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use error_chain::bail;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{MinecraftVersion, ServerConfig},
+    errors::*,
+    paths::Paths,
+};
+
+pub mod constraint;
+pub mod sources;
+
+use constraint::VersionConstraint;
+use sources::{HangarRepository, ModrinthRepository, PluginRepository, RemoteVersion};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "source", rename_all = "lowercase")]
+pub enum PluginSource {
+    Modrinth {
+        project: String,
+    },
+    Hangar {
+        project: String,
+    },
+    /// A jar downloaded from a fixed URL
+    Url {
+        url: String,
+    },
+    /// A jar on the local filesystem
+    Path {
+        path: PathBuf,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InstalledPlugin {
+    pub version: String,
+    /// File name inside the server's `plugins` directory
+    pub file: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PluginEntry {
+    #[serde(flatten)]
+    pub source: PluginSource,
+    #[serde(default)]
+    pub version: VersionConstraint,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub installed: Option<InstalledPlugin>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct PluginManifest {
+    #[serde(default)]
+    pub plugins: BTreeMap<String, PluginEntry>,
+}
+
+impl ServerConfig {
+    pub fn plugins_manifest_path(&self) -> PathBuf {
+        self.path().join("plugins.toml")
+    }
+}
+
+impl PluginManifest {
+    pub fn load(config: &ServerConfig) -> Result<Self> {
+        let path = config.plugins_manifest_path();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = fs::read_to_string(&path).chain_err(|| "failed to read plugin manifest")?;
+        toml::from_str(&text).chain_err(|| "invalid plugin manifest")
+    }
+
+    pub fn save(&self, config: &ServerConfig) -> Result<()> {
+        let text =
+            toml::to_string_pretty(self).chain_err(|| "failed to serialize plugin manifest")?;
+        fs::write(config.plugins_manifest_path(), text)
+            .chain_err(|| "failed to write plugin manifest")
+    }
+}
+
+/// The repositories plugins are resolved from.
+pub struct Sources {
+    pub modrinth: Box<dyn PluginRepository>,
+    pub hangar: Box<dyn PluginRepository>,
+}
+
+impl Sources {
+    /// Modrinth and Hangar, as the instance in `paths` is set up to reach
+    /// them
+    pub fn new(paths: &Paths) -> Self {
+        Self {
+            modrinth: Box::new(ModrinthRepository::plugins(paths)),
+            hangar: Box::new(HangarRepository::new(paths)),
+        }
+    }
+}
+
+/// The `plugin.yml` (or `paper-plugin.yml`) of a plugin jar.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PluginDescription {
+    pub name: String,
+    pub version: String,
+    pub api_version: Option<MinecraftVersion>,
+}
+
+impl PluginDescription {
+    /// Parse the top-level keys of a plugin description.
+    ///
+    /// This does not use a YAML parser on purpose: YAML would read
+    /// `api-version: 1.20` as the number `1.2`.
+    ///
+    /// # Examples
+    /// ```
+    /// use rustone::plugins::PluginDescription;
+    /// let desc = PluginDescription::parse("name: Test\nversion: '1.0'\napi-version: 1.20\n").unwrap();
+    /// assert_eq!(desc.version, "1.0");
+    /// assert_eq!(desc.api_version, Some("1.20".parse().unwrap()));
+    /// ```
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut keys = BTreeMap::new();
+        for line in text.lines() {
+            if line.starts_with(char::is_whitespace) || line.starts_with('#') {
+                continue;
+            }
+            if let Some((key, value)) = line.split_once(':') {
+                let value = value.split(" #").next().unwrap().trim();
+                keys.insert(key.trim(), value.trim_matches(|c| c == '"' || c == '\''));
+            }
+        }
+        Some(Self {
+            name: keys.get("name")?.to_string(),
+            version: keys.get("version")?.to_string(),
+            api_version: keys.get("api-version").and_then(|v| v.parse().ok()),
+        })
+    }
+
+    /// Read the plugin description from a jar, if it has one.
+    pub fn read(jar: &Path) -> Result<Option<Self>> {
+        let file = fs::File::open(jar)
+            .chain_err(|| format!("failed to open {}", jar.to_string_lossy()))?;
+        let mut archive = zip::ZipArchive::new(file)
+            .chain_err(|| format!("{} is not a valid jar", jar.to_string_lossy()))?;
+        for name in &["paper-plugin.yml", "plugin.yml"] {
+            if let Ok(mut entry) = archive.by_name(name) {
+                let mut text = String::new();
+                entry
+                    .read_to_string(&mut text)
+                    .chain_err(|| format!("failed to read {}", name))?;
+                return Ok(Self::parse(&text));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Plugins using a newer API than the server provides won't load.
+    pub fn supports(&self, minecraft: &MinecraftVersion) -> bool {
+        self.api_version.is_none_or(|api| api <= *minecraft)
+    }
+}
+
+fn plugins_dir(config: &ServerConfig) -> PathBuf {
+    config.path().join("plugins")
+}
+
+/// Fetch the version of `entry` which matches its constraint and supports
+/// the Minecraft version of the server into `dest`. Returns the version and
+/// the file name the plugin should be installed as.
+async fn fetch(
+    config: &ServerConfig,
+    name: &str,
+    entry: &PluginEntry,
+    sources: &Sources,
+    dest: &Path,
+) -> Result<(String, String)> {
+    let minecraft = &config.version.minecraft;
+    let (repository, project) = match &entry.source {
+        PluginSource::Modrinth { project } => (&sources.modrinth, project),
+        PluginSource::Hangar { project } => (&sources.hangar, project),
+        PluginSource::Url { url } => {
+            let file_name = sources::url_file_name(url).unwrap_or_else(|| format!("{}.jar", name));
+            sources::check_file_name(&file_name)?;
+            sources::download_to(&config.paths, url, dest).await?;
+            return Ok((check_local(name, entry, minecraft, dest)?, file_name));
+        }
+        PluginSource::Path { path } => {
+            fs::copy(path, dest)
+                .chain_err(|| format!("failed to copy {}", path.to_string_lossy()))?;
+            let file_name = path
+                .file_name()
+                .map_or_else(|| format!("{}.jar", name), |f| f.to_string_lossy().into());
+            return Ok((check_local(name, entry, minecraft, dest)?, file_name));
+        }
+    };
+
+    let version = resolve(
+        name,
+        &entry.version,
+        repository.as_ref(),
+        project,
+        minecraft,
+    )
+    .await?;
+    sources::check_file_name(&version.file.file_name)?;
+    repository.download(&version.file, dest).await?;
+    version.file.verify(dest)?;
+    Ok((version.version.clone(), version.file.file_name.clone()))
+}
+
+/// Find the newest version of `project` in `repository` which matches
+/// `constraint` and supports `minecraft`.
+pub async fn resolve(
+    name: &str,
+    constraint: &VersionConstraint,
+    repository: &dyn PluginRepository,
+    project: &str,
+    minecraft: &MinecraftVersion,
+) -> Result<RemoteVersion> {
+    let mut versions = repository.versions(project).await?;
+    sources::newest_first(&mut versions);
+    let mut matching = versions
+        .into_iter()
+        .filter(|v| constraint.matches(&v.version))
+        .peekable();
+    if matching.peek().is_none() {
+        bail!("no version of {} matches '{}'", name, constraint);
+    }
+    match matching.find(|v| v.supports(minecraft)) {
+        Some(version) => Ok(version),
+        None => bail!(
+            "no version of {} matching '{}' supports Minecraft {}",
+            name,
+            constraint,
+            minecraft
+        ),
+    }
+}
+
+/// Check a plugin which was not resolved from a repository, using its
+/// plugin description.
+fn check_local(
+    name: &str,
+    entry: &PluginEntry,
+    minecraft: &MinecraftVersion,
+    jar: &Path,
+) -> Result<String> {
+    let description = match PluginDescription::read(jar)? {
+        Some(description) => description,
+        None => bail!("{} is not a Bukkit plugin (no plugin.yml found)", name),
+    };
+    if !entry.version.matches(&description.version) {
+        bail!(
+            "{} has version {}, which does not match '{}'",
+            name,
+            description.version,
+            entry.version
+        );
+    }
+    if !description.supports(minecraft) {
+        bail!(
+            "{} {} needs Minecraft {} or newer",
+            name,
+            description.version,
+            description.api_version.unwrap()
+        );
+    }
+    Ok(description.version)
+}
+
+/// Resolve and download `entry` into the plugins directory, replacing the
+/// previously installed file. Fails if another plugin of `manifest` is
+/// installed as the same file.
+async fn install(
+    config: &ServerConfig,
+    name: &str,
+    entry: &PluginEntry,
+    manifest: &PluginManifest,
+    sources: &Sources,
+) -> Result<InstalledPlugin> {
+    let dir = plugins_dir(config);
+    fs::create_dir_all(&dir).chain_err(|| "failed to create plugins directory")?;
+    let partial = dir.join(format!(".{}.part", name));
+    let fetched = fetch(config, name, entry, sources, &partial).await;
+    let (version, file) = match fetched {
+        Ok(fetched) => fetched,
+        Err(e) => {
+            let _ = fs::remove_file(&partial);
+            return Err(e);
+        }
+    };
+    let taken_by = manifest.plugins.iter().find(|(other, e)| {
+        other.as_str() != name && e.installed.as_ref().is_some_and(|i| i.file == file)
+    });
+    if let Some((other, _)) = taken_by {
+        let _ = fs::remove_file(&partial);
+        bail!(ErrorKind::AlreadyExists(format!(
+            "{} (installed by plugin {})",
+            file, other
+        )));
+    }
+    fs::rename(&partial, dir.join(&file)).chain_err(|| format!("failed to install {}", file))?;
+    // Only drop the old jar once the new one is in place
+    if let Some(old) = &entry.installed {
+        if old.file != file {
+            let _ = fs::remove_file(dir.join(&old.file));
+        }
+    }
+    Ok(InstalledPlugin { version, file })
+}
+
+/// Add a plugin to the manifest of a server and install it.
+pub async fn add(
+    config: &ServerConfig,
+    name: &str,
+    mut entry: PluginEntry,
+    sources: &Sources,
+) -> Result<InstalledPlugin> {
+    let mut manifest = PluginManifest::load(config)?;
+    if manifest.plugins.contains_key(name) {
+        bail!(ErrorKind::AlreadyExists(format!("plugin {}", name)));
+    }
+    entry.installed = None;
+    let installed = install(config, name, &entry, &manifest, sources).await?;
+    entry.installed = Some(installed.clone());
+    manifest.plugins.insert(name.to_owned(), entry);
+    manifest.save(config)?;
+    Ok(installed)
+}
+
+/// Remove a plugin from the manifest and delete its jar.
+pub fn remove(config: &ServerConfig, name: &str) -> Result<()> {
+    let mut manifest = PluginManifest::load(config)?;
+    let entry = match manifest.plugins.remove(name) {
+        Some(entry) => entry,
+        None => bail!(ErrorKind::NotFound(format!("plugin {}", name))),
+    };
+    if let Some(installed) = entry.installed {
+        let path = plugins_dir(config).join(&installed.file);
+        if path.exists() {
+            fs::remove_file(&path).chain_err(|| format!("failed to remove {}", installed.file))?;
+        }
+    }
+    manifest.save(config)
+}
+
+pub struct PluginUpdate {
+    pub name: String,
+    pub from: Option<String>,
+    pub to: String,
+}
+
+/// Update one or every plugin of a server to the newest version matching its
+/// constraint. Plugins which are missing from the plugins directory are
+/// installed again.
+pub async fn update(
+    config: &ServerConfig,
+    name: Option<&str>,
+    sources: &Sources,
+) -> Result<Vec<PluginUpdate>> {
+    let mut manifest = PluginManifest::load(config)?;
+    if let Some(name) = name {
+        if !manifest.plugins.contains_key(name) {
+            bail!(ErrorKind::NotFound(format!("plugin {}", name)));
+        }
+    }
+    let names: Vec<String> = match name {
+        Some(name) => vec![name.to_owned()],
+        None => manifest.plugins.keys().cloned().collect(),
+    };
+    let mut updates = vec![];
+    for plugin in names {
+        let mut entry = manifest.plugins[&plugin].clone();
+        let installed = install(config, &plugin, &entry, &manifest, sources).await?;
+        let from = entry.installed.as_ref().map(|i| i.version.clone());
+        if from.as_ref() != Some(&installed.version) {
+            updates.push(PluginUpdate {
+                name: plugin.clone(),
+                from,
+                to: installed.version.clone(),
+            });
+        }
+        entry.installed = Some(installed);
+        manifest.plugins.insert(plugin, entry);
+        // Save after every plugin, so a failure does not lose the others
+        manifest.save(config)?;
+    }
+    Ok(updates)
+}
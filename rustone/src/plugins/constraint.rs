@@ -0,0 +1,110 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::errors::*;
+
+/// A constraint on the version of a plugin.
+///
+/// Plugin versions are arbitrary strings, so besides semver requirements,
+/// exact matches and prefix wildcards are also supported.
+///
+/// # Examples
+/// ```
+/// use rustone::plugins::constraint::VersionConstraint;
+/// let any: VersionConstraint = "*".parse().unwrap();
+/// assert!(any.matches("whatever-SNAPSHOT"));
+/// let wildcard: VersionConstraint = "5.4.x".parse().unwrap();
+/// assert!(wildcard.matches("5.4.102") && !wildcard.matches("5.5.0"));
+/// let req: VersionConstraint = ">=2.19, <3".parse().unwrap();
+/// assert!(req.matches("v2.20.1") && !req.matches("3.0.0"));
+/// let exact: VersionConstraint = "7.2.15".parse().unwrap();
+/// assert!(exact.matches("7.2.15") && !exact.matches("7.2.16"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum VersionConstraint {
+    /// The latest compatible version
+    #[default]
+    Any,
+    Exact(String),
+    /// Any version starting with the prefix (written as `1.2.x` or `1.2.*`)
+    Prefix(String),
+    Semver(semver::VersionReq),
+}
+
+/// Parse a plugin version as semver, tolerating a `v` prefix and missing
+/// components (`v2.1` is treated as `2.1.0`).
+fn lenient_semver(version: &str) -> Option<semver::Version> {
+    let version = version.trim().trim_start_matches('v');
+    let (core, rest) = match version.find(['-', '+']) {
+        Some(i) => version.split_at(i),
+        None => (version, ""),
+    };
+    let mut parts: Vec<&str> = core.split('.').collect();
+    while parts.len() < 3 {
+        parts.push("0");
+    }
+    semver::Version::parse(&format!("{}{}", parts.join("."), rest)).ok()
+}
+
+impl VersionConstraint {
+    pub fn matches(&self, version: &str) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Exact(exact) => exact == version,
+            Self::Prefix(prefix) => version.starts_with(prefix.as_str()),
+            Self::Semver(req) => lenient_semver(version).is_some_and(|v| req.matches(&v)),
+        }
+    }
+}
+
+impl FromStr for VersionConstraint {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() || s == "*" || s == "latest" {
+            Ok(Self::Any)
+        } else if let Some(prefix) = s.strip_suffix(".x").or_else(|| s.strip_suffix(".*")) {
+            Ok(Self::Prefix(format!("{}.", prefix)))
+        } else if s.contains(|c| "<>=^~,".contains(c)) {
+            Ok(Self::Semver(s.parse().chain_err(|| {
+                format!("invalid version requirement '{}'", s)
+            })?))
+        } else {
+            Ok(Self::Exact(s.to_owned()))
+        }
+    }
+}
+
+impl Display for VersionConstraint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Any => write!(f, "*"),
+            Self::Exact(v) => write!(f, "{}", v),
+            Self::Prefix(p) => write!(f, "{}x", p),
+            Self::Semver(req) => write!(f, "{}", req),
+        }
+    }
+}
+
+impl Serialize for VersionConstraint {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for VersionConstraint {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        use serde::de::Error;
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
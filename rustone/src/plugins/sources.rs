@@ -0,0 +1,736 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::Read,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use error_chain::bail;
+use lazy_static::lazy_static;
+use percent_encoding::percent_decode_str;
+use reqwest::{StatusCode, Url};
+use serde::Deserialize;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::{
+    config::MinecraftVersion,
+    download::{self, DownloadOptions},
+    errors::*,
+    paths::Paths,
+    settings::{self, EffectiveNetwork, IpVersion},
+};
+
+/// A downloadable file of a plugin or mod version. The hashes are lowercase
+/// hex strings, and are checked when present.
+#[derive(Debug, Clone, Default)]
+pub struct RemoteFile {
+    pub url: String,
+    pub file_name: String,
+    pub sha1: Option<String>,
+    pub sha256: Option<String>,
+    pub sha512: Option<String>,
+}
+
+impl RemoteFile {
+    /// Verify the downloaded file at `path` against the known hashes.
+    pub fn verify(&self, path: &Path) -> Result<()> {
+        let mut file = std::fs::File::open(path)
+            .chain_err(|| format!("failed to open {}", path.to_string_lossy()))?;
+        let mut sha1 = Sha1::new();
+        let mut sha256 = Sha256::new();
+        let mut sha512 = Sha512::new();
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let read = file
+                .read(&mut buffer)
+                .chain_err(|| format!("failed to read {}", path.to_string_lossy()))?;
+            if read == 0 {
+                break;
+            }
+            sha1.update(&buffer[..read]);
+            sha256.update(&buffer[..read]);
+            sha512.update(&buffer[..read]);
+        }
+        let matches = |expected: &Option<String>, actual: &[u8]| {
+            expected
+                .as_ref()
+                .is_none_or(|e| e.eq_ignore_ascii_case(&hex::encode(actual)))
+        };
+        if !matches(&self.sha1, &sha1.finalize())
+            || !matches(&self.sha256, &sha256.finalize())
+            || !matches(&self.sha512, &sha512.finalize())
+        {
+            bail!("checksum mismatch for {}", self.file_name);
+        }
+        Ok(())
+    }
+}
+
+/// A dependency of a mod version.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteDependency {
+    pub project: String,
+    /// The exact version of the dependency, as a repository-specific ID
+    pub version_id: Option<String>,
+}
+
+/// A version of a plugin or mod, as published on a repository.
+#[derive(Debug, Clone, Default)]
+pub struct RemoteVersion {
+    /// Repository-specific ID of the version
+    pub id: String,
+    /// Repository-specific ID of the project the version belongs to
+    pub project_id: String,
+    pub version: String,
+    /// Minecraft versions this version supports. Entries may be ranges,
+    /// like `1.19-1.20.4`.
+    pub game_versions: Vec<String>,
+    pub file: RemoteFile,
+    /// Required dependencies
+    pub dependencies: Vec<RemoteDependency>,
+    /// When the version was published, as an RFC 3339 timestamp, if known
+    pub published: Option<String>,
+}
+
+impl RemoteVersion {
+    /// Check whether the plugin version declares support for `minecraft`.
+    ///
+    /// # Examples
+    /// ```
+    /// use rustone::plugins::sources::{RemoteFile, RemoteVersion};
+    /// let version = RemoteVersion {
+    ///     version: "1.0".into(),
+    ///     game_versions: vec!["1.18.2".into(), "1.19-1.20.1".into()],
+    ///     ..Default::default()
+    /// };
+    /// assert!(version.supports(&"1.18.2".parse().unwrap()));
+    /// assert!(version.supports(&"1.19.4".parse().unwrap()));
+    /// assert!(!version.supports(&"1.20.2".parse().unwrap()));
+    /// ```
+    pub fn supports(&self, minecraft: &MinecraftVersion) -> bool {
+        self.game_versions.iter().any(|entry| {
+            let mut range = entry
+                .splitn(2, '-')
+                .map(|v| v.trim().parse::<MinecraftVersion>());
+            match (range.next(), range.next()) {
+                (Some(Ok(from)), Some(Ok(to))) => from <= *minecraft && *minecraft <= to,
+                (Some(Ok(exact)), None) => exact == *minecraft,
+                _ => false,
+            }
+        })
+    }
+}
+
+/// Sort `versions` newest first by when they were published. Versions
+/// without a date go last, and keep their order.
+///
+/// # Examples
+/// ```
+/// use rustone::plugins::sources::{newest_first, RemoteVersion};
+/// let version = |version: &str, published: Option<&str>| RemoteVersion {
+///     version: version.into(),
+///     published: published.map(str::to_owned),
+///     ..Default::default()
+/// };
+/// let mut versions = vec![
+///     version("1.0", Some("2023-01-05T10:00:00Z")),
+///     version("local", None),
+///     version("1.1", Some("2023-03-01T08:30:00.123456Z")),
+/// ];
+/// newest_first(&mut versions);
+/// let order: Vec<_> = versions.iter().map(|v| v.version.as_str()).collect();
+/// assert_eq!(order, ["1.1", "1.0", "local"]);
+/// ```
+pub fn newest_first(versions: &mut [RemoteVersion]) {
+    // Timestamps differ in their fractions of seconds, up to the seconds
+    // they compare as text
+    fn key(version: &RemoteVersion) -> Option<&str> {
+        version.published.as_ref().and_then(|p| p.get(..19))
+    }
+    versions.sort_by(|a, b| key(b).cmp(&key(a)));
+}
+
+/// The file name of a jar downloaded from `url`: the decoded last segment of
+/// its path, if that names a jar.
+///
+/// # Examples
+/// ```
+/// use rustone::plugins::sources::url_file_name;
+/// assert_eq!(
+///     url_file_name("https://example.com/files/My%20Plugin-1.0.jar?token=x").as_deref(),
+///     Some("My Plugin-1.0.jar")
+/// );
+/// assert_eq!(url_file_name("https://example.com/download"), None);
+/// ```
+pub fn url_file_name(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let segment = url.path_segments()?.next_back()?;
+    let file_name = percent_decode_str(segment).decode_utf8().ok()?;
+    Some(file_name.into_owned()).filter(|f| f.ends_with(".jar"))
+}
+
+/// Check that a file name from a repository stays in the directory it is
+/// installed into.
+///
+/// # Examples
+/// ```
+/// use rustone::plugins::sources::check_file_name;
+/// assert!(check_file_name("worldedit-7.2.jar").is_ok());
+/// assert!(check_file_name("../server.jar").is_err());
+/// assert!(check_file_name("..").is_err());
+/// assert!(check_file_name("plugins\\evil.jar").is_err());
+/// ```
+pub fn check_file_name(file_name: &str) -> Result<()> {
+    if file_name.is_empty()
+        || file_name == "."
+        || file_name == ".."
+        || file_name.contains(&['/', '\\'][..])
+    {
+        bail!("refusing to install a file named '{}'", file_name);
+    }
+    Ok(())
+}
+
+/// A plugin or mod repository, like Modrinth, Hangar or CurseForge.
+///
+/// Implement this to point plugin management to something else, for example
+/// a local stand-in in tests.
+#[async_trait]
+pub trait PluginRepository: Send + Sync {
+    /// List the versions of `project` (a slug or an ID). Callers sort them
+    /// with [`newest_first`].
+    async fn versions(&self, project: &str) -> Result<Vec<RemoteVersion>>;
+
+    /// Download `file` into `dest`.
+    async fn download(&self, file: &RemoteFile, dest: &Path) -> Result<()>;
+}
+
+/// The HTTP clients of an instance.
+#[derive(Clone)]
+struct Clients {
+    /// Gives up on whole requests after the read timeout
+    api: reqwest::Client,
+    /// Has no timeout for whole requests, bodies of any size are streamed
+    /// with a timeout for each read instead
+    download: reqwest::Client,
+}
+
+lazy_static! {
+    /// The clients built so far, by the global configuration of their
+    /// instance. Each one keeps its connections open for the next requests.
+    static ref CLIENTS: Mutex<HashMap<PathBuf, Clients>> = Mutex::new(HashMap::new());
+}
+
+/// The clients of the instance in `paths`, built the first time they are
+/// needed. Fails in offline mode.
+fn clients(paths: &Paths) -> Result<Clients> {
+    settings::check_online(paths)?;
+    let key = paths.global_config();
+    if let Some(clients) = CLIENTS.lock().unwrap().get(&key) {
+        return Ok(clients.clone());
+    }
+    let network = settings::effective(paths, None)?.network;
+    let clients = Clients {
+        api: client_builder(&network)?
+            .timeout(Duration::from_secs(network.read_timeout.value))
+            .build()
+            .chain_err(|| "failed to create HTTP client")?,
+        download: client_builder(&network)?
+            .build()
+            .chain_err(|| "failed to create HTTP client")?,
+    };
+    CLIENTS.lock().unwrap().insert(key, clients.clone());
+    Ok(clients)
+}
+
+/// The client for requests to the network, following the network settings
+/// of the instance in `paths`. Fails in offline mode.
+pub(crate) fn http_client(paths: &Paths) -> Result<reqwest::Client> {
+    Ok(clients(paths)?.api)
+}
+
+/// Like [`http_client`], for responses whose body is streamed with
+/// [`download::stream`], so that large files aren't cut off.
+pub(crate) fn download_client(paths: &Paths) -> Result<reqwest::Client> {
+    Ok(clients(paths)?.download)
+}
+
+fn client_builder(network: &EffectiveNetwork) -> Result<reqwest::ClientBuilder> {
+    let mut builder = reqwest::Client::builder()
+        .user_agent(network.user_agent.value.as_str())
+        .connect_timeout(Duration::from_secs(network.connect_timeout.value));
+    if let Some(proxy) = &network.proxy.value {
+        let proxy = Url::parse(proxy).chain_err(|| {
+            ErrorKind::InvalidConfig(format!("proxy '{}' ({})", proxy, network.proxy.origin))
+        })?;
+        let network = network.clone();
+        builder = builder.proxy(reqwest::Proxy::custom(move |url| match url.host_str() {
+            Some(host) if network.bypasses_proxy(host) => None,
+            _ => Some(proxy.clone()),
+        }));
+    }
+    if let Some(path) = &network.ca_bundle.value {
+        let bundle = fs::read_to_string(path)
+            .chain_err(|| format!("failed to read CA bundle {}", path.to_string_lossy()))?;
+        const END: &str = "-----END CERTIFICATE-----";
+        // Certificates are added one by one, a bundle has several
+        for pem in bundle.split_inclusive(END).filter(|pem| pem.contains(END)) {
+            let certificate = reqwest::Certificate::from_pem(pem.as_bytes())
+                .chain_err(|| format!("invalid certificate in {}", path.to_string_lossy()))?;
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    builder = match network.ip_version.value {
+        IpVersion::Any => builder,
+        IpVersion::Ipv4 => builder.local_address(IpAddr::from(Ipv4Addr::UNSPECIFIED)),
+        IpVersion::Ipv6 => builder.local_address(IpAddr::from(Ipv6Addr::UNSPECIFIED)),
+    };
+    Ok(builder)
+}
+
+/// Download `url` into the file `dest`, with the network settings of the
+/// instance in `paths`.
+pub async fn download_to(paths: &Paths, url: &str, dest: &Path) -> Result<()> {
+    let mut file = std::fs::File::create(dest)
+        .chain_err(|| format!("failed to create {}", dest.to_string_lossy()))?;
+    download::get(paths, url, &mut file, &DownloadOptions::default(), |_| {}).await?;
+    Ok(())
+}
+
+/// [Modrinth](https://modrinth.com) API client.
+pub struct ModrinthRepository {
+    pub base_url: String,
+    /// Only versions for one of these loaders are listed
+    pub loaders: Vec<String>,
+    paths: Paths,
+}
+
+impl ModrinthRepository {
+    /// Modrinth, as the instance in `paths` is set up to reach it
+    pub fn new(paths: &Paths, loaders: &[&str]) -> Self {
+        Self {
+            base_url: settings::mirrors(paths).modrinth.value,
+            loaders: loaders.iter().map(|l| l.to_string()).collect(),
+            paths: paths.clone(),
+        }
+    }
+
+    /// Modrinth, listing versions which run on Paper
+    pub fn plugins(paths: &Paths) -> Self {
+        Self::new(paths, &["paper", "spigot", "bukkit"])
+    }
+}
+
+#[derive(Deserialize)]
+struct ModrinthHashes {
+    sha1: Option<String>,
+    sha512: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ModrinthFile {
+    url: String,
+    filename: String,
+    primary: bool,
+    hashes: ModrinthHashes,
+}
+
+#[derive(Deserialize)]
+struct ModrinthDependency {
+    project_id: Option<String>,
+    version_id: Option<String>,
+    dependency_type: String,
+}
+
+#[derive(Deserialize)]
+struct ModrinthVersion {
+    id: String,
+    project_id: String,
+    version_number: String,
+    date_published: Option<String>,
+    game_versions: Vec<String>,
+    files: Vec<ModrinthFile>,
+    #[serde(default)]
+    dependencies: Vec<ModrinthDependency>,
+}
+
+#[async_trait]
+impl PluginRepository for ModrinthRepository {
+    async fn versions(&self, project: &str) -> Result<Vec<RemoteVersion>> {
+        let loaders =
+            serde_json::to_string(&self.loaders).chain_err(|| "failed to encode loaders")?;
+        let response = http_client(&self.paths)?
+            .get(format!("{}/project/{}/version", self.base_url, project))
+            .query(&[("loaders", loaders)])
+            .send()
+            .await
+            .chain_err(|| "modrinth: failed to request version list")?;
+        if response.status() == StatusCode::NOT_FOUND {
+            bail!(ErrorKind::NotFound(format!(
+                "modrinth: project {}",
+                project
+            )));
+        }
+        let versions: Vec<ModrinthVersion> = response
+            .error_for_status()
+            .chain_err(|| "modrinth: failed to request version list")?
+            .json()
+            .await
+            .chain_err(|| "modrinth: failed to decode version list")?;
+        Ok(versions
+            .into_iter()
+            .filter_map(|v| {
+                let index = v.files.iter().position(|f| f.primary).unwrap_or(0);
+                let file = v.files.into_iter().nth(index)?;
+                Some(RemoteVersion {
+                    id: v.id,
+                    project_id: v.project_id,
+                    version: v.version_number,
+                    game_versions: v.game_versions,
+                    file: RemoteFile {
+                        url: file.url,
+                        file_name: file.filename,
+                        sha1: file.hashes.sha1,
+                        sha256: None,
+                        sha512: file.hashes.sha512,
+                    },
+                    dependencies: v
+                        .dependencies
+                        .into_iter()
+                        .filter(|d| d.dependency_type == "required")
+                        .filter_map(|d| {
+                            Some(RemoteDependency {
+                                project: d.project_id?,
+                                version_id: d.version_id,
+                            })
+                        })
+                        .collect(),
+                    published: v.date_published,
+                })
+            })
+            .collect())
+    }
+
+    async fn download(&self, file: &RemoteFile, dest: &Path) -> Result<()> {
+        download_to(&self.paths, &file.url, dest).await
+    }
+}
+
+/// [Hangar](https://hangar.papermc.io) API client.
+pub struct HangarRepository {
+    pub base_url: String,
+    paths: Paths,
+}
+
+impl HangarRepository {
+    /// Hangar, as the instance in `paths` is set up to reach it
+    pub fn new(paths: &Paths) -> Self {
+        Self {
+            base_url: settings::mirrors(paths).hangar.value,
+            paths: paths.clone(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HangarFileInfo {
+    name: String,
+    sha256_hash: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HangarDownload {
+    file_info: Option<HangarFileInfo>,
+    external_url: Option<String>,
+    download_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HangarVersion {
+    name: String,
+    created_at: Option<String>,
+    platform_dependencies: HashMap<String, Vec<String>>,
+    downloads: HashMap<String, HangarDownload>,
+}
+
+#[derive(Deserialize)]
+struct HangarPagination {
+    count: usize,
+}
+
+#[derive(Deserialize)]
+struct HangarVersions {
+    pagination: HangarPagination,
+    result: Vec<HangarVersion>,
+}
+
+/// Versions requested from Hangar at once, the most it allows
+const HANGAR_PAGE_SIZE: usize = 25;
+
+impl HangarRepository {
+    async fn page(&self, project: &str, offset: usize) -> Result<HangarVersions> {
+        let response = http_client(&self.paths)?
+            .get(format!("{}/projects/{}/versions", self.base_url, project))
+            .query(&[
+                ("limit", HANGAR_PAGE_SIZE.to_string()),
+                ("offset", offset.to_string()),
+                ("platform", "PAPER".to_owned()),
+            ])
+            .send()
+            .await
+            .chain_err(|| "hangar: failed to request version list")?;
+        if response.status() == StatusCode::NOT_FOUND {
+            bail!(ErrorKind::NotFound(format!("hangar: project {}", project)));
+        }
+        response
+            .error_for_status()
+            .chain_err(|| "hangar: failed to request version list")?
+            .json()
+            .await
+            .chain_err(|| "hangar: failed to decode version list")
+    }
+}
+
+#[async_trait]
+impl PluginRepository for HangarRepository {
+    async fn versions(&self, project: &str) -> Result<Vec<RemoteVersion>> {
+        let mut versions = vec![];
+        loop {
+            let page = self.page(project, versions.len()).await?;
+            let last = page.result.is_empty()
+                || versions.len() + page.result.len() >= page.pagination.count;
+            versions.extend(page.result);
+            if last {
+                break;
+            }
+        }
+        Ok(versions
+            .into_iter()
+            .filter_map(|mut v| {
+                let download = v.downloads.remove("PAPER")?;
+                let url = download.download_url.or(download.external_url)?;
+                let (file_name, sha256) = match download.file_info {
+                    Some(info) => (info.name, Some(info.sha256_hash)),
+                    None => (format!("{}-{}.jar", project, v.name), None),
+                };
+                Some(RemoteVersion {
+                    id: v.name.clone(),
+                    project_id: project.to_owned(),
+                    game_versions: v.platform_dependencies.remove("PAPER").unwrap_or_default(),
+                    version: v.name,
+                    file: RemoteFile {
+                        url,
+                        file_name,
+                        sha256,
+                        ..Default::default()
+                    },
+                    dependencies: vec![],
+                    published: v.created_at,
+                })
+            })
+            .collect())
+    }
+
+    async fn download(&self, file: &RemoteFile, dest: &Path) -> Result<()> {
+        download_to(&self.paths, &file.url, dest).await
+    }
+}
+
+/// CurseForge API client. The API needs a key, which is read from the
+/// `CURSEFORGE_API_KEY` environment variable by default.
+///
+/// Projects can be given by their numeric ID or by their slug.
+pub struct CurseForgeRepository {
+    pub base_url: String,
+    pub api_key: Option<String>,
+    /// CurseForge mod loader type (1: Forge, 4: Fabric, 5: Quilt, 6: NeoForge)
+    pub loader_type: Option<u32>,
+    paths: Paths,
+}
+
+const CURSEFORGE_MINECRAFT_ID: u32 = 432;
+const CURSEFORGE_MODS_CLASS_ID: u32 = 6;
+
+impl CurseForgeRepository {
+    /// CurseForge, as the instance in `paths` is set up to reach it
+    pub fn new(paths: &Paths, loader: &str) -> Self {
+        Self {
+            base_url: settings::mirrors(paths).curseforge.value,
+            api_key: std::env::var("CURSEFORGE_API_KEY").ok(),
+            loader_type: match loader {
+                "forge" => Some(1),
+                "fabric" => Some(4),
+                "quilt" => Some(5),
+                "neoforge" => Some(6),
+                _ => None,
+            },
+            paths: paths.clone(),
+        }
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<T> {
+        let api_key = match &self.api_key {
+            Some(key) => key,
+            None => bail!("curseforge: an API key is needed (set CURSEFORGE_API_KEY)"),
+        };
+        let response = http_client(&self.paths)?
+            .get(format!("{}{}", self.base_url, path))
+            .header("x-api-key", api_key)
+            .query(query)
+            .send()
+            .await
+            .chain_err(|| format!("curseforge: failed to request {}", path))?;
+        if response.status() == StatusCode::NOT_FOUND {
+            bail!(ErrorKind::NotFound(format!("curseforge: {}", path)));
+        }
+        response
+            .error_for_status()
+            .chain_err(|| format!("curseforge: failed to request {}", path))?
+            .json::<CurseForgeResponse<T>>()
+            .await
+            .map(|r| r.data)
+            .chain_err(|| format!("curseforge: failed to decode {}", path))
+    }
+
+    /// Find the numeric ID of a project.
+    async fn project_id(&self, project: &str) -> Result<String> {
+        if project.chars().all(|c| c.is_ascii_digit()) {
+            return Ok(project.to_owned());
+        }
+        let found: Vec<CurseForgeMod> = self
+            .get(
+                "/mods/search",
+                &[
+                    ("gameId", CURSEFORGE_MINECRAFT_ID.to_string()),
+                    ("classId", CURSEFORGE_MODS_CLASS_ID.to_string()),
+                    ("slug", project.to_owned()),
+                ],
+            )
+            .await?;
+        match found.first() {
+            Some(m) => Ok(m.id.to_string()),
+            None => bail!(ErrorKind::NotFound(format!(
+                "curseforge: project {}",
+                project
+            ))),
+        }
+    }
+
+    /// Get a single file of a project, as listed in modpack manifests.
+    pub async fn file(&self, project_id: u64, file_id: u64) -> Result<RemoteVersion> {
+        let file: CurseForgeFile = self
+            .get(&format!("/mods/{}/files/{}", project_id, file_id), &[])
+            .await?;
+        Ok(file.into())
+    }
+}
+
+#[derive(Deserialize)]
+struct CurseForgeResponse<T> {
+    data: T,
+}
+
+#[derive(Deserialize)]
+struct CurseForgeMod {
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct CurseForgeHash {
+    value: String,
+    algo: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CurseForgeDependency {
+    mod_id: u64,
+    relation_type: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CurseForgeFile {
+    id: u64,
+    mod_id: u64,
+    display_name: String,
+    file_name: String,
+    file_date: Option<String>,
+    download_url: Option<String>,
+    #[serde(default)]
+    game_versions: Vec<String>,
+    #[serde(default)]
+    hashes: Vec<CurseForgeHash>,
+    #[serde(default)]
+    dependencies: Vec<CurseForgeDependency>,
+}
+
+impl From<CurseForgeFile> for RemoteVersion {
+    fn from(file: CurseForgeFile) -> Self {
+        const SHA1: u32 = 1;
+        const REQUIRED_DEPENDENCY: u32 = 3;
+        Self {
+            id: file.id.to_string(),
+            project_id: file.mod_id.to_string(),
+            version: file.display_name,
+            game_versions: file.game_versions,
+            file: RemoteFile {
+                // Files of projects which opted out of third-party
+                // distribution have no download URL
+                url: file.download_url.unwrap_or_default(),
+                file_name: file.file_name,
+                sha1: file
+                    .hashes
+                    .into_iter()
+                    .find(|h| h.algo == SHA1)
+                    .map(|h| h.value),
+                ..Default::default()
+            },
+            dependencies: file
+                .dependencies
+                .into_iter()
+                .filter(|d| d.relation_type == REQUIRED_DEPENDENCY)
+                .map(|d| RemoteDependency {
+                    project: d.mod_id.to_string(),
+                    version_id: None,
+                })
+                .collect(),
+            published: file.file_date,
+        }
+    }
+}
+
+#[async_trait]
+impl PluginRepository for CurseForgeRepository {
+    async fn versions(&self, project: &str) -> Result<Vec<RemoteVersion>> {
+        let id = self.project_id(project).await?;
+        let mut query = vec![("pageSize", "50".to_owned())];
+        if let Some(loader_type) = self.loader_type {
+            query.push(("modLoaderType", loader_type.to_string()));
+        }
+        let files: Vec<CurseForgeFile> = self.get(&format!("/mods/{}/files", id), &query).await?;
+        Ok(files.into_iter().map(RemoteVersion::from).collect())
+    }
+
+    async fn download(&self, file: &RemoteFile, dest: &Path) -> Result<()> {
+        if file.url.is_empty() {
+            bail!(
+                "curseforge: {} can not be downloaded by third-party tools, download it manually",
+                file.file_name
+            );
+        }
+        download_to(&self.paths, &file.url, dest).await
+    }
+}
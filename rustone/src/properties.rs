@@ -0,0 +1,86 @@
+//! Reading and editing Java properties files, like `server.properties`.
+
+use std::{collections::BTreeMap, fs, path::Path};
+
+use crate::{config::ServerConfig, errors::*};
+
+/// The port Minecraft listens on when `server.properties` doesn't set one.
+pub const DEFAULT_PORT: u16 = 25565;
+
+/// Parse Java properties, ignoring escapes.
+pub fn parse(text: &str) -> impl Iterator<Item = (&str, &str)> {
+    text.lines()
+        .map(str::trim)
+        .filter(|l| !l.starts_with('#') && !l.starts_with('!'))
+        .filter_map(|l| l.split_once('='))
+        .map(|(k, v)| (k.trim(), v.trim()))
+}
+
+/// Read a properties file. A missing file has no properties.
+pub fn read(path: &Path) -> Result<BTreeMap<String, String>> {
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let text = fs::read_to_string(path)
+        .chain_err(|| format!("failed to read {}", path.to_string_lossy()))?;
+    Ok(parse(&text)
+        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+        .collect())
+}
+
+/// Set properties in `text`, keeping comments and the order of the other
+/// lines. Keys which are not there yet are appended.
+///
+/// # Examples
+/// ```
+/// use rustone::properties::set;
+/// let text = "#Minecraft server properties\nserver-port=25565\nmotd=Hi\n";
+/// assert_eq!(
+///     set(text, &[("server-port", "25566"), ("pvp", "false")]),
+///     "#Minecraft server properties\nserver-port=25566\nmotd=Hi\npvp=false\n"
+/// );
+/// ```
+pub fn set(text: &str, values: &[(&str, &str)]) -> String {
+    let mut remaining: Vec<_> = values.to_vec();
+    let mut output = String::new();
+    for line in text.lines() {
+        let key = parse(line).next().map(|(k, _)| k);
+        match remaining.iter().position(|(k, _)| Some(*k) == key) {
+            Some(i) => {
+                let (key, value) = remaining.remove(i);
+                output.push_str(&format!("{}={}\n", key, value));
+            }
+            None => {
+                output.push_str(line);
+                output.push('\n');
+            }
+        }
+    }
+    for (key, value) in remaining {
+        output.push_str(&format!("{}={}\n", key, value));
+    }
+    output
+}
+
+/// Set properties in the file at `path`, creating it if needed.
+pub fn set_in_file(path: &Path, values: &[(&str, &str)]) -> Result<()> {
+    let text = if path.exists() {
+        fs::read_to_string(path)
+            .chain_err(|| format!("failed to read {}", path.to_string_lossy()))?
+    } else {
+        String::new()
+    };
+    fs::write(path, set(&text, values))
+        .chain_err(|| format!("failed to write {}", path.to_string_lossy()))
+}
+
+/// The port a server listens on, from its `server.properties`.
+pub fn server_port(config: &ServerConfig) -> Result<u16> {
+    let properties = read(&config.configs_path().join("server.properties"))?;
+    match properties.get("server-port") {
+        Some(port) if !port.is_empty() => port
+            .parse()
+            .chain_err(|| format!("{} has an invalid server-port: {}", config.name, port)),
+        _ => Ok(DEFAULT_PORT),
+    }
+}
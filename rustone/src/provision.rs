@@ -0,0 +1,251 @@
+//! Getting a server ready to start: updated as its policy allows, with its
+//! directories, EULA, server jar and Java in place. Frontends launch servers
+//! with [`Server::start_watched`] after [`Server::prepare`], and show their
+//! progress through a [`ProgressSink`].
+
+use std::{
+    path::PathBuf,
+    process::Stdio,
+    time::{Duration, Instant},
+};
+
+use tokio::process::Child;
+use tracing::{info, instrument};
+
+use crate::{
+    cacher::{self, JarKey},
+    config::ServerVersion,
+    download::{self, DownloadOptions, Progress},
+    errors::*,
+    java,
+    server_kinds::{Build, ServerKind},
+    servers::Server,
+    settings,
+    updates::{self, Startup},
+};
+
+/// What [`Server::prepare`] should do besides making sure the server can
+/// start.
+#[derive(Debug, Clone, Copy)]
+pub struct ProvisionOptions {
+    /// Apply the update the server's policy allows, unless offline
+    pub update: bool,
+    pub download: DownloadOptions,
+}
+
+impl Default for ProvisionOptions {
+    fn default() -> Self {
+        Self {
+            update: true,
+            download: DownloadOptions::default(),
+        }
+    }
+}
+
+/// An update applied while preparing a server.
+#[derive(Debug, Clone)]
+pub struct Update {
+    pub from: ServerVersion,
+    pub to: ServerVersion,
+    /// The builds in between, with what changed in them. Empty if the
+    /// changelog could not be fetched.
+    pub builds: Vec<Build>,
+}
+
+/// A prepared server.
+#[derive(Debug, Clone)]
+pub struct Prepared {
+    pub update: Option<Update>,
+    /// The cached server jar
+    pub jar: PathBuf,
+    /// The `java` executable to launch the server with
+    pub java: PathBuf,
+}
+
+/// Where the standard streams of a server launched with
+/// [`Server::start_watched`] go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Console {
+    /// The streams of the frontend. The output of a new build goes through
+    /// `on_line` instead while it is watched.
+    Inherit,
+    /// Pipes for the frontend to use. The output of a new build is taken for
+    /// `on_line`.
+    Piped,
+}
+
+impl Console {
+    fn stdio(self) -> Stdio {
+        match self {
+            Self::Inherit => Stdio::inherit(),
+            Self::Piped => Stdio::piped(),
+        }
+    }
+}
+
+/// Receives what [`Server::prepare`] and [`Server::start_watched`] are doing. Every method does nothing by
+/// default, and `()` ignores everything.
+pub trait ProgressSink: Sync {
+    /// A step started, or finished with something worth telling
+    fn status(&self, _message: &str) {}
+    /// Something went wrong without stopping the preparation
+    fn warning(&self, _message: &str) {}
+    /// The server was updated
+    fn updated(&self, _update: &Update) {}
+    /// The server jar is downloading
+    fn download(&self, _progress: Progress) {}
+    /// The server jar finished downloading
+    fn downloaded(&self) {}
+    /// The new build `bad` did not start, and the server was rolled back to
+    /// `to`
+    fn rolled_back(&self, _bad: &ServerVersion, _startup: Startup, _to: &ServerVersion) {}
+}
+
+impl ProgressSink for () {}
+
+impl Server {
+    /// Make sure the server can start: apply the update its policy allows if
+    /// `options` say so, pin its build, create its directories, accept the
+    /// EULA, cache its jar and find Java for it. Changes to the
+    /// configuration are saved.
+    #[instrument(skip_all, fields(server = %self.config.name))]
+    pub async fn prepare(
+        &mut self,
+        options: &ProvisionOptions,
+        sink: &dyn ProgressSink,
+    ) -> Result<Prepared> {
+        let kind = self.config.kind.parse::<ServerKind>()?;
+        let paths = self.config.paths.clone();
+        let begun = Instant::now();
+
+        let mut update = None;
+        if options.update {
+            if settings::effective(&paths, Some(&self.config))?
+                .offline
+                .value
+            {
+                sink.status("Offline, not checking for updates");
+            } else {
+                sink.status("Checking for updates...");
+                let version = updates::update(&self.config, false).await?;
+                if updates::same_build(&version, &self.config.version) {
+                    sink.status("Server up to date");
+                } else {
+                    let from = self.config.version;
+                    updates::apply(&mut self.config, version);
+                    self.save()?;
+                    let builds = match updates::changelog(&paths, &kind, &from, &version).await {
+                        Ok(builds) => builds,
+                        Err(e) => {
+                            sink.warning(&format!("could not get the changelog: {}", e));
+                            vec![]
+                        }
+                    };
+                    updates::log_update(&self.config, from, &builds)?;
+                    let applied = Update {
+                        from,
+                        to: version,
+                        builds,
+                    };
+                    sink.updated(&applied);
+                    update = Some(applied);
+                }
+            }
+        }
+
+        // Servers reference exact builds
+        if self.config.version.patch.is_none() {
+            let minecraft = self.config.version.minecraft;
+            self.config.version.patch = Some(kind.get_latest_patch(&paths, &minecraft).await?);
+            self.save()?;
+        }
+
+        // Creates the directories and accepts the EULA
+        kind.initialize(&self.config)?;
+
+        let key = JarKey::of(&self.config).unwrap();
+        let jar = match cacher::get_cached(&paths, &key)? {
+            Some((jar, _)) => jar,
+            None => {
+                sink.status("Downloading server jar...");
+                let mut version = self.config.version;
+                let (response, mut writer) = cacher::download(&paths, &kind, &mut version).await?;
+                download::stream(
+                    &paths,
+                    response,
+                    &mut writer,
+                    &options.download,
+                    |progress| sink.download(progress),
+                )
+                .await?;
+                sink.downloaded();
+                writer.finish()?
+            }
+        };
+
+        let java = java::java_for(&self.config).await?;
+        info!(
+            version = %self.config.version,
+            java = %java.display(),
+            elapsed = ?begun.elapsed(),
+            "server prepared"
+        );
+        Ok(Prepared { update, jar, java })
+    }
+
+    /// Launch the server after [`Server::prepare`]. A new build, which can
+    /// still be rolled back, is watched while it starts, its output passed
+    /// to `on_line`. If it exits or hangs before it is done starting, it is
+    /// marked bad, and the server is rolled back to the build before it,
+    /// prepared again with `options` (without updating) and launched. A
+    /// build which started is kept. Returns the running server.
+    #[instrument(skip_all, fields(server = %self.config.name))]
+    pub async fn start_watched<F>(
+        &mut self,
+        options: &ProvisionOptions,
+        console: Console,
+        sink: &dyn ProgressSink,
+        on_line: F,
+    ) -> Result<Child>
+    where
+        F: FnMut(&str) + Send + 'static,
+    {
+        let kind = self.config.kind.parse::<ServerKind>()?;
+        if self.config.rollback_to.is_some() {
+            let mut child = kind
+                .launch(
+                    self.config.clone(),
+                    Stdio::piped(),
+                    console.stdio(),
+                    console.stdio(),
+                )
+                .await?;
+            let timeout = Duration::from_secs(
+                self.config
+                    .startup_timeout
+                    .unwrap_or(updates::DEFAULT_STARTUP_TIMEOUT),
+            );
+            let startup = updates::watch_startup(&mut child, timeout, on_line).await?;
+            if startup == Startup::Done {
+                self.config.rollback_to = None;
+                self.save()?;
+                return Ok(child);
+            }
+            let bad = updates::roll_back(&mut self.config).unwrap();
+            self.save()?;
+            sink.rolled_back(&bad, startup, &self.config.version);
+            let options = ProvisionOptions {
+                update: false,
+                ..*options
+            };
+            self.prepare(&options, sink).await?;
+        }
+        kind.launch(
+            self.config.clone(),
+            console.stdio(),
+            console.stdio(),
+            console.stdio(),
+        )
+        .await
+    }
+}
@@ -1,60 +1,181 @@
-use std::{process::Stdio, str::FromStr};
+use std::{fmt::Display, process::Stdio, str::FromStr};
 
 use crate::{
     config::{MinecraftVersion, ServerConfig, ServerVersion},
     errors::*,
+    java,
+    paths::Paths,
 };
 use error_chain::bail;
 use reqwest::Response;
+use serde::{Deserialize, Serialize};
+use tracing::info;
 
+pub mod fabric;
 pub mod forge;
 pub mod paper;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ServerKind {
     Paper,
+    Fabric,
+    Forge,
+}
+
+/// A change in a build, usually a commit.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    pub summary: String,
+}
+
+/// A build of a server kind for one Minecraft version.
+#[derive(Debug, Clone)]
+pub struct Build {
+    pub number: u32,
+    /// When the build was made, as an RFC 3339 timestamp, if known
+    pub time: Option<String>,
+    /// The release channel, like `default` or `experimental` for Paper and
+    /// `stable` or `unstable` for Fabric
+    pub channel: String,
+    pub changes: Vec<Change>,
+    /// The SHA-256 of the server jar, if upstream publishes it
+    pub sha256: Option<String>,
 }
 
 impl ServerKind {
-    pub async fn is_latest_patch(&self, version: &MinecraftVersion, patch: u32) -> Result<bool> {
+    /// Every server kind rustone supports.
+    pub const ALL: &'static [ServerKind] = &[Self::Paper, Self::Fabric, Self::Forge];
+
+    pub async fn is_latest_patch(
+        &self,
+        paths: &Paths,
+        version: &MinecraftVersion,
+        patch: u32,
+    ) -> Result<bool> {
         match self {
-            Self::Paper => paper::is_latest_patch(version, patch).await,
+            Self::Paper => paper::is_latest_patch(paths, version, patch).await,
+            Self::Fabric => fabric::is_latest_patch(paths, version, patch).await,
+            Self::Forge => forge::is_latest_patch(paths, version, patch).await,
         }
     }
 
-    pub async fn get_latest(&self) -> Result<ServerVersion> {
+    pub async fn get_latest(&self, paths: &Paths) -> Result<ServerVersion> {
         match self {
-            Self::Paper => paper::get_latest().await,
+            Self::Paper => paper::get_latest(paths).await,
+            Self::Fabric => fabric::get_latest(paths).await,
+            Self::Forge => forge::get_latest(paths).await,
         }
     }
 
-    pub async fn get_latest_patch(&self, version: &MinecraftVersion) -> Result<u32> {
+    pub async fn get_versions(&self, paths: &Paths) -> Result<Vec<MinecraftVersion>> {
         match self {
-            Self::Paper => paper::get_latest_patch(version).await,
+            Self::Paper => paper::get_versions(paths).await,
+            Self::Fabric => fabric::get_versions(paths).await,
+            Self::Forge => forge::get_versions(paths).await,
         }
     }
 
-    pub async fn download_response(&self, version: &mut ServerVersion) -> Result<Response> {
+    /// The builds of `version`, oldest first.
+    pub async fn get_builds(
+        &self,
+        paths: &Paths,
+        version: &MinecraftVersion,
+    ) -> Result<Vec<Build>> {
         match self {
-            Self::Paper => paper::download_response(version).await,
+            Self::Paper => paper::get_builds(paths, version).await,
+            Self::Fabric => fabric::get_builds(paths, version).await,
+            Self::Forge => forge::get_builds(paths, version).await,
+        }
+    }
+
+    /// The SHA-256 upstream publishes for the jar of a build, `None` if it
+    /// publishes none. Downloads are only left unchecked then.
+    pub async fn get_build_sha256(
+        &self,
+        paths: &Paths,
+        version: &MinecraftVersion,
+        build: u32,
+    ) -> Result<Option<String>> {
+        let build = self
+            .get_builds(paths, version)
+            .await?
+            .into_iter()
+            .find(|b| b.number == build)
+            .ok_or_else(|| {
+                ErrorKind::NotFound(format!(
+                    "{}: build {} of {}",
+                    self,
+                    self.build_name(build),
+                    version
+                ))
+            })?;
+        Ok(build.sha256)
+    }
+
+    pub async fn get_latest_patch(&self, paths: &Paths, version: &MinecraftVersion) -> Result<u32> {
+        match self {
+            Self::Paper => paper::get_latest_patch(paths, version).await,
+            Self::Fabric => fabric::get_latest_patch(paths, version).await,
+            Self::Forge => forge::get_latest_patch(paths, version).await,
+        }
+    }
+
+    pub async fn download_response(
+        &self,
+        paths: &Paths,
+        version: &mut ServerVersion,
+    ) -> Result<Response> {
+        match self {
+            Self::Paper => paper::download_response(paths, version).await,
+            Self::Fabric => fabric::download_response(paths, version).await,
+            Self::Forge => forge::download_response(paths, version).await,
         }
     }
 
     pub fn initialize(&self, config: &ServerConfig) -> Result<()> {
         match self {
             Self::Paper => paper::initialize(config),
+            Self::Fabric => fabric::initialize(config),
+            Self::Forge => forge::initialize(config),
+        }
+    }
+
+    /// How a build number is shown to users: Fabric builds are loader
+    /// versions, Forge builds Forge versions.
+    pub fn build_name(&self, build: u32) -> String {
+        match self {
+            Self::Paper => build.to_string(),
+            Self::Fabric | Self::Forge => fabric::decode_loader_version(build),
         }
     }
 
-    pub fn launch(
+    /// The mod loader of modded server kinds
+    pub fn mod_loader(&self) -> Option<&'static str> {
+        match self {
+            Self::Paper => None,
+            Self::Fabric => Some("fabric"),
+            Self::Forge => Some("forge"),
+        }
+    }
+
+    pub async fn launch(
         &self,
         config: ServerConfig,
         stdout: Stdio,
         stdin: Stdio,
         stderr: Stdio,
     ) -> Result<tokio::process::Child> {
-        match self {
-            Self::Paper => paper::launch(config, stdout, stdin, stderr),
-        }
+        let java = java::java_for(&config).await?;
+        let name = config.name.clone();
+        let child = match self {
+            Self::Paper => paper::launch(config, &java, stdout, stdin, stderr),
+            Self::Fabric => fabric::launch(config, &java, stdout, stdin, stderr),
+            Self::Forge => forge::launch(config, &java, stdout, stdin, stderr).await,
+        }?;
+        info!(server = %name, pid = ?child.id(), java = %java.display(), "server launched");
+        Ok(child)
     }
 }
 
@@ -64,15 +185,23 @@ impl FromStr for ServerKind {
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
         match s {
             "paper" => Ok(Self::Paper),
+            "fabric" => Ok(Self::Fabric),
+            "forge" => Ok(Self::Forge),
+            "neoforge" => bail!(
+                "{} servers are not supported yet, only paper, fabric and forge servers are",
+                s
+            ),
             _ => bail!("string {} is not a valid server type", s),
         }
     }
 }
 
-impl ToString for ServerKind {
-    fn to_string(&self) -> String {
+impl Display for ServerKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Paper => "paper".to_owned(),
+            Self::Paper => write!(f, "paper"),
+            Self::Fabric => write!(f, "fabric"),
+            Self::Forge => write!(f, "forge"),
         }
     }
 }
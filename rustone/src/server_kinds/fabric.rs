@@ -0,0 +1,259 @@
+//! Fabric servers, launched through the Fabric server launcher.
+//!
+//! Fabric has no build numbers like Paper. The loader version is used as
+//! the build instead, encoded into a single number: `0.15.7` is build 15007.
+
+use std::{fs, io::Write, process::Stdio};
+
+use error_chain::bail;
+use serde::Deserialize;
+
+use super::Build;
+use crate::{
+    cacher,
+    config::{MinecraftVersion, ServerLayout, ServerVersion},
+    errors::*,
+    metadata,
+    paths::Paths,
+    plugins::sources::download_client,
+    settings,
+};
+
+/// Encode a loader version into a build number.
+///
+/// # Examples
+/// ```
+/// use rustone::server_kinds::fabric::{decode_loader_version, encode_loader_version};
+/// assert_eq!(encode_loader_version("0.15.7"), Some(15007));
+/// assert_eq!(decode_loader_version(15007), "0.15.7");
+/// assert_eq!(encode_loader_version("0.15.7+build.1"), None);
+/// ```
+pub fn encode_loader_version(version: &str) -> Option<u32> {
+    let mut parts = version.split('.').map(|p| p.parse::<u32>().ok());
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(Some(major)), Some(Some(minor)), Some(Some(patch)), None)
+            if minor < 1000 && patch < 1000 =>
+        {
+            Some(major * 1_000_000 + minor * 1000 + patch)
+        }
+        _ => None,
+    }
+}
+
+pub fn decode_loader_version(build: u32) -> String {
+    format!(
+        "{}.{}.{}",
+        build / 1_000_000,
+        build / 1000 % 1000,
+        build % 1000
+    )
+}
+
+#[derive(Deserialize)]
+struct GameVersion {
+    version: String,
+    stable: bool,
+}
+
+#[derive(Deserialize)]
+struct LoaderVersion {
+    version: String,
+    stable: bool,
+}
+
+#[derive(Deserialize)]
+struct LoaderEntry {
+    loader: LoaderVersion,
+}
+
+#[derive(Deserialize)]
+struct InstallerVersion {
+    version: String,
+    stable: bool,
+}
+
+pub async fn is_latest_patch(
+    paths: &Paths,
+    version: &MinecraftVersion,
+    patch: u32,
+) -> Result<bool> {
+    Ok(get_latest_patch(paths, version).await? <= patch)
+}
+
+/// The stable Minecraft versions Fabric supports.
+pub async fn get_versions(paths: &Paths) -> Result<Vec<MinecraftVersion>> {
+    let url = format!("{}/versions/game", settings::mirrors(paths).fabric.value);
+    let versions: Vec<GameVersion> = metadata::get_json(paths, &url)
+        .await
+        .chain_err(|| "fabric: failed to get version list")?
+        .ok_or_else(|| ErrorKind::NotFound("fabric: version list".to_owned()))?;
+    Ok(versions
+        .iter()
+        .filter(|v| v.stable)
+        .filter_map(|v| v.version.parse::<MinecraftVersion>().ok())
+        .collect())
+}
+
+pub async fn get_latest(paths: &Paths) -> Result<ServerVersion> {
+    let latest = get_versions(paths)
+        .await?
+        .into_iter()
+        .max()
+        .ok_or_else::<Error, _>(|| "fabric: no maximum version found".into())?;
+    Ok(ServerVersion {
+        minecraft: latest,
+        patch: Some(get_latest_patch(paths, &latest).await?),
+    })
+}
+
+async fn get_loaders(paths: &Paths, version: &MinecraftVersion) -> Result<Vec<LoaderEntry>> {
+    let url = format!(
+        "{}/versions/loader/{}",
+        settings::mirrors(paths).fabric.value,
+        version
+    );
+    metadata::get_json(paths, &url)
+        .await
+        .chain_err(|| "fabric: failed to get loader list")?
+        .ok_or_else(|| ErrorKind::NotFound(format!("fabric: minecraft version {}", version)).into())
+}
+
+/// The loader versions for `version`. Fabric does not publish when they
+/// were released, nor changelogs.
+pub async fn get_builds(paths: &Paths, version: &MinecraftVersion) -> Result<Vec<Build>> {
+    let mut builds: Vec<Build> = get_loaders(paths, version)
+        .await?
+        .into_iter()
+        .filter_map(|l| {
+            Some(Build {
+                number: encode_loader_version(&l.loader.version)?,
+                time: None,
+                channel: if l.loader.stable {
+                    "stable"
+                } else {
+                    "unstable"
+                }
+                .to_owned(),
+                changes: vec![],
+                sha256: None,
+            })
+        })
+        .collect();
+    builds.sort_by_key(|b| b.number);
+    Ok(builds)
+}
+
+pub async fn get_latest_patch(paths: &Paths, version: &MinecraftVersion) -> Result<u32> {
+    get_loaders(paths, version)
+        .await?
+        .iter()
+        .filter(|l| l.loader.stable)
+        .find_map(|l| encode_loader_version(&l.loader.version))
+        .ok_or_else(|| format!("fabric: no loader found for minecraft version {}", version).into())
+}
+
+async fn get_latest_installer(paths: &Paths) -> Result<String> {
+    let url = format!(
+        "{}/versions/installer",
+        settings::mirrors(paths).fabric.value
+    );
+    let installers: Vec<InstallerVersion> = metadata::get_json(paths, &url)
+        .await
+        .chain_err(|| "fabric: failed to get installer list")?
+        .ok_or_else(|| ErrorKind::NotFound("fabric: installer list".to_owned()))?;
+    installers
+        .into_iter()
+        .find(|i| i.stable)
+        .map(|i| i.version)
+        .ok_or_else(|| "fabric: no stable installer found".into())
+}
+
+pub async fn download_response(
+    paths: &Paths,
+    version: &mut ServerVersion,
+) -> Result<reqwest::Response> {
+    let patch = match version.patch {
+        Some(p) => p,
+        None => get_latest_patch(paths, &version.minecraft).await?,
+    };
+    version.patch = Some(patch);
+    let url = format!(
+        "{}/versions/loader/{}/{}/{}/server/jar",
+        settings::mirrors(paths).fabric.value,
+        version.minecraft,
+        decode_loader_version(patch),
+        get_latest_installer(paths).await?
+    );
+    match download_client(paths)?.get(url).send().await {
+        Ok(resp) => {
+            if resp.status().is_success() {
+                Ok(resp)
+            } else {
+                bail!(ErrorKind::NotFound(format!(
+                    "fabric: server version {}",
+                    version
+                )));
+            }
+        }
+        Err(e) => Err(e).chain_err(|| format!("fabric: failed to download version {}", version)),
+    }
+}
+
+pub fn initialize(config: &crate::config::ServerConfig) -> Result<()> {
+    for path in &[
+        config.configs_path(),
+        config.worlds_path(),
+        config.path().join("mods"),
+    ] {
+        fs::create_dir_all(path).chain_err(|| {
+            format!(
+                "fabric: failed to create directory @ {}",
+                path.to_string_lossy()
+            )
+        })?;
+    }
+    let mut eula_file = fs::File::create(config.configs_path().join("eula.txt"))
+        .chain_err(|| "fabric: failed to create eula file")?;
+    writeln!(&mut eula_file, "eula=true").chain_err(|| "fabric: failed to write into eula file")?;
+    Ok(())
+}
+
+pub fn launch(
+    config: crate::config::ServerConfig,
+    java: &std::path::Path,
+    stdout: Stdio,
+    stdin: Stdio,
+    stderr: Stdio,
+) -> Result<tokio::process::Child> {
+    let world_path = config
+        .worlds_path()
+        .canonicalize()
+        .chain_err(|| "canonicalize failed")?;
+    let mut command = tokio::process::Command::new(java);
+    command.args(crate::jvm::java_args(&config)?);
+    // Mods are kept outside of the working directory, like Paper plugins.
+    // Flat servers have them in the working directory, where Fabric looks
+    // by itself.
+    if config.layout == ServerLayout::Standard {
+        let mods_path = config
+            .path()
+            .join("mods")
+            .canonicalize()
+            .chain_err(|| "canonicalize failed")?;
+        command.arg(format!("-Dfabric.addMods={}", mods_path.to_string_lossy()));
+    }
+    let child = command
+        .arg("-jar")
+        .arg(cacher::server_jar(&config)?)
+        .arg("--nogui")
+        .arg("--universe")
+        .arg(world_path)
+        .args(&config.extra_server_args)
+        .current_dir(config.configs_path())
+        .stdout(stdout)
+        .stdin(stdin)
+        .stderr(stderr)
+        .spawn()
+        .chain_err(|| ErrorKind::ProcessFailed("spawning the server process".to_owned()))?;
+    Ok(child)
+}
@@ -0,0 +1,267 @@
+//! Forge servers, set up by the Forge installer.
+//!
+//! The Forge version is used as the build, encoded like Fabric loader
+//! versions: `47.2.0` is build 47002000. The installer is what is cached;
+//! it installs Forge into the working directory of the server when the
+//! server is launched with a build for the first time.
+
+use std::{fs, io::Write, path::Path, process::Stdio};
+
+use error_chain::bail;
+use tracing::info;
+
+use super::{
+    fabric::{decode_loader_version, encode_loader_version},
+    Build,
+};
+use crate::{
+    cacher,
+    config::{MinecraftVersion, ServerConfig, ServerVersion},
+    errors::*,
+    metadata,
+    paths::Paths,
+    plugins::sources::download_client,
+    settings,
+};
+
+/// Records which Forge version is installed in the working directory
+const INSTALLED_FILE: &str = ".forge-installed";
+
+/// Parse the versions listed in the `maven-metadata.xml` of Forge into
+/// Minecraft versions and builds. Versions which do not fit a build, like
+/// the four-part ones of old Forge releases, are left out.
+///
+/// # Examples
+/// ```
+/// use rustone::server_kinds::forge::parse_maven_metadata;
+/// let builds = parse_maven_metadata(
+///     "<metadata><versioning><versions>\
+///     <version>1.20.1-47.2.0</version>\
+///     <version>1.12.2-14.23.5.2859</version>\
+///     </versions></versioning></metadata>",
+/// );
+/// assert_eq!(builds.len(), 1);
+/// assert_eq!(builds[0].0.to_string(), "1.20.1");
+/// assert_eq!(builds[0].1, 47002000);
+/// ```
+pub fn parse_maven_metadata(xml: &str) -> Vec<(MinecraftVersion, u32)> {
+    xml.split("<version>")
+        .skip(1)
+        .filter_map(|s| s.split("</version>").next())
+        .filter_map(|v| {
+            let (minecraft, forge) = v.trim().split_once('-')?;
+            Some((minecraft.parse().ok()?, encode_loader_version(forge)?))
+        })
+        .collect()
+}
+
+async fn get_all_builds(paths: &Paths) -> Result<Vec<(MinecraftVersion, u32)>> {
+    let url = format!(
+        "{}/maven-metadata.xml",
+        settings::mirrors(paths).forge.value
+    );
+    let xml = metadata::fetch(paths, &url)
+        .await
+        .chain_err(|| "forge: failed to get version list")?
+        .ok_or_else(|| ErrorKind::NotFound("forge: version list".to_owned()))?;
+    Ok(parse_maven_metadata(&xml))
+}
+
+pub async fn is_latest_patch(
+    paths: &Paths,
+    version: &MinecraftVersion,
+    patch: u32,
+) -> Result<bool> {
+    Ok(get_latest_patch(paths, version).await? <= patch)
+}
+
+/// The Minecraft versions Forge has builds for.
+pub async fn get_versions(paths: &Paths) -> Result<Vec<MinecraftVersion>> {
+    let mut versions: Vec<MinecraftVersion> = get_all_builds(paths)
+        .await?
+        .into_iter()
+        .map(|(minecraft, _)| minecraft)
+        .collect();
+    versions.sort();
+    versions.dedup();
+    Ok(versions)
+}
+
+pub async fn get_latest(paths: &Paths) -> Result<ServerVersion> {
+    let latest = get_versions(paths)
+        .await?
+        .into_iter()
+        .max()
+        .ok_or_else::<Error, _>(|| "forge: no maximum version found".into())?;
+    Ok(ServerVersion {
+        minecraft: latest,
+        patch: Some(get_latest_patch(paths, &latest).await?),
+    })
+}
+
+/// The Forge versions for `version`. The Maven metadata has neither
+/// release times nor changelogs.
+pub async fn get_builds(paths: &Paths, version: &MinecraftVersion) -> Result<Vec<Build>> {
+    let mut builds: Vec<Build> = get_all_builds(paths)
+        .await?
+        .into_iter()
+        .filter(|(minecraft, _)| minecraft == version)
+        .map(|(_, number)| Build {
+            number,
+            time: None,
+            channel: "default".to_owned(),
+            changes: vec![],
+            sha256: None,
+        })
+        .collect();
+    if builds.is_empty() {
+        bail!(ErrorKind::NotFound(format!(
+            "forge: minecraft version {}",
+            version
+        )));
+    }
+    builds.sort_by_key(|b| b.number);
+    Ok(builds)
+}
+
+pub async fn get_latest_patch(paths: &Paths, version: &MinecraftVersion) -> Result<u32> {
+    Ok(get_builds(paths, version).await?.last().unwrap().number)
+}
+
+/// The Forge version of a build for `minecraft`, as Forge names it
+fn forge_version(minecraft: &MinecraftVersion, patch: u32) -> String {
+    format!("{}-{}", minecraft, decode_loader_version(patch))
+}
+
+/// Request the installer of `version`.
+pub async fn download_response(
+    paths: &Paths,
+    version: &mut ServerVersion,
+) -> Result<reqwest::Response> {
+    let patch = match version.patch {
+        Some(p) => p,
+        None => get_latest_patch(paths, &version.minecraft).await?,
+    };
+    version.patch = Some(patch);
+    let forge = forge_version(&version.minecraft, patch);
+    let url = format!(
+        "{}/{}/forge-{}-installer.jar",
+        settings::mirrors(paths).forge.value,
+        forge,
+        forge
+    );
+    match download_client(paths)?.get(url).send().await {
+        Ok(resp) => {
+            if resp.status().is_success() {
+                Ok(resp)
+            } else {
+                bail!(ErrorKind::NotFound(format!(
+                    "forge: server version {}",
+                    version
+                )));
+            }
+        }
+        Err(e) => Err(e).chain_err(|| format!("forge: failed to download version {}", version)),
+    }
+}
+
+pub fn initialize(config: &ServerConfig) -> Result<()> {
+    for path in &[
+        config.configs_path(),
+        config.worlds_path(),
+        config.mods_path(),
+    ] {
+        fs::create_dir_all(path).chain_err(|| {
+            format!(
+                "forge: failed to create directory @ {}",
+                path.to_string_lossy()
+            )
+        })?;
+    }
+    let mut eula_file = fs::File::create(config.configs_path().join("eula.txt"))
+        .chain_err(|| "forge: failed to create eula file")?;
+    writeln!(&mut eula_file, "eula=true").chain_err(|| "forge: failed to write into eula file")?;
+    Ok(())
+}
+
+/// Run `installer` in the working directory of the server, unless `forge`
+/// is installed there already. Forge puts its libraries and the arguments
+/// to launch it with next to the configuration files.
+async fn install(config: &ServerConfig, java: &Path, installer: &Path, forge: &str) -> Result<()> {
+    let dir = config.configs_path();
+    let installed = dir.join(INSTALLED_FILE);
+    if fs::read_to_string(&installed).ok().as_deref() == Some(forge) {
+        return Ok(());
+    }
+    info!(server = %config.name, forge, "installing forge");
+    let output = tokio::process::Command::new(java)
+        .arg("-jar")
+        .arg(installer)
+        .arg("--installServer")
+        .arg(&dir)
+        .current_dir(&dir)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .chain_err(|| ErrorKind::ProcessFailed("running the forge installer".to_owned()))?;
+    if !output.status.success() {
+        bail!(ErrorKind::ProcessFailed(format!(
+            "installing forge {} ({})",
+            forge, output.status
+        )));
+    }
+    fs::write(&installed, forge).chain_err(|| "forge: failed to record the installed version")
+}
+
+pub async fn launch(
+    config: ServerConfig,
+    java: &Path,
+    stdout: Stdio,
+    stdin: Stdio,
+    stderr: Stdio,
+) -> Result<tokio::process::Child> {
+    let installer = cacher::server_jar(&config)?;
+    let patch = config
+        .version
+        .patch
+        .ok_or_else(|| format!("{} has no build yet", config.name))?;
+    let forge = forge_version(&config.version.minecraft, patch);
+    install(&config, java, &installer, &forge).await?;
+
+    let dir = config.configs_path();
+    let world_path = config
+        .worlds_path()
+        .canonicalize()
+        .chain_err(|| "canonicalize failed")?;
+    let mut command = tokio::process::Command::new(java);
+    command.args(crate::jvm::java_args(&config)?);
+    // Forge for Minecraft 1.17 and newer is launched through an argument
+    // file, older versions through a jar
+    let args_file = dir
+        .join("libraries/net/minecraftforge/forge")
+        .join(&forge)
+        .join(if cfg!(windows) {
+            "win_args.txt"
+        } else {
+            "unix_args.txt"
+        });
+    if args_file.is_file() {
+        command.arg(format!("@{}", args_file.to_string_lossy()));
+    } else {
+        command
+            .arg("-jar")
+            .arg(dir.join(format!("forge-{}.jar", forge)));
+    }
+    let child = command
+        .arg("--nogui")
+        .arg("--universe")
+        .arg(world_path)
+        .args(&config.extra_server_args)
+        .current_dir(dir)
+        .stdout(stdout)
+        .stdin(stdin)
+        .stderr(stderr)
+        .spawn()
+        .chain_err(|| ErrorKind::ProcessFailed("spawning the server process".to_owned()))?;
+    Ok(child)
+}
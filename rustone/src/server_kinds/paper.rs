@@ -1,74 +1,128 @@
-use std::{fs, io::Write, process::Stdio};
+use std::{collections::HashMap, fs, io::Write, process::Stdio};
 
 use error_chain::bail;
 use reqwest::StatusCode;
 use serde::Deserialize;
 
+use super::{Build, Change};
 use crate::{
-    cacher::CachedJarMetaKey,
+    cacher,
     config::{MinecraftVersion, ServerVersion},
     errors::*,
-    servers::Server,
+    metadata,
+    paths::Paths,
+    plugins::sources::download_client,
+    settings,
 };
 
 pub struct PaperUpdater;
 
-pub async fn is_latest_patch(version: &MinecraftVersion, patch: u32) -> Result<bool> {
-    Ok(get_latest_patch(version).await? <= patch)
+pub async fn is_latest_patch(
+    paths: &Paths,
+    version: &MinecraftVersion,
+    patch: u32,
+) -> Result<bool> {
+    Ok(get_latest_patch(paths, version).await? <= patch)
 }
 
-pub async fn get_latest() -> Result<ServerVersion> {
-    let response = reqwest::get("https://papermc.io/api/v1/paper")
+/// The Minecraft versions Paper has builds for.
+pub async fn get_versions(paths: &Paths) -> Result<Vec<MinecraftVersion>> {
+    let url = format!("{}/paper", settings::mirrors(paths).paper.value);
+    Ok(metadata::get_json::<ProjectResponse>(paths, &url)
         .await
-        .chain_err(|| "paper: failed to request version list")?;
-    let versions = response
-        .json::<ProjectResponse>()
-        .await
-        .chain_err(|| "paper: failed to decode project info json")?
-        .versions;
+        .chain_err(|| "paper: failed to get version list")?
+        .ok_or_else(|| ErrorKind::NotFound("paper: version list".to_owned()))?
+        .versions)
+}
+
+pub async fn get_latest(paths: &Paths) -> Result<ServerVersion> {
+    let versions = get_versions(paths).await?;
     let latest = versions
         .iter()
         .max()
         .ok_or_else::<Error, _>(|| "paper: no maximum version found".into())?;
     Ok(ServerVersion {
         minecraft: *latest,
-        patch: Some(get_latest_patch(latest).await?),
+        patch: Some(get_latest_patch(paths, latest).await?),
     })
 }
 
-pub async fn download_response(version: &mut ServerVersion) -> Result<reqwest::Response> {
-    let (url, p) = get_download_url(version).await?;
+pub async fn download_response(
+    paths: &Paths,
+    version: &mut ServerVersion,
+) -> Result<reqwest::Response> {
+    let (url, p) = get_download_url(paths, version).await?;
     version.patch = Some(p);
-    match reqwest::get(url).await {
+    match download_client(paths)?.get(url).send().await {
         Ok(resp) => {
             if resp.status() != StatusCode::NOT_FOUND {
                 Ok(resp)
             } else {
-                bail!("paper: nonexistant server version {}", version);
+                bail!(ErrorKind::NotFound(format!(
+                    "paper: server version {}",
+                    version
+                )));
             }
         }
         Err(e) => Err(e).chain_err(|| format!("paper: failed to download version {}", version)),
     }
 }
 
-pub async fn get_latest_patch(version: &MinecraftVersion) -> Result<u32> {
-    let url = format!("https://papermc.io/api/v1/paper/{}", version);
-    let response = reqwest::get(url)
+pub async fn get_latest_patch(paths: &Paths, version: &MinecraftVersion) -> Result<u32> {
+    let url = format!("{}/paper/{}", settings::mirrors(paths).paper.value, version);
+    let project: ProjectVersionResponse = metadata::get_json(paths, &url)
         .await
-        .chain_err(|| "paper: failed to request build list")?;
-    if response.status() == StatusCode::NOT_FOUND {
-        bail!("paper: nonexistant minecraft version {}", version);
+        .chain_err(|| "paper: failed to get build list")?
+        .ok_or_else(|| ErrorKind::NotFound(format!("paper: minecraft version {}", version)))?;
+    Ok(project.builds.latest)
+}
+
+/// The base URL of the v2 API, which has the details of builds. Mirrors
+/// are configured with the v1 API and the v2 one is expected next to it,
+/// other mirror URLs are used as they are.
+fn v2_base(paths: &Paths) -> String {
+    let mirror = settings::mirrors(paths).paper.value;
+    match mirror.trim_end_matches('/').strip_suffix("/v1") {
+        Some(base) => format!("{}/v2", base),
+        None => mirror,
     }
-    let project: ProjectVersionResponse = response
-        .json()
+}
+
+pub async fn get_builds(paths: &Paths, version: &MinecraftVersion) -> Result<Vec<Build>> {
+    let url = format!(
+        "{}/projects/paper/versions/{}/builds",
+        v2_base(paths),
+        version
+    );
+    let builds: BuildsResponse = metadata::get_json(paths, &url)
         .await
-        .chain_err(|| "paper: failed to decode project version info json")?;
-    Ok(project.builds.latest)
+        .chain_err(|| "paper: failed to get build list")?
+        .ok_or_else(|| ErrorKind::NotFound(format!("paper: minecraft version {}", version)))?;
+    Ok(builds
+        .builds
+        .into_iter()
+        .map(|b| Build {
+            number: b.build,
+            time: Some(b.time),
+            channel: b.channel,
+            changes: b
+                .changes
+                .into_iter()
+                .map(|c| Change {
+                    commit: Some(c.commit),
+                    summary: c.summary,
+                })
+                .collect(),
+            sha256: b
+                .downloads
+                .get("application")
+                .map(|download| download.sha256.clone()),
+        })
+        .collect())
 }
 
 #[derive(Deserialize, Debug)]
 struct ProjectResponse {
-    pub project: String,
     pub versions: Vec<MinecraftVersion>,
 }
 
@@ -83,15 +137,43 @@ struct ProjectVersionResponse {
     builds: PatchList,
 }
 
-async fn get_download_url(version: &ServerVersion) -> Result<(String, u32)> {
+#[derive(Deserialize)]
+struct BuildsResponse {
+    builds: Vec<BuildResponse>,
+}
+
+#[derive(Deserialize)]
+struct BuildResponse {
+    build: u32,
+    time: String,
+    channel: String,
+    changes: Vec<ChangeResponse>,
+    #[serde(default)]
+    downloads: HashMap<String, DownloadResponse>,
+}
+
+#[derive(Deserialize)]
+struct DownloadResponse {
+    sha256: String,
+}
+
+#[derive(Deserialize)]
+struct ChangeResponse {
+    commit: String,
+    summary: String,
+}
+
+async fn get_download_url(paths: &Paths, version: &ServerVersion) -> Result<(String, u32)> {
     let patch = match version.patch {
         Some(p) => Ok(p),
-        None => get_latest_patch(&version.minecraft).await,
+        None => get_latest_patch(paths, &version.minecraft).await,
     }?;
     Ok((
         format!(
-            "https://papermc.io/api/v1/paper/{}/{}/download",
-            version.minecraft, patch
+            "{}/paper/{}/{}/download",
+            settings::mirrors(paths).paper.value,
+            version.minecraft,
+            patch
         ),
         patch,
     ))
@@ -100,17 +182,19 @@ async fn get_download_url(version: &ServerVersion) -> Result<(String, u32)> {
 pub struct PaperServer;
 
 pub fn initialize(config: &crate::config::ServerConfig) -> Result<()> {
-    for dir in &["configs", "worlds", "plugins"] {
-        let path = config.path().join(dir);
-        fs::create_dir_all(&path).chain_err(|| {
+    for path in &[
+        config.configs_path(),
+        config.worlds_path(),
+        config.path().join("plugins"),
+    ] {
+        fs::create_dir_all(path).chain_err(|| {
             format!(
-                "paper: failed to create '{}' directory @ {}",
-                dir,
+                "paper: failed to create directory @ {}",
                 path.to_string_lossy()
             )
         })?;
     }
-    let mut eula_file = fs::File::create(config.path().join("configs").join("eula.txt"))
+    let mut eula_file = fs::File::create(config.configs_path().join("eula.txt"))
         .chain_err(|| "paper: failed to create eula file")?;
     writeln!(&mut eula_file, "eula=true").chain_err(|| "paper: failed to write into eula file")?;
     Ok(())
@@ -118,40 +202,33 @@ pub fn initialize(config: &crate::config::ServerConfig) -> Result<()> {
 
 pub fn launch(
     config: crate::config::ServerConfig,
+    java: &std::path::Path,
     stdout: Stdio,
     stdin: Stdio,
     stderr: Stdio,
 ) -> Result<tokio::process::Child> {
-    let child = tokio::process::Command::new("java")
-        .args(&config.extra_java_args)
+    let child = tokio::process::Command::new(java)
+        .args(crate::jvm::java_args(&config)?)
         .arg("-jar")
-        .arg(
-            CachedJarMetaKey {
-                kind: "paper".into(),
-                version: config.version.minecraft,
-            }
-            .path(),
-        )
+        .arg(cacher::server_jar(&config)?)
         .args(server_args(&config)?)
         .args(&config.extra_server_args)
-        .current_dir(Server { config }.server_path()?.join("configs"))
+        .current_dir(config.configs_path())
         .stdout(stdout)
         .stdin(stdin)
         .stderr(stderr)
         .spawn()
-        .chain_err(|| "failed to spawn server process")?;
+        .chain_err(|| ErrorKind::ProcessFailed("spawning the server process".to_owned()))?;
     Ok(child)
 }
 
 fn server_args(server: &crate::config::ServerConfig) -> Result<Vec<String>> {
     let config_path = server
-        .path()
-        .join("configs")
+        .configs_path()
         .canonicalize()
         .chain_err(|| "canonicalize failed")?;
     let world_path = server
-        .path()
-        .join("worlds")
+        .worlds_path()
         .canonicalize()
         .chain_err(|| "canonicalize failed")?;
     let plugins_path = server
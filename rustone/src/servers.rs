@@ -1,11 +1,15 @@
 use crate::server_kinds::ServerKind;
+use error_chain::bail;
 use fs::{read_dir, ReadDir};
-use lazy_static::lazy_static;
+use tracing::info;
 
 use crate::{
-    config::{ServerConfig, ServerVersion},
+    archive::is_temporary,
+    config::{ServerConfig, ServerLayout, ServerVersion, UpdatePolicy, SCHEMA_VERSION},
     errors::*,
-    global::*,
+    import::copy_recursive,
+    paths::Paths,
+    properties, settings,
 };
 use std::{fs, io::Write, path::PathBuf};
 
@@ -13,82 +17,197 @@ pub struct Server {
     pub config: ServerConfig,
 }
 
-lazy_static! {
-    pub static ref CONFIG_SERVER_DIR: PathBuf =
-        project_dirs().unwrap().config_dir().join("servers");
-    pub static ref DATA_SERVER_DIR: PathBuf = project_dirs().unwrap().data_dir().into();
-}
-
 impl ServerConfig {
     pub fn path(&self) -> PathBuf {
-        DATA_SERVER_DIR.join(&self.name)
+        match &self.directory {
+            Some(directory) => directory.clone(),
+            None => self.paths.server(&self.name),
+        }
+    }
+
+    /// The working directory of the server, with its configuration files.
+    pub fn configs_path(&self) -> PathBuf {
+        match self.layout {
+            ServerLayout::Standard => self.path().join("configs"),
+            ServerLayout::Flat => self.path(),
+        }
+    }
+
+    /// The directory containing the worlds of the server.
+    pub fn worlds_path(&self) -> PathBuf {
+        match self.layout {
+            ServerLayout::Standard => self.path().join("worlds"),
+            ServerLayout::Flat => self.path(),
+        }
+    }
+
+    /// The directory with the mods of modded servers. Forge only loads mods
+    /// from its working directory.
+    pub fn mods_path(&self) -> PathBuf {
+        if self.kind == "forge" {
+            self.configs_path().join("mods")
+        } else {
+            self.path().join("mods")
+        }
     }
 
     pub fn config_path(&self) -> PathBuf {
-        CONFIG_SERVER_DIR.join(format!("{}.toml", self.name))
+        self.paths.server_config(&self.name)
     }
 }
 
 impl Server {
-    pub fn get(name: &str) -> Result<Self> {
-        let path = project_dirs()?
-            .config_dir()
-            .join("servers")
-            .join(format!("{}.toml", name));
-        let config_str = fs::read_to_string(
-            project_dirs()?
-                .config_dir()
-                .join("servers")
-                .join(format!("{}.toml", name)),
-        )
-        .chain_err(|| format!("could not load config file: {}", path.to_string_lossy()))?;
-        let config = ServerConfig::new(&config_str)?;
-        Ok(Self { config })
+    pub fn get(paths: &Paths, name: &str) -> Result<Self> {
+        let path = paths.server_config(name);
+        if !path.is_file() {
+            bail!(ErrorKind::NotFound(format!("server {}", name)));
+        }
+        let config_str = fs::read_to_string(&path)
+            .chain_err(|| format!("could not load config file: {}", path.to_string_lossy()))?;
+        let (mut config, from) = ServerConfig::parse(&config_str)
+            .chain_err(|| format!("could not load config file: {}", path.to_string_lossy()))?;
+        config.paths = paths.clone();
+        let server = Self { config };
+        if from < SCHEMA_VERSION {
+            // Keep the original around, in case the migration went wrong
+            let backup = path.with_file_name(format!("{}.toml.v{}.bak", name, from));
+            fs::copy(&path, &backup).chain_err(|| "failed to back up config file")?;
+            server.save()?;
+        }
+        Ok(server)
     }
 
-    pub fn create(name: &str, version: ServerVersion, kind: String) -> Result<Self> {
-        let mut file = fs::File::create(CONFIG_SERVER_DIR.join(format!("{}.toml", name)))
-            .chain_err(|| "could not create server config")?;
-
-        let server_kind = kind.parse::<ServerKind>()?;
-
-        let config = ServerConfig {
+    pub fn create(paths: &Paths, name: &str, version: ServerVersion, kind: String) -> Result<Self> {
+        Self::create_with(ServerConfig {
+            schema_version: SCHEMA_VERSION,
             name: name.to_owned(),
             version,
             extra_java_args: vec![],
             extra_server_args: vec![],
             kind,
-        };
+            min_heap: None,
+            max_heap: None,
+            jvm_preset: None,
+            layout: ServerLayout::Standard,
+            directory: None,
+            java_path: None,
+            selector: None,
+            update_policy: UpdatePolicy::default(),
+            rollback_to: None,
+            bad_builds: vec![],
+            startup_timeout: None,
+            paths: paths.clone(),
+        })
+    }
 
+    /// Create a server from a complete configuration, in the instance of its
+    /// `paths`: initialize its directory for its kind, then write the
+    /// configuration.
+    pub fn create_with(config: ServerConfig) -> Result<Self> {
+        if config.config_path().exists() {
+            bail!(ErrorKind::AlreadyExists(format!(
+                "a server named {}",
+                config.name
+            )));
+        }
+        let server_kind = config.kind.parse::<ServerKind>()?;
         server_kind.initialize(&config)?;
 
-        file.write_all(
-            toml::to_string_pretty(&config)
-                .chain_err(|| "failed to generate configuration")?
-                .as_bytes(),
-        )
-        .chain_err(|| "failed to write config file")?;
+        let server = Self { config };
+        server.save()?;
 
-        let mut eula =
-            fs::File::create(DATA_SERVER_DIR.join(name).join("configs").join("eula.txt"))
-                .chain_err(|| "could not create eula file")?;
+        let mut eula = fs::File::create(server.config.configs_path().join("eula.txt"))
+            .chain_err(|| "could not create eula file")?;
         writeln!(&mut eula, "eula=true").chain_err(|| "could not write eula file")?;
 
-        Ok(Self { config })
+        info!(
+            server = %server.config.name,
+            kind = %server.config.kind,
+            version = %server.config.version,
+            "server created"
+        );
+        Ok(server)
     }
 
-    pub fn config_path(&self) -> Result<PathBuf> {
-        if !CONFIG_SERVER_DIR.exists() {
-            fs::create_dir_all(CONFIG_SERVER_DIR.clone().into_boxed_path())
-                .chain_err(|| "could not create config directory")?;
+    /// Copy the server into a new one called `name`, listening on `port`, or
+    /// on the first port after this server's which no other server uses.
+    /// Worlds are only copied if `worlds` is set.
+    pub fn clone_as(&self, name: &str, worlds: bool, port: Option<u16>) -> Result<Self> {
+        let mut config = self.config.clone();
+        config.name = name.to_owned();
+        config.directory = None;
+        if config.path().exists() {
+            bail!(ErrorKind::AlreadyExists(format!(
+                "the directory of server {}",
+                name
+            )));
         }
+        let port = match port {
+            Some(port) => port,
+            None => free_port(&self.config.paths, properties::server_port(&self.config)?)?,
+        };
+
+        let server = Self::create_with(config)?;
+        let copied = (|| {
+            let source = self.config.path();
+            let entries = fs::read_dir(&source)
+                .chain_err(|| format!("failed to list {}", source.to_string_lossy()))?;
+            for entry in entries {
+                let entry = entry.chain_err(|| "failed to read directory entry")?;
+                let path = entry.path();
+                let is_world = match self.config.layout {
+                    ServerLayout::Standard => path == self.config.worlds_path(),
+                    ServerLayout::Flat => path.join("level.dat").is_file(),
+                };
+                if (is_world && !worlds) || is_temporary(&entry.file_name().to_string_lossy()) {
+                    continue;
+                }
+                copy_recursive(&path, &server.config.path().join(entry.file_name()))?;
+            }
+            properties::set_in_file(
+                &server.config.configs_path().join("server.properties"),
+                &[("server-port", &port.to_string())],
+            )
+        })();
+        if let Err(e) = copied {
+            let _ = server.remove();
+            return Err(e);
+        }
+        Ok(server)
+    }
+
+    /// Delete the files and the configuration of the server. Servers in their
+    /// own directory are only forgotten, their files are left alone.
+    pub fn remove(&self) -> Result<()> {
+        info!(server = %self.config.name, "removing server");
+        let path = self.config.path();
+        if self.config.directory.is_none() && path.exists() {
+            fs::remove_dir_all(&path).chain_err(|| "failed to remove server directory")?;
+        }
+        let log = self.config.paths.update_log(&self.config.name);
+        if log.exists() {
+            fs::remove_file(&log).chain_err(|| "failed to remove update log")?;
+        }
+        fs::remove_file(self.config_path()?).chain_err(|| "failed to remove server config")
+    }
 
-        let config = CONFIG_SERVER_DIR.join(format!("{}.toml", self.config.name,));
-        Ok(config)
+    /// Write the configuration of the server back to its file.
+    pub fn save(&self) -> Result<()> {
+        let text = toml::to_string_pretty(&self.config)
+            .chain_err(|| "failed to generate configuration")?;
+        fs::write(self.config_path()?, text).chain_err(|| "failed to write config file")
+    }
+
+    pub fn config_path(&self) -> Result<PathBuf> {
+        let dir = self.config.paths.server_configs();
+        if !dir.exists() {
+            fs::create_dir_all(&dir).chain_err(|| "could not create config directory")?;
+        }
+        Ok(self.config.config_path())
     }
 
     pub fn server_path(&self) -> Result<PathBuf> {
-        let path = DATA_SERVER_DIR.join(&self.config.name);
+        let path = self.config.path();
         if !path.exists() {
             fs::create_dir_all(path.clone()).chain_err(|| "could not create server directory")?;
         }
@@ -96,21 +215,42 @@ impl Server {
     }
 }
 
+/// The first port of the configured port range from `start` on which no
+/// server listens, or the first one before `start` if there is none.
+pub fn free_port(paths: &Paths, start: u16) -> Result<u16> {
+    let range = settings::effective(paths, None)?.port_range.value;
+    let used = get_servers(paths)?
+        .iter()
+        .map(|server| properties::server_port(&server.config))
+        .collect::<Result<Vec<_>>>()?;
+    (start.max(range.start)..=range.end)
+        .chain(range.start..start.min(range.end))
+        .find(|port| !used.contains(port))
+        .ok_or_else(|| format!("no free port left in {}", range).into())
+}
+
 /// Return an iterator to the servers directory.
-fn iter_servers_directory() -> Result<ReadDir> {
-    read_dir(project_dirs()?.data_dir()).chain_err(|| "failed to list servers directory")
+fn iter_servers_directory(paths: &Paths) -> Result<ReadDir> {
+    read_dir(&paths.data).chain_err(|| "failed to list servers directory")
 }
 
-pub fn get_servers() -> Result<Vec<Server>> {
-    let dir = iter_servers_directory()?;
-    let mapped = dir.map(|entry| {
-        Server::get(
-            entry
-                .chain_err(|| "failed to read directory entry")?
-                .file_name()
-                .to_str()
-                .unwrap(),
-        )
+pub fn get_servers(paths: &Paths) -> Result<Vec<Server>> {
+    // A new instance has no servers yet
+    if !paths.data.exists() {
+        return Ok(vec![]);
+    }
+    let dir = iter_servers_directory(paths)?;
+    let mapped = dir.filter_map(|entry| {
+        let name = match entry.chain_err(|| "failed to read directory entry") {
+            Ok(entry) => entry.file_name().to_string_lossy().into_owned(),
+            Err(e) => return Some(Err(e)),
+        };
+        // Hidden directories (like the backup repository) are not servers
+        if name.starts_with('.') {
+            None
+        } else {
+            Some(Server::get(paths, &name))
+        }
     });
-    Ok(mapped.collect::<Result<_>>()?)
+    mapped.collect::<Result<_>>()
 }
@@ -0,0 +1,663 @@
+//! Host-wide settings, and how they combine with the settings of a server.
+//!
+//! Defaults for every server live in `rustone.toml`, in rustone's
+//! configuration directory. A server's configuration overrides them field by
+//! field, and `RUSTONE_*` environment variables override both.
+
+use std::{
+    env,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    result,
+    str::FromStr,
+};
+
+use error_chain::bail;
+use serde::{de::IntoDeserializer, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    config::{JvmPreset, MemorySize, ServerConfig},
+    errors::*,
+    jvm::default_max_heap,
+    paths::Paths,
+};
+
+pub const DEFAULT_PAPER_MIRROR: &str = "https://papermc.io/api/v1";
+pub const DEFAULT_FABRIC_MIRROR: &str = "https://meta.fabricmc.net/v2";
+pub const DEFAULT_FORGE_MIRROR: &str = "https://maven.minecraftforge.net/net/minecraftforge/forge";
+pub const DEFAULT_MOJANG_MIRROR: &str = "https://piston-meta.mojang.com";
+pub const DEFAULT_MODRINTH_MIRROR: &str = "https://api.modrinth.com/v2";
+pub const DEFAULT_HANGAR_MIRROR: &str = "https://hangar.papermc.io/api/v1";
+pub const DEFAULT_CURSEFORGE_MIRROR: &str = "https://api.curseforge.com/v1";
+/// How long upstream metadata is used before it is revalidated, in seconds
+pub const DEFAULT_METADATA_TTL: u64 = 600;
+pub const DEFAULT_USER_AGENT: &str = concat!("rustone/", env!("CARGO_PKG_VERSION"));
+/// In seconds
+pub const DEFAULT_CONNECT_TIMEOUT: u64 = 10;
+/// In seconds
+pub const DEFAULT_READ_TIMEOUT: u64 = 30;
+
+/// An inclusive range of ports, written like `25565-25600`.
+///
+/// # Examples
+/// ```
+/// use rustone::settings::PortRange;
+/// let range: PortRange = "25565-25600".parse().unwrap();
+/// assert_eq!((range.start, range.end), (25565, 25600));
+/// assert!("25600-25565".parse::<PortRange>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl Default for PortRange {
+    fn default() -> Self {
+        Self {
+            start: crate::properties::DEFAULT_PORT,
+            end: u16::MAX,
+        }
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = Error;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        let (start, end) = s.split_once('-').unwrap_or((s, s));
+        let parse = |port: &str| {
+            port.trim()
+                .parse::<u16>()
+                .chain_err(|| format!("invalid port range '{}'", s))
+        };
+        let (start, end) = (parse(start)?, parse(end)?);
+        if start > end {
+            bail!("invalid port range '{}', it ends before it starts", s);
+        }
+        Ok(Self { start, end })
+    }
+}
+
+impl Display for PortRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+impl Serialize for PortRange {
+    fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for PortRange {
+    fn deserialize<D>(deserializer: D) -> result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        use serde::de::Error;
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(|e: crate::errors::Error| D::Error::custom(e.to_string()))
+    }
+}
+
+/// Base URLs to use instead of the upstream APIs.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Mirrors {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paper: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fabric: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forge: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mojang: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modrinth: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hangar: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub curseforge: Option<String>,
+}
+
+/// The IP version connections are made with.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IpVersion {
+    /// Whichever the system resolves first
+    #[default]
+    Any,
+    Ipv4,
+    Ipv6,
+}
+
+impl FromStr for IpVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "any" => Ok(Self::Any),
+            "ipv4" | "4" => Ok(Self::Ipv4),
+            "ipv6" | "6" => Ok(Self::Ipv6),
+            _ => bail!("invalid IP version '{}', expected any, ipv4 or ipv6", s),
+        }
+    }
+}
+
+impl Display for IpVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Any => write!(f, "any"),
+            Self::Ipv4 => write!(f, "ipv4"),
+            Self::Ipv6 => write!(f, "ipv6"),
+        }
+    }
+}
+
+/// How requests reach the network.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Network {
+    /// The proxy every request goes through, like `http://proxy:3128`.
+    /// Without it, the `HTTP_PROXY` and `HTTPS_PROXY` variables are used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// Hosts reached without the proxy, with their subdomains
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_proxy: Option<Vec<String>>,
+    /// PEM certificates to trust besides the system's, for proxies
+    /// intercepting TLS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_bundle: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// Seconds to wait for a connection
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_timeout: Option<u64>,
+    /// Seconds to wait for a response, or for more of a download
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_timeout: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip_version: Option<IpVersion>,
+}
+
+/// The settings of `rustone.toml`. Everything is optional.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct GlobalConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub java_path: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_heap: Option<MemorySize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_heap: Option<MemorySize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jvm_preset: Option<JvmPreset>,
+    /// The ports new servers are given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port_range: Option<PortRange>,
+    #[serde(default)]
+    pub mirrors: Mirrors,
+    /// The backup repository
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup_target: Option<PathBuf>,
+    /// Never connect to the network
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offline: Option<bool>,
+    /// Seconds until cached upstream metadata is revalidated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata_ttl: Option<u64>,
+    #[serde(default)]
+    pub network: Network,
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "" | "0" | "false" | "no" | "off" => Ok(false),
+        _ => bail!("{} must be true or false, not '{}'", name, value),
+    }
+}
+
+impl GlobalConfig {
+    /// Read the global configuration at `path`. A missing file sets nothing.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = fs::read_to_string(path)
+            .chain_err(|| format!("failed to read {}", path.to_string_lossy()))?;
+        toml::from_str(&text)
+            .chain_err(|| ErrorKind::InvalidConfig(path.to_string_lossy().into_owned()))
+    }
+
+    /// The settings given by `RUSTONE_*` variables, like `RUSTONE_MAX_HEAP`
+    /// or `RUSTONE_MIRROR_PAPER`. Other variables are ignored.
+    pub fn from_vars<I: IntoIterator<Item = (String, String)>>(vars: I) -> Result<Self> {
+        let mut config = Self::default();
+        for (name, value) in vars {
+            let invalid = || ErrorKind::InvalidConfig(format!("{}: '{}'", name, value));
+            match name.as_str() {
+                "RUSTONE_JAVA_PATH" => config.java_path = Some(value.into()),
+                "RUSTONE_MIN_HEAP" => config.min_heap = Some(value.parse().chain_err(invalid)?),
+                "RUSTONE_MAX_HEAP" => config.max_heap = Some(value.parse().chain_err(invalid)?),
+                "RUSTONE_JVM_PRESET" => {
+                    let deserializer: serde::de::value::StrDeserializer<serde::de::value::Error> =
+                        value.as_str().into_deserializer();
+                    config.jvm_preset =
+                        Some(JvmPreset::deserialize(deserializer).chain_err(invalid)?)
+                }
+                "RUSTONE_PORT_RANGE" => config.port_range = Some(value.parse().chain_err(invalid)?),
+                "RUSTONE_BACKUP_TARGET" => config.backup_target = Some(value.into()),
+                "RUSTONE_OFFLINE" => config.offline = Some(parse_bool(&name, &value)?),
+                "RUSTONE_METADATA_TTL" => {
+                    config.metadata_ttl = Some(value.parse().chain_err(invalid)?)
+                }
+                "RUSTONE_PROXY" => config.network.proxy = Some(value),
+                "RUSTONE_NO_PROXY" => {
+                    config.network.no_proxy = Some(
+                        value
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|host| !host.is_empty())
+                            .map(str::to_owned)
+                            .collect(),
+                    )
+                }
+                "RUSTONE_CA_BUNDLE" => config.network.ca_bundle = Some(value.into()),
+                "RUSTONE_USER_AGENT" => config.network.user_agent = Some(value),
+                "RUSTONE_CONNECT_TIMEOUT" => {
+                    config.network.connect_timeout = Some(value.parse().chain_err(invalid)?)
+                }
+                "RUSTONE_READ_TIMEOUT" => {
+                    config.network.read_timeout = Some(value.parse().chain_err(invalid)?)
+                }
+                "RUSTONE_IP_VERSION" => {
+                    config.network.ip_version = Some(value.parse().chain_err(invalid)?)
+                }
+                "RUSTONE_MIRROR_PAPER" => config.mirrors.paper = Some(value),
+                "RUSTONE_MIRROR_FABRIC" => config.mirrors.fabric = Some(value),
+                "RUSTONE_MIRROR_FORGE" => config.mirrors.forge = Some(value),
+                "RUSTONE_MIRROR_MOJANG" => config.mirrors.mojang = Some(value),
+                "RUSTONE_MIRROR_MODRINTH" => config.mirrors.modrinth = Some(value),
+                "RUSTONE_MIRROR_HANGAR" => config.mirrors.hangar = Some(value),
+                "RUSTONE_MIRROR_CURSEFORGE" => config.mirrors.curseforge = Some(value),
+                _ => {}
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Where the value of a setting comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    Default,
+    /// `rustone.toml`
+    Global,
+    /// The server's own configuration
+    Server,
+    /// An environment variable
+    Environment(&'static str),
+}
+
+impl Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Default => write!(f, "default"),
+            Self::Global => write!(f, "rustone.toml"),
+            Self::Server => write!(f, "server config"),
+            Self::Environment(name) => write!(f, "${}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Setting<T> {
+    pub value: T,
+    pub origin: Origin,
+}
+
+/// The value of the last layer setting it, or `default`.
+fn layer<T>(default: T, layers: Vec<(Option<T>, Origin)>) -> Setting<T> {
+    layers
+        .into_iter()
+        .filter_map(|(value, origin)| value.map(|value| Setting { value, origin }))
+        .last()
+        .unwrap_or(Setting {
+            value: default,
+            origin: Origin::Default,
+        })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EffectiveMirrors {
+    pub paper: Setting<String>,
+    pub fabric: Setting<String>,
+    pub forge: Setting<String>,
+    pub mojang: Setting<String>,
+    pub modrinth: Setting<String>,
+    pub hangar: Setting<String>,
+    pub curseforge: Setting<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EffectiveNetwork {
+    pub proxy: Setting<Option<String>>,
+    pub no_proxy: Setting<Vec<String>>,
+    pub ca_bundle: Setting<Option<PathBuf>>,
+    pub user_agent: Setting<String>,
+    pub connect_timeout: Setting<u64>,
+    pub read_timeout: Setting<u64>,
+    pub ip_version: Setting<IpVersion>,
+}
+
+impl EffectiveNetwork {
+    /// Whether requests to `host` skip the proxy: it, or a domain it belongs
+    /// to, is listed in `no_proxy`. `*` lists every host.
+    ///
+    /// # Examples
+    /// ```
+    /// use rustone::settings::Layers;
+    /// let mut network = Layers::default().effective(None).network;
+    /// network.no_proxy.value = vec!["internal.example".to_owned(), ".lan".to_owned()];
+    /// assert!(network.bypasses_proxy("internal.example"));
+    /// assert!(network.bypasses_proxy("maven.internal.example"));
+    /// assert!(network.bypasses_proxy("nas.lan"));
+    /// assert!(!network.bypasses_proxy("notinternal.example"));
+    /// assert!(!network.bypasses_proxy("papermc.io"));
+    /// ```
+    pub fn bypasses_proxy(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        self.no_proxy.value.iter().any(|entry| {
+            let domain = entry.trim_start_matches('.').to_ascii_lowercase();
+            entry == "*"
+                || host == domain
+                || host
+                    .strip_suffix(&domain)
+                    .is_some_and(|sub| sub.ends_with('.'))
+        })
+    }
+}
+
+/// The settings in effect, after merging every layer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EffectiveConfig {
+    pub java_path: Setting<Option<PathBuf>>,
+    pub min_heap: Setting<Option<MemorySize>>,
+    pub max_heap: Setting<MemorySize>,
+    pub jvm_preset: Setting<JvmPreset>,
+    pub port_range: Setting<PortRange>,
+    pub mirrors: EffectiveMirrors,
+    pub backup_target: Setting<PathBuf>,
+    pub offline: Setting<bool>,
+    pub metadata_ttl: Setting<u64>,
+    pub network: EffectiveNetwork,
+}
+
+/// The global configuration and the environment.
+#[derive(Debug, Clone, Default)]
+pub struct Layers {
+    /// The instance the global configuration belongs to
+    pub paths: Paths,
+    pub global: GlobalConfig,
+    pub env: GlobalConfig,
+}
+
+impl Layers {
+    /// Read the `rustone.toml` of the instance in `paths`, and the
+    /// environment.
+    pub fn load(paths: &Paths) -> Result<Self> {
+        Ok(Self {
+            paths: paths.clone(),
+            global: GlobalConfig::load(&paths.global_config())?,
+            env: GlobalConfig::from_vars(env::vars())?,
+        })
+    }
+
+    /// Merge the layers, with the settings of `server` if given.
+    pub fn effective(&self, server: Option<&ServerConfig>) -> EffectiveConfig {
+        let (global, env) = (&self.global, &self.env);
+        // Optional settings are wrapped once more, so that a layer can set them
+        fn some<T: Clone>(value: &Option<T>) -> Option<Option<T>> {
+            value.clone().map(Some)
+        }
+        let mirror = |default: &str, global: &Option<String>, env: &Option<String>, var| {
+            layer(
+                default.to_owned(),
+                vec![
+                    (global.clone(), Origin::Global),
+                    (env.clone(), Origin::Environment(var)),
+                ],
+            )
+        };
+        EffectiveConfig {
+            java_path: layer(
+                None,
+                vec![
+                    (some(&global.java_path), Origin::Global),
+                    (server.and_then(|s| some(&s.java_path)), Origin::Server),
+                    (
+                        some(&env.java_path),
+                        Origin::Environment("RUSTONE_JAVA_PATH"),
+                    ),
+                ],
+            ),
+            min_heap: layer(
+                None,
+                vec![
+                    (some(&global.min_heap), Origin::Global),
+                    (server.and_then(|s| some(&s.min_heap)), Origin::Server),
+                    (some(&env.min_heap), Origin::Environment("RUSTONE_MIN_HEAP")),
+                ],
+            ),
+            max_heap: layer(
+                default_max_heap(),
+                vec![
+                    (global.max_heap, Origin::Global),
+                    (server.and_then(|s| s.max_heap), Origin::Server),
+                    (env.max_heap, Origin::Environment("RUSTONE_MAX_HEAP")),
+                ],
+            ),
+            jvm_preset: layer(
+                JvmPreset::default(),
+                vec![
+                    (global.jvm_preset, Origin::Global),
+                    (server.and_then(|s| s.jvm_preset), Origin::Server),
+                    (env.jvm_preset, Origin::Environment("RUSTONE_JVM_PRESET")),
+                ],
+            ),
+            port_range: layer(
+                PortRange::default(),
+                vec![
+                    (global.port_range, Origin::Global),
+                    (env.port_range, Origin::Environment("RUSTONE_PORT_RANGE")),
+                ],
+            ),
+            mirrors: EffectiveMirrors {
+                paper: mirror(
+                    DEFAULT_PAPER_MIRROR,
+                    &global.mirrors.paper,
+                    &env.mirrors.paper,
+                    "RUSTONE_MIRROR_PAPER",
+                ),
+                fabric: mirror(
+                    DEFAULT_FABRIC_MIRROR,
+                    &global.mirrors.fabric,
+                    &env.mirrors.fabric,
+                    "RUSTONE_MIRROR_FABRIC",
+                ),
+                forge: mirror(
+                    DEFAULT_FORGE_MIRROR,
+                    &global.mirrors.forge,
+                    &env.mirrors.forge,
+                    "RUSTONE_MIRROR_FORGE",
+                ),
+                mojang: mirror(
+                    DEFAULT_MOJANG_MIRROR,
+                    &global.mirrors.mojang,
+                    &env.mirrors.mojang,
+                    "RUSTONE_MIRROR_MOJANG",
+                ),
+                modrinth: mirror(
+                    DEFAULT_MODRINTH_MIRROR,
+                    &global.mirrors.modrinth,
+                    &env.mirrors.modrinth,
+                    "RUSTONE_MIRROR_MODRINTH",
+                ),
+                hangar: mirror(
+                    DEFAULT_HANGAR_MIRROR,
+                    &global.mirrors.hangar,
+                    &env.mirrors.hangar,
+                    "RUSTONE_MIRROR_HANGAR",
+                ),
+                curseforge: mirror(
+                    DEFAULT_CURSEFORGE_MIRROR,
+                    &global.mirrors.curseforge,
+                    &env.mirrors.curseforge,
+                    "RUSTONE_MIRROR_CURSEFORGE",
+                ),
+            },
+            backup_target: layer(
+                self.paths.backups(),
+                vec![
+                    (global.backup_target.clone(), Origin::Global),
+                    (
+                        env.backup_target.clone(),
+                        Origin::Environment("RUSTONE_BACKUP_TARGET"),
+                    ),
+                ],
+            ),
+            offline: layer(
+                false,
+                vec![
+                    (global.offline, Origin::Global),
+                    (env.offline, Origin::Environment("RUSTONE_OFFLINE")),
+                ],
+            ),
+            metadata_ttl: layer(
+                DEFAULT_METADATA_TTL,
+                vec![
+                    (global.metadata_ttl, Origin::Global),
+                    (
+                        env.metadata_ttl,
+                        Origin::Environment("RUSTONE_METADATA_TTL"),
+                    ),
+                ],
+            ),
+            network: {
+                let (global, env) = (&global.network, &env.network);
+                EffectiveNetwork {
+                    proxy: layer(
+                        None,
+                        vec![
+                            (some(&global.proxy), Origin::Global),
+                            (some(&env.proxy), Origin::Environment("RUSTONE_PROXY")),
+                        ],
+                    ),
+                    no_proxy: layer(
+                        vec![],
+                        vec![
+                            (global.no_proxy.clone(), Origin::Global),
+                            (
+                                env.no_proxy.clone(),
+                                Origin::Environment("RUSTONE_NO_PROXY"),
+                            ),
+                        ],
+                    ),
+                    ca_bundle: layer(
+                        None,
+                        vec![
+                            (some(&global.ca_bundle), Origin::Global),
+                            (
+                                some(&env.ca_bundle),
+                                Origin::Environment("RUSTONE_CA_BUNDLE"),
+                            ),
+                        ],
+                    ),
+                    user_agent: layer(
+                        DEFAULT_USER_AGENT.to_owned(),
+                        vec![
+                            (global.user_agent.clone(), Origin::Global),
+                            (
+                                env.user_agent.clone(),
+                                Origin::Environment("RUSTONE_USER_AGENT"),
+                            ),
+                        ],
+                    ),
+                    connect_timeout: layer(
+                        DEFAULT_CONNECT_TIMEOUT,
+                        vec![
+                            (global.connect_timeout, Origin::Global),
+                            (
+                                env.connect_timeout,
+                                Origin::Environment("RUSTONE_CONNECT_TIMEOUT"),
+                            ),
+                        ],
+                    ),
+                    read_timeout: layer(
+                        DEFAULT_READ_TIMEOUT,
+                        vec![
+                            (global.read_timeout, Origin::Global),
+                            (
+                                env.read_timeout,
+                                Origin::Environment("RUSTONE_READ_TIMEOUT"),
+                            ),
+                        ],
+                    ),
+                    ip_version: layer(
+                        IpVersion::default(),
+                        vec![
+                            (global.ip_version, Origin::Global),
+                            (env.ip_version, Origin::Environment("RUSTONE_IP_VERSION")),
+                        ],
+                    ),
+                }
+            },
+        }
+    }
+}
+
+/// The settings in effect in the instance in `paths`, for `server` or for
+/// no server in particular.
+pub fn effective(paths: &Paths, server: Option<&ServerConfig>) -> Result<EffectiveConfig> {
+    Ok(Layers::load(paths)?.effective(server))
+}
+
+/// The mirrors in effect in the instance in `paths`. An invalid global
+/// configuration is ignored here, it is reported by everything else reading
+/// it.
+pub fn mirrors(paths: &Paths) -> EffectiveMirrors {
+    Layers::load(paths)
+        .unwrap_or_default()
+        .effective(None)
+        .mirrors
+}
+
+/// The network settings in effect in the instance in `paths`, or the
+/// defaults if its global configuration is invalid.
+pub fn network(paths: &Paths) -> EffectiveNetwork {
+    Layers::load(paths)
+        .unwrap_or_default()
+        .effective(None)
+        .network
+}
+
+/// Fail if offline mode is on in the instance in `paths`.
+pub fn check_online(paths: &Paths) -> Result<()> {
+    let offline = effective(paths, None)?.offline;
+    if offline.value {
+        return Err(Error::from(ErrorKind::UpstreamUnavailable(
+            "the network".to_owned(),
+        )))
+        .chain_err(|| format!("offline mode is enabled (by {})", offline.origin));
+    }
+    Ok(())
+}
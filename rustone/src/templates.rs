@@ -0,0 +1,101 @@
+//! Templates to create servers from.
+//!
+//! A template is a directory in rustone's configuration directory, under
+//! `templates/<name>/`. Its `template.toml` is a server configuration without
+//! a name (and optionally without a version), and everything else is laid
+//! over the new server: `configs/` into its configuration directory, and
+//! other files and directories, like `plugins/` or `plugins.toml`, into the
+//! server's directory.
+
+use std::{fs, path::Path};
+
+use error_chain::bail;
+
+use crate::{
+    config::{ServerConfig, ServerVersion},
+    errors::*,
+    import::copy_recursive,
+    paths::Paths,
+    servers::Server,
+};
+
+const TEMPLATE_CONFIG: &str = "template.toml";
+
+/// The names of the available templates.
+pub fn list(paths: &Paths) -> Result<Vec<String>> {
+    let templates = paths.templates();
+    if !templates.exists() {
+        return Ok(vec![]);
+    }
+    let mut names = vec![];
+    for entry in fs::read_dir(&templates).chain_err(|| "failed to list templates")? {
+        let entry = entry.chain_err(|| "failed to read directory entry")?;
+        if entry.path().join(TEMPLATE_CONFIG).is_file() {
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Build the configuration of the server `name` from the template in `dir`.
+/// `version` overrides the version of the template, and is required if it
+/// has none.
+pub fn config(dir: &Path, name: &str, version: Option<ServerVersion>) -> Result<ServerConfig> {
+    let path = dir.join(TEMPLATE_CONFIG);
+    let text = fs::read_to_string(&path)
+        .chain_err(|| format!("failed to read {}", path.to_string_lossy()))?;
+    let mut table: toml::value::Table = toml::from_str(&text)
+        .chain_err(|| ErrorKind::InvalidConfig(path.to_string_lossy().into_owned()))?;
+    table.insert("name".to_owned(), name.into());
+    if let Some(version) = version {
+        table.insert("version".to_owned(), version.to_string().into());
+    } else if !table.contains_key("version") {
+        bail!(
+            "template {} has no version, give one explicitly",
+            dir.file_name().unwrap_or_default().to_string_lossy()
+        );
+    }
+    let text = toml::to_string(&table).chain_err(|| "failed to generate configuration")?;
+    let mut config = ServerConfig::new(&text)?;
+    // Servers made from templates live in rustone's data directory
+    config.directory = None;
+    Ok(config)
+}
+
+/// Create the server `name` from `template`, in the instance in `paths`.
+pub fn create(
+    paths: &Paths,
+    name: &str,
+    template: &str,
+    version: Option<ServerVersion>,
+) -> Result<Server> {
+    let dir = paths.templates().join(template);
+    if !dir.join(TEMPLATE_CONFIG).is_file() {
+        bail!(ErrorKind::NotFound(format!(
+            "template {} (in {})",
+            template,
+            paths.templates().to_string_lossy()
+        )));
+    }
+    let mut config = config(&dir, name, version)?;
+    config.paths = paths.clone();
+    let server = Server::create_with(config)?;
+    let applied = (|| {
+        for entry in fs::read_dir(&dir).chain_err(|| "failed to list template")? {
+            let entry = entry.chain_err(|| "failed to read directory entry")?;
+            let dest = match entry.file_name().to_string_lossy().as_ref() {
+                TEMPLATE_CONFIG => continue,
+                "configs" => server.config.configs_path(),
+                _ => server.config.path().join(entry.file_name()),
+            };
+            copy_recursive(&entry.path(), &dest)?;
+        }
+        Ok(())
+    })();
+    if let Err(e) = applied {
+        let _ = server.remove();
+        return Err(e);
+    }
+    Ok(server)
+}
@@ -0,0 +1,360 @@
+//! Choosing the version a server runs: resolving version selectors like
+//! `latest` or `1.20.x`, as far as the server's update policy allows.
+//!
+//! Policies never move a server to another minor version, that is an
+//! upgrade (see [`crate::upgrade`]), which may need its worlds, plugins and
+//! mods to be checked first.
+//!
+//! Updated servers can be rolled back: until a new build has started
+//! successfully, the build before it is remembered and kept in the cache.
+//! Builds which fail to start are marked bad and not updated to again.
+
+use std::{
+    fmt::Display,
+    fs,
+    io::Write,
+    time::{Duration, Instant, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::Child,
+    sync::oneshot,
+};
+
+use crate::{
+    config::{
+        MinecraftSelector, MinecraftVersion, ServerConfig, ServerVersion, Stage, UpdatePolicy,
+        VersionSelector,
+    },
+    errors::*,
+    paths::Paths,
+    server_kinds::{Build, Change, ServerKind},
+};
+
+/// The newest version of `kind` which passes `filter`.
+async fn newest(
+    paths: &Paths,
+    kind: &ServerKind,
+    filter: impl Fn(&MinecraftVersion) -> bool,
+) -> Result<Option<MinecraftVersion>> {
+    Ok(kind
+        .get_versions(paths)
+        .await?
+        .into_iter()
+        .filter(|v| filter(v))
+        .max())
+}
+
+/// Resolve `selector` to a version of `kind`, for a new server.
+pub async fn resolve(
+    paths: &Paths,
+    kind: &ServerKind,
+    selector: &VersionSelector,
+) -> Result<ServerVersion> {
+    let minecraft = match selector.minecraft {
+        MinecraftSelector::Exact(version) => version,
+        _ => newest(paths, kind, |v| selector.allows(v))
+            .await?
+            .ok_or_else(|| {
+                ErrorKind::NotFound(format!("{} version matching {}", kind, selector))
+            })?,
+    };
+    let patch = match selector.build {
+        Some(build) => build,
+        None => kind.get_latest_patch(paths, &minecraft).await?,
+    };
+    Ok(ServerVersion {
+        minecraft,
+        patch: Some(patch),
+    })
+}
+
+/// The version the server of `config` should run now. `requested` is set
+/// when the user asked for updates, which servers with a manual policy wait
+/// for.
+pub async fn update(config: &ServerConfig, requested: bool) -> Result<ServerVersion> {
+    let kind = config.kind.parse::<ServerKind>()?;
+    let paths = &config.paths;
+    let current = config.version;
+    let policy = match config.update_policy {
+        UpdatePolicy::Manual if requested => UpdatePolicy::LatestBuild,
+        UpdatePolicy::Manual => UpdatePolicy::Pinned,
+        policy => policy,
+    };
+    if let (UpdatePolicy::Pinned, Some(_)) = (policy, current.patch) {
+        return Ok(current);
+    }
+
+    let allowed = |v: &MinecraftVersion| config.selector.is_none_or(|s| s.allows(v));
+    let minecraft = match policy {
+        UpdatePolicy::LatestPatch => {
+            let versions: Vec<_> = kind
+                .get_versions(paths)
+                .await?
+                .into_iter()
+                .filter(|v| allowed(v))
+                .collect();
+            // The policy never leaves the minor version, say when that is
+            // holding the server back
+            let newer_minor = versions
+                .iter()
+                .filter(|v| matches!(v, MinecraftVersion::Release(.., Stage::Release)))
+                .filter(|v| {
+                    current.minecraft.minor().is_some() && v.minor() > current.minecraft.minor()
+                })
+                .max();
+            if let Some(newer) = newer_minor {
+                warn!(
+                    server = %config.name,
+                    current = %current.minecraft,
+                    %newer,
+                    "a newer minor version is out, which the latest-patch policy does not upgrade to"
+                );
+            }
+            versions
+                .into_iter()
+                .filter(|v| v.minor() == current.minecraft.minor() && *v > current.minecraft)
+                .max()
+                .unwrap_or(current.minecraft)
+        }
+        _ => current.minecraft,
+    };
+    let pinned_build = config
+        .selector
+        .and_then(|s| s.exact())
+        .filter(|exact| exact.minecraft == minecraft)
+        .and_then(|exact| exact.patch);
+    // Pinned servers without a build yet get the newest one, once
+    let patch = match pinned_build {
+        Some(build) => build,
+        None => kind.get_latest_patch(paths, &minecraft).await?,
+    };
+    let version = ServerVersion {
+        minecraft,
+        patch: Some(patch),
+    };
+    // Servers stay where they are until a build newer than a bad one comes
+    if is_bad(config, &version) && current.patch.is_some() {
+        info!(server = %config.name, %version, "skipping bad build");
+        return Ok(current);
+    }
+    Ok(version)
+}
+
+/// Whether `a` and `b` are the same version and build. Comparing
+/// [`ServerVersion`]s with `==` ignores the build.
+pub fn same_build(a: &ServerVersion, b: &ServerVersion) -> bool {
+    a.minecraft == b.minecraft && a.patch == b.patch
+}
+
+/// The builds between `from` (excluding it) and `to`, oldest first, with
+/// what changed in them. Builds of another Minecraft version only count up
+/// to `to`.
+pub async fn changelog(
+    paths: &Paths,
+    kind: &ServerKind,
+    from: &ServerVersion,
+    to: &ServerVersion,
+) -> Result<Vec<Build>> {
+    let after = match (from.minecraft == to.minecraft, from.patch) {
+        (true, Some(patch)) => patch,
+        _ => 0,
+    };
+    let until = to.patch.unwrap_or(u32::MAX);
+    Ok(kind
+        .get_builds(paths, &to.minecraft)
+        .await?
+        .into_iter()
+        .filter(|b| b.number > after && b.number <= until)
+        .collect())
+}
+
+/// An update applied to a server, as kept in its update log.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UpdateRecord {
+    /// In seconds since the Unix epoch
+    pub time: u64,
+    pub from: ServerVersion,
+    pub to: ServerVersion,
+    /// The changes of the builds in between
+    #[serde(default)]
+    pub changes: Vec<Change>,
+}
+
+/// Add the update of the server of `config` from `from` to its current
+/// version to its log.
+pub fn log_update(config: &ServerConfig, from: ServerVersion, builds: &[Build]) -> Result<()> {
+    let record = UpdateRecord {
+        time: SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        from,
+        to: config.version,
+        changes: builds.iter().flat_map(|b| b.changes.clone()).collect(),
+    };
+    let path = config.paths.update_log(&config.name);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).chain_err(|| "failed to create update log directory")?;
+    }
+    let mut line = serde_json::to_string(&record).chain_err(|| "failed to serialize update")?;
+    line.push('\n');
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .chain_err(|| format!("failed to write {}", path.to_string_lossy()))
+}
+
+/// The updates applied to the server of `config`, oldest first.
+pub fn read_log(config: &ServerConfig) -> Result<Vec<UpdateRecord>> {
+    let path = config.paths.update_log(&config.name);
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let text = fs::read_to_string(&path)
+        .chain_err(|| format!("failed to read {}", path.to_string_lossy()))?;
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).chain_err(|| "failed to parse update log"))
+        .collect()
+}
+
+/// Whether `version` failed to start on the server of `config` before.
+pub fn is_bad(config: &ServerConfig, version: &ServerVersion) -> bool {
+    config.bad_builds.iter().any(|bad| same_build(bad, version))
+}
+
+/// Move the server of `config` to `version`, remembering the build to roll
+/// back to until it has started successfully.
+pub fn apply(config: &mut ServerConfig, version: ServerVersion) {
+    if same_build(&version, &config.version) {
+        return;
+    }
+    // An update which never started is not worth going back to
+    if config.rollback_to.is_none() && config.version.patch.is_some() {
+        config.rollback_to = Some(config.version);
+    }
+    info!(server = %config.name, from = %config.version, to = %version, "updating");
+    config.version = version;
+    if config
+        .rollback_to
+        .is_some_and(|rollback| same_build(&rollback, &version))
+    {
+        config.rollback_to = None;
+    }
+}
+
+/// Mark the current build of `config` bad and go back to the one before it.
+/// Returns the bad build, or `None` if there is nothing to roll back to.
+pub fn roll_back(config: &mut ServerConfig) -> Option<ServerVersion> {
+    let previous = config.rollback_to.take()?;
+    let bad = config.version;
+    if !is_bad(config, &bad) {
+        config.bad_builds.push(bad);
+    }
+    config.version = previous;
+    warn!(server = %config.name, %bad, to = %previous, "rolled back");
+    Some(bad)
+}
+
+/// How long servers may take to start by default, in seconds.
+pub const DEFAULT_STARTUP_TIMEOUT: u64 = 300;
+
+/// How a server's startup went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Startup {
+    /// It printed its "Done" line
+    Done,
+    /// It exited before that, with this exit code if it had one
+    Exited(Option<i32>),
+    /// It took too long, and was killed
+    TimedOut(Duration),
+}
+
+impl Display for Startup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Done => write!(f, "started"),
+            Self::Exited(Some(code)) => write!(f, "exited with code {} while starting", code),
+            Self::Exited(None) => write!(f, "was killed while starting"),
+            Self::TimedOut(timeout) => {
+                write!(f, "did not finish starting in {}s", timeout.as_secs())
+            }
+        }
+    }
+}
+
+/// Whether a line of server output says the server has started.
+///
+/// # Examples
+/// ```
+/// use rustone::updates::is_done_line;
+/// assert!(is_done_line(
+///     "[12:00:00 INFO]: Done (3.217s)! For help, type \"help\""
+/// ));
+/// assert!(is_done_line("[12:00:00] [Server thread/INFO]: Done (12.5s)! For help, type \"help\""));
+/// assert!(!is_done_line("[12:00:00 INFO]: <Steve> Done (finally)"));
+/// ```
+pub fn is_done_line(line: &str) -> bool {
+    match line.split_once("]: Done (") {
+        Some((_, rest)) => rest.contains(")! For help"),
+        None => false,
+    }
+}
+
+/// Watch a server start. Its standard output, which must be piped, is
+/// passed to `on_line` line by line, for as long as it runs. Servers
+/// still starting after `timeout` are killed.
+pub async fn watch_startup<F>(
+    child: &mut Child,
+    timeout: Duration,
+    mut on_line: F,
+) -> Result<Startup>
+where
+    F: FnMut(&str) + Send + 'static,
+{
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or("the output of the server is not piped")?;
+    let (done, started) = oneshot::channel();
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stdout).lines();
+        let mut done = Some(done);
+        while let Ok(Some(line)) = lines.next_line().await {
+            on_line(&line);
+            if is_done_line(&line) {
+                if let Some(done) = done.take() {
+                    let _ = done.send(());
+                }
+            }
+        }
+    });
+    let begun = Instant::now();
+    let startup = tokio::select! {
+        Ok(()) = started => Startup::Done,
+        status = child.wait() => {
+            let status = status
+                .chain_err(|| ErrorKind::ProcessFailed("waiting for the server".to_owned()))?;
+            Startup::Exited(status.code())
+        }
+        _ = tokio::time::sleep(timeout) => {
+            child
+                .kill()
+                .await
+                .chain_err(|| ErrorKind::ProcessFailed("stopping the server".to_owned()))?;
+            Startup::TimedOut(timeout)
+        }
+    };
+    match startup {
+        Startup::Done => info!(elapsed = ?begun.elapsed(), "server started"),
+        _ => warn!(elapsed = ?begun.elapsed(), %startup, "server failed to start"),
+    }
+    Ok(startup)
+}
@@ -0,0 +1,306 @@
+//! Safety checks for moving a server to another Minecraft version.
+//!
+//! Minecraft upgrades worlds when it loads them, and can not load worlds
+//! saved by a newer version. The `DataVersion` in each world's `level.dat`
+//! tells which version last saved it.
+
+use std::{fs, path::Path};
+
+use error_chain::bail;
+
+use crate::{
+    config::{
+        MinecraftSelector, MinecraftVersion, ServerConfig, ServerVersion, Stage, VersionSelector,
+    },
+    errors::*,
+    mods::{ModManifest, ModSource, ModSources},
+    plugins::{self, PluginManifest, PluginSource, Sources},
+};
+
+pub mod nbt;
+
+/// Data versions of release versions.
+/// See <https://minecraft.wiki/w/Data_version>.
+const DATA_VERSIONS: &[(u32, u32, u32, i32)] = &[
+    (1, 9, 0, 169),
+    (1, 9, 1, 175),
+    (1, 9, 2, 176),
+    (1, 9, 3, 183),
+    (1, 9, 4, 184),
+    (1, 10, 0, 510),
+    (1, 10, 1, 511),
+    (1, 10, 2, 512),
+    (1, 11, 0, 819),
+    (1, 11, 1, 921),
+    (1, 11, 2, 922),
+    (1, 12, 0, 1139),
+    (1, 12, 1, 1241),
+    (1, 12, 2, 1343),
+    (1, 13, 0, 1519),
+    (1, 13, 1, 1628),
+    (1, 13, 2, 1631),
+    (1, 14, 0, 1952),
+    (1, 14, 1, 1957),
+    (1, 14, 2, 1963),
+    (1, 14, 3, 1968),
+    (1, 14, 4, 1976),
+    (1, 15, 0, 2225),
+    (1, 15, 1, 2227),
+    (1, 15, 2, 2230),
+    (1, 16, 0, 2566),
+    (1, 16, 1, 2567),
+    (1, 16, 2, 2578),
+    (1, 16, 3, 2580),
+    (1, 16, 4, 2584),
+    (1, 16, 5, 2586),
+    (1, 17, 0, 2724),
+    (1, 17, 1, 2730),
+    (1, 18, 0, 2860),
+    (1, 18, 1, 2865),
+    (1, 18, 2, 2975),
+    (1, 19, 0, 3105),
+    (1, 19, 1, 3117),
+    (1, 19, 2, 3120),
+    (1, 19, 3, 3218),
+    (1, 19, 4, 3337),
+    (1, 20, 0, 3463),
+    (1, 20, 1, 3465),
+    (1, 20, 2, 3578),
+    (1, 20, 3, 3698),
+    (1, 20, 4, 3700),
+    (1, 20, 5, 3837),
+    (1, 20, 6, 3839),
+    (1, 21, 0, 3953),
+    (1, 21, 1, 3955),
+    (1, 21, 2, 4080),
+    (1, 21, 3, 4082),
+    (1, 21, 4, 4189),
+];
+
+/// The data version of a release version, if it is known. Pre-releases and
+/// snapshots have their own, which are not listed.
+///
+/// # Examples
+/// ```
+/// use rustone::upgrade::data_version;
+/// assert_eq!(data_version(&"1.20.1".parse().unwrap()), Some(3465));
+/// assert_eq!(data_version(&"1.20".parse().unwrap()), Some(3463));
+/// assert_eq!(data_version(&"1.8.8".parse().unwrap()), None);
+/// assert_eq!(data_version(&"1.20.2-rc1".parse().unwrap()), None);
+/// ```
+pub fn data_version(version: &MinecraftVersion) -> Option<i32> {
+    let (major, minor, patch) = match *version {
+        MinecraftVersion::Release(major, minor, patch, Stage::Release) => (major, minor, patch),
+        _ => return None,
+    };
+    DATA_VERSIONS
+        .iter()
+        .find(|(ma, mi, pa, _)| (*ma, *mi, *pa) == (major, minor, patch.unwrap_or(0)))
+        .map(|(.., data)| *data)
+}
+
+/// The version which last saved a world.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorldVersion {
+    pub world: String,
+    pub data_version: i32,
+    /// Like `1.20.1`, written since 1.9
+    pub name: Option<String>,
+}
+
+/// Read the version from a (gzip compressed) `level.dat`.
+pub fn read_level_dat(path: &Path) -> Result<(i32, Option<String>)> {
+    let file =
+        fs::File::open(path).chain_err(|| format!("failed to open {}", path.to_string_lossy()))?;
+    let root = nbt::read(flate2::read::GzDecoder::new(file))
+        .chain_err(|| format!("failed to read {}", path.to_string_lossy()))?;
+    let data = match root.get("Data") {
+        Some(data) => data,
+        None => bail!("{} has no Data tag", path.to_string_lossy()),
+    };
+    // Worlds from before 1.9 have no data version
+    let data_version = data
+        .get("DataVersion")
+        .and_then(|v| v.as_int())
+        .unwrap_or(0);
+    let name = data
+        .get("Version")
+        .and_then(|v| v.get("Name"))
+        .and_then(|n| n.as_str())
+        .map(str::to_owned);
+    Ok((data_version, name))
+}
+
+/// The versions of every world of a server.
+pub fn world_versions(config: &ServerConfig) -> Result<Vec<WorldVersion>> {
+    let worlds = config.worlds_path();
+    if !worlds.exists() {
+        return Ok(vec![]);
+    }
+    let mut versions = vec![];
+    for entry in fs::read_dir(&worlds).chain_err(|| "failed to list worlds")? {
+        let entry = entry.chain_err(|| "failed to read directory entry")?;
+        let level_dat = entry.path().join("level.dat");
+        if !level_dat.is_file() {
+            continue;
+        }
+        let (data_version, name) = read_level_dat(&level_dat)?;
+        versions.push(WorldVersion {
+            world: entry.file_name().to_string_lossy().into_owned(),
+            data_version,
+            name,
+        });
+    }
+    versions.sort_by(|a, b| a.world.cmp(&b.world));
+    Ok(versions)
+}
+
+/// Refuse to move a server to an older Minecraft version, checking both its
+/// configuration and its worlds. Worlds can't be checked against versions
+/// newer than the data version table, which is refused unless `force`d.
+pub fn check_downgrade(
+    config: &ServerConfig,
+    target: &MinecraftVersion,
+    force: bool,
+) -> Result<()> {
+    let current = config.version.minecraft;
+    if *target == current {
+        bail!("{} is already on Minecraft {}", config.name, current);
+    }
+    if *target < current {
+        bail!(
+            "refusing to downgrade {} from Minecraft {} to {}",
+            config.name,
+            current,
+            target
+        );
+    }
+    let worlds = world_versions(config)?;
+    let target_data = match data_version(target) {
+        Some(data) => data,
+        None if force || worlds.is_empty() => return Ok(()),
+        None => bail!(
+            "the data version of Minecraft {} is unknown, so the worlds of {} can't be checked against it (force the upgrade to skip this check)",
+            target,
+            config.name
+        ),
+    };
+    for world in worlds {
+        if world.data_version > target_data {
+            bail!(
+                "world {} was saved by Minecraft {} (data version {}), which is newer than {} ({})",
+                world.world,
+                world.name.as_deref().unwrap_or("unknown"),
+                world.data_version,
+                target,
+                target_data
+            );
+        }
+    }
+    Ok(())
+}
+
+/// A plugin or mod which won't work after an upgrade.
+#[derive(Debug, Clone)]
+pub struct Incompatibility {
+    pub name: String,
+    pub reason: String,
+}
+
+/// Check that every plugin of the server has a version supporting `target`.
+/// Plugins from URLs and paths can not be checked.
+pub async fn check_plugins(
+    config: &ServerConfig,
+    target: &MinecraftVersion,
+    sources: &Sources,
+) -> Result<Vec<Incompatibility>> {
+    let manifest = PluginManifest::load(config)?;
+    let mut incompatible = vec![];
+    for (name, entry) in &manifest.plugins {
+        let result = match &entry.source {
+            PluginSource::Modrinth { project } => plugins::resolve(
+                name,
+                &entry.version,
+                sources.modrinth.as_ref(),
+                project,
+                target,
+            )
+            .await
+            .map(|_| ()),
+            PluginSource::Hangar { project } => plugins::resolve(
+                name,
+                &entry.version,
+                sources.hangar.as_ref(),
+                project,
+                target,
+            )
+            .await
+            .map(|_| ()),
+            PluginSource::Url { .. } | PluginSource::Path { .. } => continue,
+        };
+        if let Err(e) = result {
+            incompatible.push(Incompatibility {
+                name: name.clone(),
+                reason: e.to_string(),
+            });
+        }
+    }
+    Ok(incompatible)
+}
+
+/// Check that every mod in the server's `mods.toml` has a version supporting
+/// `target`. Mods from URLs and paths can not be checked.
+pub async fn check_mods(
+    config: &ServerConfig,
+    target: &MinecraftVersion,
+) -> Result<Vec<Incompatibility>> {
+    let manifest = ModManifest::load(config)?;
+    if manifest.mods.is_empty() {
+        return Ok(vec![]);
+    }
+    let sources = ModSources::new(&config.paths, &manifest.loader(config)?);
+    let mut incompatible = vec![];
+    for (name, entry) in &manifest.mods {
+        let (repository, project) = match &entry.source {
+            ModSource::Modrinth { project } => (&sources.modrinth, project),
+            ModSource::Curseforge { project } => (&sources.curseforge, project),
+            ModSource::Url { .. } | ModSource::Path { .. } => continue,
+        };
+        let resolved =
+            plugins::resolve(name, &entry.version, repository.as_ref(), project, target).await;
+        if let Err(e) = resolved {
+            incompatible.push(Incompatibility {
+                name: name.clone(),
+                reason: e.to_string(),
+            });
+        }
+    }
+    Ok(incompatible)
+}
+
+/// Move the server of `config` to `target`. An upgrade is not rolled back,
+/// so the build to roll back to is forgotten, as are the bad builds of the
+/// old Minecraft version. A selector which does not allow `target` follows
+/// it to its minor version, or is dropped.
+pub fn apply(config: &mut ServerConfig, target: ServerVersion) {
+    let old = config.version.minecraft;
+    config.rollback_to = None;
+    config.bad_builds.retain(|bad| bad.minecraft != old);
+    config.selector = match config.selector {
+        Some(selector) if selector.allows(&target.minecraft) && selector.build.is_none() => {
+            Some(selector)
+        }
+        Some(VersionSelector {
+            minecraft: MinecraftSelector::Minor(..),
+            ..
+        }) => target
+            .minecraft
+            .minor()
+            .map(|(major, minor)| VersionSelector {
+                minecraft: MinecraftSelector::Minor(major, minor),
+                build: None,
+            }),
+        _ => None,
+    };
+    config.version = target;
+}
@@ -0,0 +1,144 @@
+//! A minimal reader for Minecraft's NBT format, enough to read `level.dat`.
+
+use std::{collections::HashMap, io::Read};
+
+use error_chain::bail;
+
+use crate::errors::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<u8>),
+    String(String),
+    List(Vec<Tag>),
+    Compound(HashMap<String, Tag>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl Tag {
+    pub fn get(&self, name: &str) -> Option<&Tag> {
+        match self {
+            Self::Compound(tags) => tags.get(name),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i32> {
+        match self {
+            Self::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+struct Reader<R> {
+    inner: R,
+}
+
+impl<R: Read> Reader<R> {
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut buf = [0; N];
+        self.inner
+            .read_exact(&mut buf)
+            .chain_err(|| "nbt: unexpected end of data")?;
+        Ok(buf)
+    }
+
+    fn length(&mut self) -> Result<usize> {
+        let length = i32::from_be_bytes(self.bytes()?);
+        if length < 0 {
+            bail!("nbt: negative length");
+        }
+        Ok(length as usize)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let length = u16::from_be_bytes(self.bytes()?) as usize;
+        let mut buf = vec![0; length];
+        self.inner
+            .read_exact(&mut buf)
+            .chain_err(|| "nbt: unexpected end of data")?;
+        // Java's modified UTF-8 only differs for characters names don't use
+        Ok(String::from_utf8_lossy(&buf).into_owned())
+    }
+
+    fn payload(&mut self, kind: u8) -> Result<Tag> {
+        Ok(match kind {
+            1 => Tag::Byte(i8::from_be_bytes(self.bytes()?)),
+            2 => Tag::Short(i16::from_be_bytes(self.bytes()?)),
+            3 => Tag::Int(i32::from_be_bytes(self.bytes()?)),
+            4 => Tag::Long(i64::from_be_bytes(self.bytes()?)),
+            5 => Tag::Float(f32::from_be_bytes(self.bytes()?)),
+            6 => Tag::Double(f64::from_be_bytes(self.bytes()?)),
+            7 => {
+                let length = self.length()?;
+                let mut buf = vec![0; length];
+                self.inner
+                    .read_exact(&mut buf)
+                    .chain_err(|| "nbt: unexpected end of data")?;
+                Tag::ByteArray(buf)
+            }
+            8 => Tag::String(self.string()?),
+            9 => {
+                let [item_kind] = self.bytes()?;
+                let length = self.length()?;
+                let items = (0..length)
+                    .map(|_| self.payload(item_kind))
+                    .collect::<Result<_>>()?;
+                Tag::List(items)
+            }
+            10 => {
+                let mut tags = HashMap::new();
+                loop {
+                    let [kind] = self.bytes()?;
+                    if kind == 0 {
+                        break;
+                    }
+                    let name = self.string()?;
+                    tags.insert(name, self.payload(kind)?);
+                }
+                Tag::Compound(tags)
+            }
+            11 => {
+                let length = self.length()?;
+                let items = (0..length)
+                    .map(|_| Ok(i32::from_be_bytes(self.bytes()?)))
+                    .collect::<Result<_>>()?;
+                Tag::IntArray(items)
+            }
+            12 => {
+                let length = self.length()?;
+                let items = (0..length)
+                    .map(|_| Ok(i64::from_be_bytes(self.bytes()?)))
+                    .collect::<Result<_>>()?;
+                Tag::LongArray(items)
+            }
+            _ => bail!("nbt: unknown tag type {}", kind),
+        })
+    }
+}
+
+/// Read an uncompressed NBT document and return its root tag.
+pub fn read(data: impl Read) -> Result<Tag> {
+    let mut reader = Reader { inner: data };
+    let [kind] = reader.bytes()?;
+    if kind != 10 {
+        bail!("nbt: root tag is not a compound");
+    }
+    reader.string()?;
+    reader.payload(kind)
+}